@@ -0,0 +1,106 @@
+use std::hash::{Hash, Hasher};
+
+use camino::Utf8Path;
+
+use crate::Args;
+
+/// Runs `convert`, unless the same input was already converted with the same arguments, in which
+/// case the earlier result is returned instead.
+///
+/// Results are stored in `--cache-dir`, keyed by a hash of the input's contents and the other
+/// arguments. Without `--cache-dir`, this just runs `convert`.
+pub fn cached(
+	args: &Args,
+	input: &Utf8Path,
+	convert: impl FnOnce() -> eyre::Result<Vec<u8>>,
+) -> eyre::Result<Vec<u8>> {
+	// Split planes and mip files are read from several files, which the key does not cover
+	let several_files = args.split_planes || args.dds_mip_files;
+	let Some(dir) = args.cache_dir.as_ref().filter(|_| !several_files) else {
+		return convert();
+	};
+	let path = dir.join(format!("{:016x}", key(args, input)?));
+	match std::fs::read(&path) {
+		Ok(data) => {
+			tracing::info!("using cached {path}");
+			return Ok(data);
+		}
+		Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+		Err(e) => return Err(e.into()),
+	}
+
+	let data = convert()?;
+	std::fs::create_dir_all(dir)?;
+	// Written under a temporary name first, so that an interrupted run cannot leave a truncated
+	// entry behind
+	let tmp = path.with_extension(format!("tmp{}", std::process::id()));
+	std::fs::write(&tmp, &data)?;
+	std::fs::rename(tmp, path)?;
+	Ok(data)
+}
+
+fn key(args: &Args, input: &Utf8Path) -> eyre::Result<u64> {
+	// DefaultHasher may change between Rust versions, but that only causes cache misses
+	let mut h = std::collections::hash_map::DefaultHasher::new();
+	env!("CARGO_PKG_VERSION").hash(&mut h);
+	let args = Args {
+		cache_dir: None,
+		..args.clone()
+	};
+	format!("{args:?}").hash(&mut h);
+	input.extension().hash(&mut h);
+	std::fs::read(input)?.hash(&mut h);
+	if let Some(path) = &args.palette_from {
+		std::fs::read(path)?.hash(&mut h);
+	}
+	Ok(h.finish())
+}
+
+#[test]
+fn test_cache() -> eyre::Result<()> {
+	use cradle::raster::Raster;
+	let tmpdir = camino_tempfile::tempdir()?;
+	let input = tmpdir.path().join("a.png");
+	let write_png = |color| -> eyre::Result<()> {
+		let img = crate::png::Png::Argb32(vec![Raster::splat(4, 4, color)]);
+		crate::png::write(std::fs::File::create(&input)?, &img)
+	};
+	write_png(0xFFFF0000)?;
+	let args = &Args {
+		cache_dir: Some(tmpdir.path().join("cache")),
+		..Args::default()
+	};
+
+	let first = cached(args, &input, || crate::to_itp(args, &input))?;
+	let second = cached(args, &input, || panic!("should be cached"))?;
+	assert!(first == second);
+	assert_eq!(tmpdir.path().join("cache").read_dir_utf8()?.count(), 1);
+
+	// Changing the input or the arguments converts it again
+	write_png(0xFF00FF00)?;
+	let third = cached(args, &input, || crate::to_itp(args, &input))?;
+	assert!(third != first);
+	let args = &Args {
+		use_alpha: Some(true),
+		..args.clone()
+	};
+	let mut converted = false;
+	cached(args, &input, || {
+		converted = true;
+		crate::to_itp(args, &input)
+	})?;
+	assert!(converted);
+
+	// Nor is anything cached when reading several files
+	let args = &Args {
+		dds_mip_files: true,
+		..args.clone()
+	};
+	let entries = tmpdir.path().join("cache").read_dir_utf8()?.count();
+	cached(args, &input, || crate::to_itp(args, &input))?;
+	assert_eq!(
+		tmpdir.path().join("cache").read_dir_utf8()?.count(),
+		entries
+	);
+	Ok(())
+}
@@ -0,0 +1,115 @@
+use std::fmt::Write as _;
+
+use cradle::{
+	itp::{abbr::*, ImageData, Itp, Palette},
+	raster::Raster,
+};
+use num_enum::TryFromPrimitive;
+
+/// File extensions that are converted, and what they are converted to.
+const FILES: &[(&str, &str)] = &[
+	("itp", "png, dds, ktx2, or itp of another revision"),
+	("itc", "png, dds, or itp frames, plus a json or csv listing"),
+	("png", "itp"),
+	("dds", "itp, or itc for texture arrays"),
+	("gif", "itc"),
+	("json", "itc"),
+	("csv", "itc"),
+];
+
+/// Describes what can be read and written.
+///
+/// The itp parts are found by trying to write a small image of each kind, so they always match
+/// what the library supports.
+pub fn list_formats() -> eyre::Result<String> {
+	let mut out = String::new();
+	writeln!(out, "files:")?;
+	for (ext, to) in FILES {
+		writeln!(out, "  {ext} -> {to}")?;
+	}
+
+	writeln!(out, "itp revisions: {}", join(variants::<IR>()))?;
+	writeln!(out, "itp image formats:")?;
+	for data in samples() {
+		let revisions = variants::<IR>()
+			.into_iter()
+			.filter(|&rev| cradle::itp::write(&Itp::new(rev, data.clone())).is_ok())
+			.collect::<Vec<_>>();
+		let revisions = if revisions.is_empty() {
+			"read only".to_owned()
+		} else {
+			join(revisions)
+		};
+		writeln!(out, "  {}: {revisions}", data.format_name())?;
+	}
+	writeln!(out, "itp base formats: {}", join(variants::<BFT>()))?;
+	writeln!(out, "itp pixel formats: {}", join(variants::<PFT>()))?;
+
+	let compressions = variants::<CT>()
+		.into_iter()
+		.map(|ct| {
+			if round_trips(ct) {
+				ct.to_string()
+			} else {
+				format!("{ct} (read only)")
+			}
+		})
+		.collect::<Vec<_>>();
+	writeln!(out, "itp compression: {}", compressions.join(", "))?;
+	Ok(out)
+}
+
+/// One small image of each kind of [`ImageData`].
+fn samples() -> Vec<ImageData> {
+	let pal = Palette::Embedded(vec![0; 256]);
+	vec![
+		ImageData::Indexed(pal, vec![Raster::new(16, 16)]),
+		ImageData::Argb16(A16::Mode1, vec![Raster::new(16, 16)]),
+		ImageData::Argb16(A16::Mode2, vec![Raster::new(16, 16)]),
+		ImageData::Argb16(A16::Mode3, vec![Raster::new(16, 16)]),
+		ImageData::Argb32(vec![Raster::new(16, 16)]),
+		ImageData::Bc1(vec![Raster::new(4, 4)]),
+		ImageData::Bc2(vec![Raster::new(4, 4)]),
+		ImageData::Bc3(vec![Raster::new(4, 4)]),
+		ImageData::Bc7(vec![Raster::new(4, 4)]),
+	]
+}
+
+fn round_trips(compression: CT) -> bool {
+	let mut itp = Itp::new(IR::V3, ImageData::Argb32(vec![Raster::new(16, 16)]));
+	itp.status.compression = compression;
+	cradle::itp::write(&itp)
+		.ok()
+		.and_then(|data| cradle::itp::read(&data).ok())
+		.is_some_and(|itp2| itp2 == itp)
+}
+
+/// All values of a header enum, in order.
+fn variants<T: TryFromPrimitive<Primitive = u16>>() -> Vec<T> {
+	(0..=u8::MAX as u16)
+		.filter_map(|v| T::try_from_primitive(v).ok())
+		.collect()
+}
+
+fn join<T: std::fmt::Display>(values: Vec<T>) -> String {
+	values
+		.iter()
+		.map(T::to_string)
+		.collect::<Vec<_>>()
+		.join(", ")
+}
+
+#[test]
+fn test_list_formats() -> eyre::Result<()> {
+	let text = list_formats()?;
+	for family in ["Indexed", "ARGB16", "ARGB32", "BC1", "BC2", "BC3", "BC7"] {
+		assert!(text.contains(&format!("\n  {family}")), "{family} missing");
+	}
+	for data in samples() {
+		assert!(text.contains(&data.format_name()));
+	}
+	assert!(text.contains("  ARGB32: V2, V3\n"));
+	assert!(text.contains("itp revisions: V1, V2, V3\n"));
+	assert!(text.contains("Bz_1"));
+	Ok(())
+}
@@ -0,0 +1,76 @@
+use std::io::Read;
+
+use cradle::raster::Raster;
+
+/// Reads every frame of a gif, as they would be displayed.
+///
+/// Gif frames only cover part of the screen, and transparent pixels show whatever was there
+/// before, so each frame is drawn onto a full-size canvas and the canvas is snapshotted. The
+/// frame's disposal method then decides what the next frame is drawn onto.
+pub fn read(f: impl Read) -> eyre::Result<Vec<Raster<u32>>> {
+	let mut options = gif::DecodeOptions::new();
+	options.set_color_output(gif::ColorOutput::RGBA);
+	let mut gif = options.read_info(f)?;
+	let (width, height) = (gif.width() as usize, gif.height() as usize);
+
+	let mut canvas = Raster::<u32>::new(width, height);
+	let mut frames = Vec::new();
+	while let Some(frame) = gif.read_next_frame()? {
+		let previous = canvas.clone();
+		let (left, top) = (frame.left as usize, frame.top as usize);
+		let (w, h) = (frame.width as usize, frame.height as usize);
+		eyre::ensure!(
+			left + w <= width && top + h <= height,
+			"frame is outside the image"
+		);
+		for (i, &[r, g, b, a]) in frame.buffer.array_chunks().enumerate() {
+			if a != 0 {
+				canvas[[left + i % w, top + i / w]] = u32::from_le_bytes([b, g, r, a]);
+			}
+		}
+		frames.push(canvas.clone());
+
+		match frame.dispose {
+			gif::DisposalMethod::Any | gif::DisposalMethod::Keep => {}
+			gif::DisposalMethod::Background => canvas.fill_rect(left, top, w, h, 0),
+			gif::DisposalMethod::Previous => canvas = previous,
+		}
+	}
+	eyre::ensure!(!frames.is_empty(), "gif has no frames");
+	Ok(frames)
+}
+
+#[test]
+fn test_read() -> eyre::Result<()> {
+	use std::borrow::Cow;
+	let palette = [0, 0, 0, 255, 0, 0, 0, 255, 0];
+	let mut data = Vec::new();
+	let mut enc = gif::Encoder::new(&mut data, 4, 2, &palette)?;
+	let mut frame = |left, width, pixels: &[u8], dispose| -> eyre::Result<()> {
+		enc.write_frame(&gif::Frame {
+			left,
+			width,
+			height: 2,
+			buffer: Cow::Borrowed(pixels),
+			transparent: Some(0),
+			dispose,
+			..gif::Frame::default()
+		})?;
+		Ok(())
+	};
+	use gif::DisposalMethod as D;
+	frame(0, 4, &[1, 1, 0, 0, 1, 1, 0, 0], D::Keep)?;
+	frame(2, 2, &[2, 2, 0, 2], D::Background)?;
+	frame(1, 2, &[0, 2, 0, 0], D::Keep)?;
+	drop(enc);
+
+	let frames = read(data.as_slice())?;
+	let (r, g) = (0xFFFF0000, 0xFF00FF00);
+	assert_eq!(frames.len(), 3);
+	assert_eq!(frames[0].as_slice(), [r, r, 0, 0, r, r, 0, 0]);
+	// Transparent pixels keep what was drawn before
+	assert_eq!(frames[1].as_slice(), [r, r, g, g, r, r, 0, g]);
+	// The second frame's area was cleared afterwards
+	assert_eq!(frames[2].as_slice(), [r, r, g, 0, r, r, 0, 0]);
+	Ok(())
+}
@@ -0,0 +1,134 @@
+use std::fmt::Write as _;
+
+use camino::Utf8Path;
+use cradle::itp::{ImageData, Palette};
+use cradle_dds as dds;
+
+/// Describes the file in a few `key: value` lines, based on its extension.
+pub fn info(path: &Utf8Path, data: &[u8]) -> eyre::Result<String> {
+	let mut out = String::new();
+	match path.extension() {
+		Some("itp") => itp_info(&mut out, &crate::util::decompress(data.to_vec())?)?,
+		Some("itc") => itc_info(&mut out, data)?,
+		Some("dds") => dds_info(&mut out, data)?,
+		Some("png") => png_info(&mut out, data)?,
+		_ => eyre::bail!("unsupported file type"),
+	}
+	Ok(out)
+}
+
+fn itp_info(out: &mut String, data: &[u8]) -> eyre::Result<()> {
+	let itp = cradle::itp::read(data)?;
+	writeln!(out, "type: itp {}", itp.status.itp_revision)?;
+	writeln!(out, "format: {}", itp.data.format_name())?;
+	writeln!(out, "size: {}x{}", itp.data.width(), itp.data.height())?;
+	writeln!(out, "mipmaps: {}", itp.data.mipmaps())?;
+	writeln!(out, "compression: {}", itp.status.compression)?;
+	if let ImageData::Bc1(_) = &itp.data {
+		let alpha = itp.data.bc1_has_alpha();
+		writeln!(out, "alpha: {}", if alpha { "1-bit" } else { "none" })?;
+	}
+	if let ImageData::Indexed(Palette::External(name), _) = &itp.data {
+		writeln!(out, "palette: {}", name.to_string_lossy())?;
+	}
+	Ok(())
+}
+
+fn itc_info(out: &mut String, data: &[u8]) -> eyre::Result<()> {
+	let itc = cradle::itc::read(data)?;
+	let frames = itc.frames().count();
+	writeln!(out, "type: itc")?;
+	writeln!(out, "frames: {frames}")?;
+	match &itc.palette {
+		Some(pal) => writeln!(out, "palette: {} colors", pal.len())?,
+		None => writeln!(out, "palette: none")?,
+	}
+	for (i, _, itp) in itc.frames() {
+		let itp = cradle::itp::read(itp)?;
+		writeln!(
+			out,
+			"frame {i}: {} {}x{}",
+			itp.data.format_name(),
+			itp.data.width(),
+			itp.data.height()
+		)?;
+	}
+	Ok(())
+}
+
+fn dds_info(out: &mut String, mut data: &[u8]) -> eyre::Result<()> {
+	let header = dds::Dds::read(&mut data)?;
+	let pf = &header.pixel_format;
+	writeln!(out, "type: dds")?;
+	if let Some(dx10) = &header.dx10 {
+		writeln!(out, "format: DXGI {}", dx10.dxgi_format)?;
+	} else if pf.flags & dds::DDPF::FOURCC != 0 {
+		writeln!(out, "format: {}", String::from_utf8_lossy(&pf.four_cc))?;
+	} else {
+		writeln!(
+			out,
+			"format: {} bpp, masks {:08X} {:08X} {:08X} {:08X}",
+			pf.bpp, pf.rmask, pf.gmask, pf.bmask, pf.amask
+		)?;
+	}
+	writeln!(out, "size: {}x{}", header.width, header.height)?;
+	writeln!(out, "mipmaps: {}", header.mip_map_count.max(1))?;
+	if let Some(dx10) = header.dx10.as_ref().filter(|dx10| dx10.array_size > 1) {
+		writeln!(out, "array size: {}", dx10.array_size)?;
+	}
+	Ok(())
+}
+
+fn png_info(out: &mut String, data: &[u8]) -> eyre::Result<()> {
+	let png = ::png::Decoder::new(data).read_info()?;
+	let info = png.info();
+	writeln!(out, "type: png")?;
+	writeln!(out, "format: {:?} {:?}", info.color_type, info.bit_depth)?;
+	writeln!(out, "size: {}x{}", info.width, info.height)?;
+	if let Some(actl) = &info.animation_control {
+		writeln!(out, "frames: {}", actl.num_frames)?;
+	}
+	Ok(())
+}
+
+#[test]
+fn test_info() -> eyre::Result<()> {
+	let itp = include_bytes!("../../samples/itp/ao__extra020.itp");
+	let expected = cradle::itp::read(itp)?;
+	let text = info(Utf8Path::new("a.itp"), itp)?;
+	assert!(text.contains(&format!("format: {}\n", expected.data.format_name())));
+	assert!(text.contains(&format!(
+		"size: {}x{}\n",
+		expected.data.width(),
+		expected.data.height()
+	)));
+
+	let itc = include_bytes!("../../samples/itc/ao__ch00051.itc");
+	let expected = cradle::itc::read(itc)?;
+	let text = info(Utf8Path::new("a.itc"), itc)?;
+	let frames = expected.frames().count();
+	assert!(text.contains(&format!("frames: {frames}\n")));
+
+	let mut header = dds::Dds {
+		width: 8,
+		height: 4,
+		..dds::Dds::default()
+	};
+	header.pixel_format.flags |= dds::DDPF::FOURCC;
+	header.pixel_format.four_cc = *b"DXT1";
+	let mut data = Vec::new();
+	header.write(&mut data)?;
+	let text = info(Utf8Path::new("a.dds"), &data)?;
+	assert!(text.contains("format: DXT1\n"));
+	assert!(text.contains("size: 8x4\n"));
+
+	let mut data = Vec::new();
+	let img = crate::png::Png::Argb32(vec![cradle::raster::Raster::new(3, 5)]);
+	crate::png::write(&mut data, &img)?;
+	let text = info(Utf8Path::new("a.png"), &data)?;
+	assert!(text.contains("format: Rgba Eight\n"));
+	assert!(text.contains("size: 3x5\n"));
+
+	assert!(info(Utf8Path::new("a.txt"), b"").is_err());
+	Ok(())
+}
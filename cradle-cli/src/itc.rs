@@ -1,6 +1,8 @@
+use std::ffi::CString;
+
 use camino::{Utf8Path, Utf8PathBuf};
 use cradle::{
-	itp::{ImageData, Palette},
+	itp::{BaseFormatType, ImageData, Itp, ItpRevision, Palette, PixelFormatType},
 	raster::Raster,
 };
 use strict_result::Strict;
@@ -15,6 +17,15 @@ struct FrameSpec {
 	offset: Option<(f32, f32)>,
 	#[serde(default = "unit_scale", skip_serializing_if = "is_unit_scale")]
 	scale: (f32, f32),
+	/// Whether the image was stretched by `scale` when extracted, with `--itc-apply-scale`.
+	#[serde(default, skip_serializing_if = "std::ops::Not::not")]
+	scale_applied: bool,
+	/// Name of the external palette the frame refers to, when converted to png or dds.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	external_palette: Option<String>,
+	/// The frame's unknown field, which is almost always zero.
+	#[serde(default, skip_serializing_if = "is_zero")]
+	unknown: u16,
 }
 
 fn unit_scale() -> (f32, f32) {
@@ -23,11 +34,33 @@ fn unit_scale() -> (f32, f32) {
 fn is_unit_scale(a: &(f32, f32)) -> bool {
 	*a == unit_scale()
 }
+fn is_zero(a: &u16) -> bool {
+	*a == 0
+}
+
+/// The size a frame is shown at, which with `--itc-apply-scale` includes its scale.
+///
+/// Only positive scales are applied; anything else is left for the game to interpret.
+fn scaled_size(args: &Args, (xs, ys): (f32, f32), w: usize, h: usize) -> (usize, usize) {
+	if args.itc_apply_scale && xs > 0.0 && ys > 0.0 {
+		let w = (w as f32 * xs).round().max(1.0) as usize;
+		let h = (h as f32 * ys).round().max(1.0) as usize;
+		(w, h)
+	} else {
+		(w, h)
+	}
+}
+
+/// Playback speed used by `--animate` when the spec does not specify one.
+pub const DEFAULT_FPS: f32 = 15.0;
 
 #[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
 pub struct ItcSpec {
 	#[serde(default, skip_serializing_if = "Option::is_none")]
 	palette: Option<Vec<u32>>,
+	/// Frames per second for the `--animate` preview. Not stored in the itc itself.
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	pub fps: Option<f32>,
 	frames: Vec<FrameSpec>,
 }
 
@@ -44,10 +77,10 @@ pub fn extract(args: &Args, itc: &cradle::itc::Itc, output: Output) -> eyre::Res
 	let mut maxh = 0;
 	if !args.itp && !args.dds && !args.itc_no_pad {
 		let _span = tracing::info_span!("calcsize").entered();
-		for (i, frame) in itc.frames.iter().enumerate() {
-			let Some(itp) = &frame.itp else { continue };
+		for (i, frame, itp) in itc.frames() {
 			let _span = tracing::info_span!("frame", i = i).entered();
 			let (w, h) = cradle::itp::read_size(itp)?;
+			let (w, h) = scaled_size(args, frame.scale, w, h);
 			let xo = frame.offset.0 * w as f32;
 			let yo = frame.offset.1 * h as f32;
 			let w = w + xo.abs().round() as usize * 2;
@@ -57,13 +90,14 @@ pub fn extract(args: &Args, itc: &cradle::itc::Itc, output: Output) -> eyre::Res
 		}
 	}
 
-	let mut frames = Vec::new();
-	for (i, frame) in itc.frames.iter().enumerate() {
-		let Some(itp) = &frame.itp else { continue };
-
-		let _span = tracing::info_span!("frame", i = i).entered();
+	let parent = tracing::Span::current();
+	let frames = itc.frames().collect::<Vec<_>>();
+	let mut frames = crate::util::par_map(args.jobs, &frames, |&(i, frame, itp)| {
+		let _span = tracing::info_span!(parent: &parent, "frame", i = i).entered();
 
 		let (w, h) = cradle::itp::read_size(itp)?;
+		let (sw, sh) = scaled_size(args, frame.scale, w, h);
+		let mut scale_applied = false;
 
 		let frame_out = if args.no_dir {
 			output.with_extension(&format!("{i}.itp"))
@@ -71,39 +105,36 @@ pub fn extract(args: &Args, itc: &cradle::itc::Itc, output: Output) -> eyre::Res
 			output.with_extension("").join(&format!("{i}.itp"))
 		};
 
-		// Not sure if this is the right formula? Previous Cradle use different
+		// Offsets are relative to the size the frame is shown at
 		let xs = frame.scale.0;
 		let ys = frame.scale.1;
-		let xo = frame.offset.0 * w as f32;
-		let yo = frame.offset.1 * h as f32;
+		let xo = frame.offset.0 * sw as f32;
+		let yo = frame.offset.1 * sh as f32;
 		let mut offset = Some((xo, yo));
+		let mut external_palette = None;
 
 		let frame_out = if args.itp {
-			std::fs::write(&frame_out, itp)?;
+			crate::util::write_file(args, &frame_out, itp)?;
 			frame_out
 		} else {
 			let mut itp = tracing::info_span!("parse_itp")
 				.in_scope(|| Ok(cradle::itp::read(itp)?))
 				.strict()?;
 
-			if let ImageData::Indexed(pal @ Palette::External(..), _) = &mut itp.data {
-				if let Some(palette) = &itc.palette {
-					tracing::warn!("inlining palette");
-					*pal = Palette::Embedded(palette.clone())
-				} else {
-					eyre::bail!("no palette")
-				}
-			}
+			external_palette = inline_palette(&mut itp, itc)?;
 
 			if args.dds {
 				let output = frame_out.with_extension("dds");
-				let f = std::fs::File::create(&output)?;
-				crate::itp_dds::itp_to_dds(args, f, &itp)?;
+				crate::itp_dds::itp_to_dds_file(args, &output, &itp)?;
 				output
 			} else {
 				let output = frame_out.with_extension("png");
-				let f = std::fs::File::create(&output)?;
+				let f = crate::util::create_file(args, &output)?;
 				let mut png = crate::itp_png::itp_to_png(args, &itp)?;
+				if (sw, sh) != (w, h) {
+					resize(&mut png, sw, sh)?;
+					scale_applied = true;
+				}
 				if !args.itc_no_pad {
 					let _span = tracing::info_span!("pad").entered();
 					if (xo - xo.round()).abs() < 0.0001 && (yo - yo.round()).abs() < 0.0001 {
@@ -117,28 +148,48 @@ pub fn extract(args: &Args, itc: &cradle::itc::Itc, output: Output) -> eyre::Res
 						offset = None;
 					}
 				}
-				png::write(f, &png)?;
+				png::write_with_icc(f, &png, crate::util::icc_profile(args)?.as_deref())?;
 				output
 			}
 		};
 
-		frames.push((
+		Ok((
 			frame.order,
 			FrameSpec {
 				frame: i,
 				path: frame_out.strip_prefix(&outdir).unwrap().to_path_buf(),
 				offset,
 				scale: (xs, ys),
+				scale_applied,
+				external_palette,
+				unknown: frame.unknown,
 			},
-		));
-	}
+		))
+	})?;
 
 	frames.sort_by_key(|a| a.0);
+	// An itc only holds one palette, so frames referring to different names presumably still share
+	// it. The names are kept per frame, so nothing is lost, but it is worth pointing out.
+	let mut names = frames
+		.iter()
+		.filter_map(|a| a.1.external_palette.as_deref())
+		.collect::<Vec<_>>();
+	names.sort();
+	names.dedup();
+	if names.len() > 1 {
+		tracing::warn!(
+			"frames refer to different external palettes ({}), but the itc only has one",
+			names.join(", ")
+		);
+	}
 	crate::Spec::write(
+		args,
 		&json_out,
 		crate::util::MyFormatter::new(2),
 		ItcSpec {
-			palette: itc.palette.as_ref().filter(|_| args.itp).cloned(),
+			// Kept even if no frame uses it, so that the itc round-trips
+			palette: itc.palette.clone(),
+			fps: None,
 			frames: frames.into_iter().map(|a| a.1).collect(),
 		},
 	)?;
@@ -150,6 +201,102 @@ pub fn extract(args: &Args, itc: &cradle::itc::Itc, output: Output) -> eyre::Res
 	}
 }
 
+const FLAT_HEADER: &str = "frame,path,offset_x,offset_y,scale_x,scale_y,unknown";
+
+/// Writes the frames as loose itp files, plus a csv listing their offsets and scales in playback
+/// order, and the palette as raw little-endian colors, if any.
+///
+/// Unlike [`extract`], the offsets and scales are written exactly as stored in the itc.
+pub fn extract_flat(
+	args: &Args,
+	itc: &cradle::itc::Itc,
+	output: Output,
+) -> eyre::Result<Utf8PathBuf> {
+	let csv_out = output.with_extension("itc.csv");
+	let base = csv_out.with_extension("");
+
+	let mut frames = Vec::new();
+	for (i, frame, itp) in itc.frames() {
+		let frame_out = base.with_extension(&format!("{i}.itp"));
+		crate::util::write_file(args, &frame_out, itp)?;
+		let name = frame_out.file_name().unwrap();
+		eyre::ensure!(!name.contains(','), "file name cannot contain commas");
+		let (x, y) = frame.offset;
+		let (sx, sy) = frame.scale;
+		let unknown = frame.unknown;
+		frames.push((
+			frame.order,
+			format!("{i},{name},{x},{y},{sx},{sy},{unknown}"),
+		));
+	}
+	frames.sort_by_key(|a| a.0);
+
+	let mut csv = String::from(FLAT_HEADER);
+	for (_, line) in frames {
+		csv.push('\n');
+		csv.push_str(&line);
+	}
+	csv.push('\n');
+	crate::util::write_file(args, &csv_out, csv)?;
+
+	if let Some(palette) = &itc.palette {
+		let pal_out = csv_out.with_extension("pal");
+		crate::util::write_file(
+			args,
+			pal_out,
+			palette
+				.iter()
+				.flat_map(|a| a.to_le_bytes())
+				.collect::<Vec<_>>(),
+		)?;
+	}
+
+	Ok(csv_out)
+}
+
+/// Reassembles an itc written by [`extract_flat`].
+pub fn create_flat(csv_path: &Utf8Path) -> eyre::Result<cradle::itc::Itc> {
+	let dir = csv_path.parent().unwrap();
+	let mut itc = cradle::itc::Itc::default();
+
+	let pal_path = csv_path.with_extension("pal");
+	if pal_path.exists() {
+		let data = std::fs::read(pal_path)?;
+		eyre::ensure!(data.len() % 4 == 0, "palette size must be a multiple of 4");
+		itc.palette = Some(
+			data.array_chunks()
+				.copied()
+				.map(u32::from_le_bytes)
+				.collect(),
+		);
+	}
+
+	let csv = std::fs::read_to_string(csv_path)?;
+	let mut lines = csv.lines();
+	eyre::ensure!(lines.next() == Some(FLAT_HEADER), "invalid csv header");
+	for (order, line) in lines.filter(|a| !a.is_empty()).enumerate() {
+		let [i, path, x, y, sx, sy, unknown] = line.split(',').collect::<Vec<_>>()[..] else {
+			eyre::bail!("invalid csv line: {line}")
+		};
+		let i = i.parse::<usize>()?;
+		let _span = tracing::info_span!("frame", i = i).entered();
+		let Some(frame) = itc.frames.get_mut(i) else {
+			eyre::bail!("invalid frame number");
+		};
+		if frame.itp.is_some() {
+			eyre::bail!("duplicate frame number");
+		}
+		*frame = cradle::itc::Frame {
+			itp: Some(std::fs::read(dir.join(path))?),
+			unknown: unknown.parse()?,
+			offset: (x.parse()?, y.parse()?),
+			scale: (sx.parse()?, sy.parse()?),
+			order,
+		};
+	}
+	Ok(itc)
+}
+
 pub fn create(args: &Args, spec: ItcSpec, dir: &Utf8Path) -> eyre::Result<cradle::itc::Itc> {
 	let mut itc = cradle::itc::Itc {
 		palette: spec.palette,
@@ -157,46 +304,219 @@ pub fn create(args: &Args, spec: ItcSpec, dir: &Utf8Path) -> eyre::Result<cradle
 	};
 	let mut frames = spec.frames.iter().enumerate().collect::<Vec<_>>();
 	frames.sort_by_key(|a| a.1.frame);
-	for (order, spec) in frames {
+	for (_, spec) in &frames {
 		let _span = tracing::info_span!("frame", i = spec.frame).entered();
-		let Some(frame) = itc.frames.get_mut(spec.frame) else {
-			eyre::bail!("invalid frame number");
+		eyre::ensure!(spec.frame < itc.frames.len(), "invalid frame number");
+	}
+	if let Some(pair) = frames.windows(2).find(|a| a[0].1.frame == a[1].1.frame) {
+		let _span = tracing::info_span!("frame", i = pair[1].1.frame).entered();
+		eyre::bail!("duplicate frame number");
+	}
+
+	// Frames are encoded in parallel, but assembled in order, so the result does not depend on
+	// the number of jobs
+	let parent = tracing::Span::current();
+	let palette = itc.palette.as_deref();
+	let encoded = crate::util::par_map(args.jobs, &frames, |&(_, spec)| {
+		let _span = tracing::info_span!(parent: &parent, "frame", i = spec.frame).entered();
+		encode_frame(args, spec, dir, palette)
+	})?;
+
+	for ((order, spec), (itp_data, offset)) in frames.into_iter().zip(encoded) {
+		let order = dedupe(args, &itc, &itp_data).unwrap_or(order);
+		itc.frames[spec.frame] = cradle::itc::Frame {
+			itp: Some(itp_data),
+			unknown: spec.unknown,
+			offset,
+			scale: spec.scale,
+			order,
 		};
-		if frame.itp.is_some() {
-			eyre::bail!("duplicate frame number");
-		}
+	}
+	Ok(itc)
+}
 
-		let path = dir.join(&spec.path);
-		let (itp_data, offset) =
-			if spec.offset.is_none() && path.extension() == Some("png") && !args.itc_no_pad {
+/// Reads or converts a frame's image to itp, returning it along with its relative offset.
+fn encode_frame(
+	args: &Args,
+	spec: &FrameSpec,
+	dir: &Utf8Path,
+	itc_palette: Option<&[u32]>,
+) -> eyre::Result<(Vec<u8>, (f32, f32))> {
+	let path = dir.join(&spec.path);
+	let crops = spec.offset.is_none() && !args.itc_no_pad;
+	let (itp_data, offset, shown_size) = if path.extension() == Some("itp") {
+		(std::fs::read(&path)?, spec.offset.unwrap_or_default(), None)
+	} else {
+		let (mut itp, offset, shown_size) =
+			if path.extension() == Some("png") && (crops || spec.scale_applied) {
 				let data = std::fs::File::open(path)?;
 				let _span = tracing::info_span!("parse_png").entered();
 				let mut png = png::read(&data)?;
-				let offset = crop(&mut png);
+				let offset = if crops {
+					let offset = crop(&mut png);
+					(offset.0 as f32, offset.1 as f32)
+				} else {
+					spec.offset.unwrap_or_default()
+				};
+				let (w, h) = (png.width(), png.height());
+				let shown_size = spec.scale_applied.then_some((w, h));
+				if spec.scale_applied {
+					let (xs, ys) = spec.scale;
+					let w = (w as f32 / xs).round().max(1.0) as usize;
+					let h = (h as f32 / ys).round().max(1.0) as usize;
+					resize(&mut png, w, h)?;
+				}
 				let mut itp = crate::itp_png::png_to_itp(args, &png);
 				drop(_span);
+				crate::clean_alpha(args, &mut itp)?;
+				crate::choose_compression(args, &mut itp);
 				crate::guess_itp_revision(args, &mut itp);
-				let offset = (offset.0 as f32, offset.1 as f32);
-				(cradle::itp::write(&itp)?, offset)
+				(itp, offset, shown_size)
 			} else {
 				let offset = spec.offset.unwrap_or_default();
-				(crate::to_itp(args, &path)?, offset)
+				(crate::image_to_itp(args, &path)?, offset, None)
 			};
+		if let Some(name) = &spec.external_palette {
+			make_external(&mut itp, name, itc_palette)?;
+		}
+		(cradle::itp::write(&itp)?, offset, shown_size)
+	};
 
-		let (w, h) = cradle::itp::read_size(&itp_data)?;
-		let offset = (offset.0 / w as f32, offset.1 / h as f32);
+	let (w, h) = match shown_size {
+		Some(size) => size,
+		None => cradle::itp::read_size(&itp_data)?,
+	};
+	Ok((itp_data, (offset.0 / w as f32, offset.1 / h as f32)))
+}
 
-		*frame = cradle::itc::Frame {
-			itp: Some(itp_data),
-			unknown: 0,
-			offset,
-			scale: spec.scale,
-			order,
+/// With `--dedupe-frames`, finds the order of an earlier frame with the same data, so that the
+/// data is written only once.
+fn dedupe(args: &Args, itc: &cradle::itc::Itc, itp: &[u8]) -> Option<usize> {
+	if !args.dedupe_frames {
+		return None;
+	}
+	itc.frames
+		.iter()
+		.find(|f| f.itp.as_deref() == Some(itp))
+		.map(|f| f.order)
+}
+
+/// Packs the itps into an itc, as consecutive frames played in order.
+pub fn from_itps(args: &Args, itps: Vec<Itp>) -> eyre::Result<cradle::itc::Itc> {
+	let mut itc = cradle::itc::Itc::default();
+	eyre::ensure!(
+		itps.len() <= itc.frames.len(),
+		"itc can hold at most {} frames, but got {}",
+		itc.frames.len(),
+		itps.len()
+	);
+	for (i, mut itp) in itps.into_iter().enumerate() {
+		crate::choose_compression(args, &mut itp);
+		crate::guess_itp_revision(args, &mut itp);
+		let data = cradle::itp::write(&itp)?;
+		itc.frames[i] = cradle::itc::Frame {
+			order: dedupe(args, &itc, &data).unwrap_or(i),
+			itp: Some(data),
+			..Default::default()
 		};
 	}
 	Ok(itc)
 }
 
+/// Replaces an external palette with the itc's palette, returning the external palette's name.
+fn inline_palette(itp: &mut Itp, itc: &cradle::itc::Itc) -> eyre::Result<Option<String>> {
+	if let ImageData::Indexed(pal, _) = &mut itp.data {
+		if let Palette::External(name) = pal {
+			let Some(palette) = &itc.palette else {
+				eyre::bail!("no palette")
+			};
+			let name = name.to_str()?.to_owned();
+			*pal = Palette::Embedded(palette.clone());
+			return Ok(Some(name));
+		}
+	}
+	Ok(None)
+}
+
+/// Writes the frames as an animated png in playback order, for previewing.
+pub fn animate(
+	args: &Args,
+	itc: &cradle::itc::Itc,
+	fps: f32,
+	write: impl std::io::Write,
+) -> eyre::Result<()> {
+	let mut frames = Vec::new();
+	for (i, frame, itp) in itc.frames() {
+		let _span = tracing::info_span!("frame", i = i).entered();
+		let mut itp = cradle::itp::read(itp)?;
+		inline_palette(&mut itp, itc)?;
+		let data = crate::itp_png::itp_to_png(args, &itp)?
+			.to_argb32()
+			.swap_remove(0);
+		let (w, h) = scaled_size(args, frame.scale, data.width(), data.height());
+		let data = data.resize_nearest(w, h);
+		let xo = (frame.offset.0 * data.width() as f32).round() as isize;
+		let yo = (frame.offset.1 * data.height() as f32).round() as isize;
+		frames.push((frame.order, data, xo, yo));
+	}
+	frames.sort_by_key(|a| a.0);
+
+	let w = frames
+		.iter()
+		.map(|(_, data, xo, _)| data.width() + xo.unsigned_abs() * 2)
+		.max()
+		.unwrap_or_default();
+	let h = frames
+		.iter()
+		.map(|(_, data, _, yo)| data.height() + yo.unsigned_abs() * 2)
+		.max()
+		.unwrap_or_default();
+	let frames = frames
+		.iter()
+		.map(|(_, data, xo, yo)| do_pad(data, *xo, *yo, w, h))
+		.collect::<Vec<_>>();
+
+	let delay = (1000. / fps).round().clamp(1., u16::MAX as f32) as u16;
+	png::write_animated(write, &frames, (delay, 1000))
+}
+
+fn make_external(itp: &mut Itp, name: &str, itc_palette: Option<&[u32]>) -> eyre::Result<()> {
+	let ImageData::Indexed(pal, _) = &mut itp.data else {
+		eyre::bail!("external palette requires an indexed image")
+	};
+	if let (Some(pal), Some(itc_palette)) = (pal.colors(), itc_palette) {
+		if pal != itc_palette {
+			tracing::warn!("palette differs from the itc's palette");
+		}
+	}
+	*pal = Palette::External(CString::new(name)?);
+	if itp.status.itp_revision != ItpRevision::V3 {
+		// In older revisions, only CCPI supports external palettes
+		itp.status.base_format = BaseFormatType::Indexed3;
+		itp.status.pixel_format = PixelFormatType::Pfp_1;
+	}
+	Ok(())
+}
+
+/// Resizes an image without mipmaps; the mip sizes would no longer make sense after scaling.
+fn resize(png: &mut png::Png, w: usize, h: usize) -> eyre::Result<()> {
+	match png {
+		png::Png::Argb32(data) => {
+			let [data] = data.as_mut_slice() else {
+				eyre::bail!("cannot scale an image with mipmaps")
+			};
+			*data = data.resize_nearest(w, h);
+		}
+		png::Png::Indexed(_, data) | png::Png::Gray(data) => {
+			let [data] = data.as_mut_slice() else {
+				eyre::bail!("cannot scale an image with mipmaps")
+			};
+			*data = data.resize_nearest(w, h);
+		}
+	}
+	Ok(())
+}
+
 fn pad(png: &mut png::Png, x: isize, y: isize, w: usize, h: usize) {
 	match png {
 		png::Png::Argb32(data) => {
@@ -204,7 +524,7 @@ fn pad(png: &mut png::Png, x: isize, y: isize, w: usize, h: usize) {
 				*data = do_pad(data, x, y, w, h);
 			}
 		}
-		png::Png::Indexed(_, data) => {
+		png::Png::Indexed(_, data) | png::Png::Gray(data) => {
 			if let [data] = data.as_mut_slice() {
 				*data = do_pad(data, x, y, w, h);
 			}
@@ -223,7 +543,7 @@ fn crop(png: &mut png::Png) -> (isize, isize) {
 				(0, 0)
 			}
 		}
-		png::Png::Indexed(_, data) => {
+		png::Png::Indexed(_, data) | png::Png::Gray(data) => {
 			if let [data] = data.as_mut_slice() {
 				let (out, offset) = do_crop(data);
 				*data = out;
@@ -235,6 +555,37 @@ fn crop(png: &mut png::Png) -> (isize, isize) {
 	}
 }
 
+/// Crops away borders that are fully transparent, returning the position of the kept region.
+///
+/// Only single-mip images with a transparent top-left pixel are trimmed; others are left as is.
+pub fn trim(png: &mut png::Png) -> (usize, usize) {
+	match png {
+		png::Png::Argb32(data) => match data.as_mut_slice() {
+			[data] if data[[0, 0]] >> 24 == 0 => do_trim(data),
+			_ => (0, 0),
+		},
+		png::Png::Indexed(pal, data) => match data.as_mut_slice() {
+			[data] if pal.get(data[[0, 0]] as usize).is_some_and(|c| c >> 24 == 0) => do_trim(data),
+			_ => (0, 0),
+		},
+		png::Png::Gray(_) => (0, 0),
+	}
+}
+
+fn do_trim<T: PartialEq + Clone>(data: &mut Raster<T>) -> (usize, usize) {
+	let Some((l, r, u, d)) = bounds(data) else {
+		return (0, 0);
+	};
+	let mut dst = Raster::splat(r - l + 1, d - u + 1, data[[0, 0]].clone());
+	for x in 0..dst.width() {
+		for y in 0..dst.height() {
+			dst[[x, y]] = data[[l + x, u + y]].clone()
+		}
+	}
+	*data = dst;
+	(l, u)
+}
+
 fn do_pad<T: PartialEq + Clone>(
 	data: &Raster<T>,
 	cx: isize,
@@ -244,20 +595,22 @@ fn do_pad<T: PartialEq + Clone>(
 ) -> Raster<T> {
 	let ox = ((w - data.width()) as isize / 2 - cx) as usize;
 	let oy = ((h - data.height()) as isize / 2 - cy) as usize;
-	let mut dst = Raster::splat(w, h, data[[0, 0]].clone());
-	for x in 0..data.width() {
-		for y in 0..data.height() {
-			dst[[ox + x, oy + y]] = data[[x, y]].clone()
-		}
-	}
-	dst
+	data.pad(
+		ox,
+		oy,
+		w - data.width() - ox,
+		h - data.height() - oy,
+		data[[0, 0]].clone(),
+	)
 }
 
 fn do_crop<T: PartialEq + Clone>(data: &Raster<T>) -> (Raster<T>, (isize, isize)) {
 	let w = data.width();
 	let h = data.height();
 
-	let (l, r, u, d) = bounds(data);
+	let Some((l, r, u, d)) = bounds(data) else {
+		panic!("not sure what to do with empty images")
+	};
 
 	let ow = (r - l + 6).next_power_of_two();
 	let oh = (d - u + 6).next_power_of_two();
@@ -279,7 +632,8 @@ fn do_crop<T: PartialEq + Clone>(data: &Raster<T>) -> (Raster<T>, (isize, isize)
 	(dst, (cx, cy))
 }
 
-fn bounds<T: PartialEq>(data: &Raster<T>) -> (usize, usize, usize, usize) {
+/// Finds the region that differs from the top-left pixel, or `None` if the image is uniform.
+fn bounds<T: PartialEq>(data: &Raster<T>) -> Option<(usize, usize, usize, usize)> {
 	let w = data.width();
 	let h = data.height();
 
@@ -288,10 +642,7 @@ fn bounds<T: PartialEq>(data: &Raster<T>) -> (usize, usize, usize, usize) {
 	let u = (0..h).find(|&y| (0..w).any(|x| data[[x, y]] != data[[0, 0]]));
 	let d = (0..h).rfind(|&y| (0..w).any(|x| data[[x, y]] != data[[0, 0]]));
 
-	let (Some(l), Some(r), Some(u), Some(d)) = (l, r, u, d) else {
-		panic!("not sure what to do with empty images")
-	};
-	(l, r, u, d)
+	Some((l?, r?, u?, d?))
 }
 
 #[cfg(test)]
@@ -316,6 +667,82 @@ fn test_itp_roundtrips(path: &Utf8Path, bytes: &[u8]) -> Result<(), eyre::Error>
 	Ok(())
 }
 
+#[cfg(test)]
+#[filetest::filetest("../../samples/itc/*.itc")]
+fn test_flat_roundtrips(path: &Utf8Path, bytes: &[u8]) -> Result<(), eyre::Error> {
+	let tmpdir = camino_tempfile::Builder::new()
+		.prefix("cradle-")
+		.suffix(&format!("-{}", path.file_stem().unwrap()))
+		.tempdir()?;
+
+	let itc = cradle::itc::read(bytes)?;
+	let csv = extract_flat(
+		&Args::default(),
+		&itc,
+		Output::In(tmpdir.path().join("a.itc")),
+	)?;
+	let itc2 = create_flat(&csv)?;
+	assert_eq!(itc, itc2);
+	let bytes2 = cradle::itc::write(&itc2)?;
+	assert_eq!(bytes, bytes2);
+	Ok(())
+}
+
+#[test]
+fn test_fps() -> Result<(), eyre::Error> {
+	let spec: ItcSpec = serde_json::from_str(r#"{"fps": 24, "frames": []}"#)?;
+	assert_eq!(spec.fps, Some(24.0));
+	assert_eq!(
+		serde_json::from_str::<ItcSpec>(&serde_json::to_string(&spec)?)?,
+		spec
+	);
+
+	let bytes = include_bytes!("../../samples/itc/3rd_psp__ch04465.itc");
+	let itc = cradle::itc::read(bytes)?;
+	let mut data = Vec::new();
+	animate(&Args::default(), &itc, 24., &mut data)?;
+	let png = ::png::Decoder::new(data.as_slice()).read_info()?;
+	let fctl = png.info().frame_control.unwrap();
+	assert_eq!((fctl.delay_num, fctl.delay_den), (42, 1000));
+	let n_frames = itc.frames().count();
+	assert_eq!(
+		png.info().animation_control.unwrap().num_frames as usize,
+		n_frames
+	);
+	Ok(())
+}
+
+#[test]
+fn test_external_palette() -> Result<(), eyre::Error> {
+	let bytes = include_bytes!("../../samples/itc/3rd_psp__ch04465.itc");
+	let tmpdir = camino_tempfile::Builder::new()
+		.prefix("cradle-")
+		.suffix("-external")
+		.tempdir()?;
+	let args = &Args {
+		itc_no_pad: true,
+		..Args::default()
+	};
+
+	let itc = cradle::itc::read(bytes)?;
+	extract(args, &itc, Output::At(tmpdir.path().to_path_buf()))?;
+	let file = std::fs::File::open(tmpdir.path().join("cradle.itc.json"))?;
+	let itc2 = create(args, serde_json::from_reader(file)?, tmpdir.path())?;
+	assert_eq!(itc.palette, itc2.palette);
+	for (f1, f2) in itc.frames.iter().zip(&itc2.frames) {
+		if let (Some(itp1), Some(itp2)) = (&f1.itp, &f2.itp) {
+			let itp1 = cradle::itp::read(itp1)?;
+			let itp2 = cradle::itp::read(itp2)?;
+			assert!(matches!(
+				&itp1.data,
+				ImageData::Indexed(Palette::External(_), _)
+			));
+			assert_eq!(itp1.data, itp2.data);
+		}
+	}
+	Ok(())
+}
+
 // Crop/pad currently do not roundtrip
 #[cfg(feature = "ignored")]
 #[cfg(test)]
@@ -347,3 +774,284 @@ fn test_crop_pad(path: &Utf8Path, bytes: &[u8]) -> Result<(), eyre::Error> {
 	assert_eq!(itc.frames, itc2.frames);
 	Ok(())
 }
+
+#[test]
+fn test_parallel() -> Result<(), eyre::Error> {
+	let bytes = include_bytes!("../../samples/itc/3rd_psp__ch04465.itc");
+	let itc = cradle::itc::read(bytes)?;
+	let serial = camino_tempfile::tempdir()?;
+	let parallel = camino_tempfile::tempdir()?;
+	extract(
+		&Args::default(),
+		&itc,
+		Output::At(serial.path().to_path_buf()),
+	)?;
+	let args = &Args {
+		jobs: Some(4),
+		..Args::default()
+	};
+	extract(args, &itc, Output::At(parallel.path().to_path_buf()))?;
+
+	let mut n = 0;
+	for entry in serial.path().read_dir_utf8()? {
+		let name = entry?.file_name().to_owned();
+		let a = std::fs::read(serial.path().join(&name))?;
+		let b = std::fs::read(parallel.path().join(&name))?;
+		assert!(a == b, "{name} differs");
+		n += 1;
+	}
+	assert_eq!(parallel.path().read_dir_utf8()?.count(), n);
+	Ok(())
+}
+
+#[test]
+fn test_distinct_external_palettes() -> Result<(), eyre::Error> {
+	let bytes = include_bytes!("../../samples/itc/3rd_psp__ch04465.itc");
+	let mut itc = cradle::itc::read(bytes)?;
+	let mut n = 0;
+	for frame in &mut itc.frames {
+		let Some(data) = &mut frame.itp else { continue };
+		let mut itp = cradle::itp::read(data)?;
+		let ImageData::Indexed(pal, _) = &mut itp.data else {
+			continue;
+		};
+		*pal = Palette::External(CString::new(format!("pal{}", n % 2))?);
+		*data = cradle::itp::write(&itp)?;
+		n += 1;
+	}
+	assert!(n >= 2);
+
+	let tmpdir = camino_tempfile::tempdir()?;
+	let args = &Args {
+		itc_no_pad: true,
+		..Args::default()
+	};
+	extract(args, &itc, Output::At(tmpdir.path().to_path_buf()))?;
+	let file = std::fs::File::open(tmpdir.path().join("cradle.itc.json"))?;
+	let itc2 = create(args, serde_json::from_reader(file)?, tmpdir.path())?;
+	assert_eq!(itc.palette, itc2.palette);
+	for (f1, f2) in itc.frames.iter().zip(&itc2.frames) {
+		if let (Some(itp1), Some(itp2)) = (&f1.itp, &f2.itp) {
+			assert_eq!(cradle::itp::read(itp1)?.data, cradle::itp::read(itp2)?.data);
+		}
+	}
+	Ok(())
+}
+
+#[test]
+fn test_trim() {
+	let mut data = Raster::splat(4, 3, 0x00000000u32);
+	data[[1, 1]] = 0xFF0000FF;
+	data[[2, 2]] = 0xFF00FF00;
+	let mut img = png::Png::Argb32(vec![data]);
+	assert_eq!(trim(&mut img), (1, 1));
+	assert_eq!((img.width(), img.height()), (2, 2));
+
+	let opaque = Raster::splat(4, 3, 0xFF000000u32);
+	let mut img = png::Png::Argb32(vec![opaque.clone()]);
+	assert_eq!(trim(&mut img), (0, 0));
+	assert_eq!(img, png::Png::Argb32(vec![opaque]));
+}
+
+#[test]
+fn test_unused_palette() -> Result<(), eyre::Error> {
+	let palette = vec![0xFF112233, 0x00000000, 0x80FFFFFF, 0xFF000001];
+	let itp = Itp::new(
+		ItpRevision::V3,
+		ImageData::Argb32(vec![Raster::splat(4, 4, 0xFF0000FF)]),
+	);
+	let mut itc = cradle::itc::Itc {
+		palette: Some(palette.clone()),
+		..Default::default()
+	};
+	itc.frames[0].itp = Some(cradle::itp::write(&itp)?);
+
+	for itp in [false, true] {
+		let tmpdir = camino_tempfile::tempdir()?;
+		let args = &Args {
+			itp,
+			..Args::default()
+		};
+		extract(args, &itc, Output::At(tmpdir.path().to_path_buf()))?;
+		let file = std::fs::File::open(tmpdir.path().join("cradle.itc.json"))?;
+		let itc2 = create(args, serde_json::from_reader(file)?, tmpdir.path())?;
+		assert_eq!(itc2.palette.as_ref(), Some(&palette));
+	}
+	Ok(())
+}
+
+#[test]
+fn test_dedupe_frames() -> Result<(), eyre::Error> {
+	let frame = |c| {
+		let data = ImageData::Argb32(vec![Raster::splat(4, 4, c)]);
+		Itp::new(ItpRevision::V3, data)
+	};
+	let itps = vec![frame(0xFFFF0000), frame(0xFF00FF00), frame(0xFFFF0000)];
+	let plain = cradle::itc::write(&from_itps(&Args::default(), itps.clone())?)?;
+	let args = Args {
+		dedupe_frames: true,
+		..Args::default()
+	};
+	let itc = from_itps(&args, itps)?;
+	assert_eq!(itc.frames[0].order, itc.frames[2].order);
+	let bytes = cradle::itc::write(&itc)?;
+	let itp_size = itc.frames[0].itp.as_ref().unwrap().len();
+	assert_eq!(bytes.len() + itp_size, plain.len());
+
+	let itc2 = cradle::itc::read(&bytes)?;
+	assert_eq!(itc2.frames[0].itp, itc2.frames[2].itp);
+	Ok(())
+}
+
+#[test]
+fn test_parallel_create() -> Result<(), eyre::Error> {
+	let bytes = include_bytes!("../../samples/itc/3rd_psp__ch04465.itc");
+	let itc = cradle::itc::read(bytes)?;
+	let tmpdir = camino_tempfile::tempdir()?;
+	extract(
+		&Args::default(),
+		&itc,
+		Output::At(tmpdir.path().to_path_buf()),
+	)?;
+	let create_with = |jobs| -> eyre::Result<Vec<u8>> {
+		let args = &Args {
+			jobs,
+			..Args::default()
+		};
+		let file = std::fs::File::open(tmpdir.path().join("cradle.itc.json"))?;
+		let itc = create(args, serde_json::from_reader(file)?, tmpdir.path())?;
+		Ok(cradle::itc::write(&itc)?)
+	};
+	assert!(create_with(Some(1))? == create_with(Some(4))?);
+	Ok(())
+}
+
+#[test]
+fn test_apply_scale() -> Result<(), eyre::Error> {
+	let (r, g) = (0xFFFF0000, 0xFF00FF00);
+	let pixels = Raster::new_with(2, 1, vec![r, g]);
+	let itp = Itp::new(ItpRevision::V3, ImageData::Argb32(vec![pixels]));
+	let mut itc = cradle::itc::Itc::default();
+	itc.frames[0] = cradle::itc::Frame {
+		itp: Some(cradle::itp::write(&itp)?),
+		unknown: 0,
+		offset: (0.0, 0.0),
+		scale: (2.0, 1.0),
+		order: 0,
+	};
+
+	let tmpdir = camino_tempfile::tempdir()?;
+	let args = &Args {
+		itc_no_pad: true,
+		itc_apply_scale: true,
+		..Args::default()
+	};
+	extract(args, &itc, Output::At(tmpdir.path().to_path_buf()))?;
+	let file = std::fs::File::open(tmpdir.path().join("0.png"))?;
+	let png = png::read(file)?;
+	assert_eq!(png.to_argb32()[0].as_slice(), [r, r, g, g]);
+
+	let file = std::fs::File::open(tmpdir.path().join("cradle.itc.json"))?;
+	let spec: ItcSpec = serde_json::from_reader(file)?;
+	assert!(spec.frames[0].scale_applied);
+	let itc2 = create(args, spec, tmpdir.path())?;
+	assert_eq!(itc2.frames[0].scale, (2.0, 1.0));
+	let itp2 = cradle::itp::read(itc2.frames[0].itp.as_ref().unwrap())?;
+	assert_eq!(itp2.data, itp.data);
+
+	// Mipmaps cannot be scaled along with it, so that is an error rather than silently unscaled
+	let mips = vec![Raster::new(2, 2), Raster::new(1, 1)];
+	let itp = Itp::new(ItpRevision::V3, ImageData::Argb32(mips));
+	itc.frames[0].itp = Some(cradle::itp::write(&itp)?);
+	let args = &Args {
+		png_mipmap: true,
+		..args.clone()
+	};
+	let output = Output::At(tmpdir.path().join("mips"));
+	assert!(extract(args, &itc, output).is_err());
+	Ok(())
+}
+
+#[test]
+fn test_clean_alpha_crop() -> Result<(), eyre::Error> {
+	let tmpdir = camino_tempfile::tempdir()?;
+	let pixels = vec![0xFFFF0000, 0x00FFFFFF, 0xFF00FF00];
+	let img = png::Png::Argb32(vec![Raster::new_with(3, 1, pixels)]);
+	png::write(std::fs::File::create(tmpdir.path().join("a.png"))?, &img)?;
+
+	let spec = FrameSpec {
+		frame: 0,
+		path: "a.png".into(),
+		offset: None,
+		scale: unit_scale(),
+		scale_applied: false,
+		external_palette: None,
+		unknown: 0,
+	};
+	let args = &Args {
+		clean_alpha: Some(crate::CleanAlpha::Black),
+		..Args::default()
+	};
+	let (data, _) = encode_frame(args, &spec, tmpdir.path(), None)?;
+	let ImageData::Argb32(mips) = cradle::itp::read(&data)?.data else {
+		eyre::bail!("expected argb32")
+	};
+	assert_eq!(mips[0].as_slice(), [0xFFFF0000, 0, 0xFF00FF00]);
+	Ok(())
+}
+
+#[test]
+fn test_unknown_roundtrips() -> Result<(), eyre::Error> {
+	let itp = Itp::new(ItpRevision::V3, ImageData::Argb32(vec![Raster::new(2, 2)]));
+	let mut itc = cradle::itc::Itc::default();
+	itc.frames[0] = cradle::itc::Frame {
+		itp: Some(cradle::itp::write(&itp)?),
+		unknown: 5,
+		offset: (0.0, 0.0),
+		scale: (1.0, 1.0),
+		order: 0,
+	};
+
+	let tmpdir = camino_tempfile::tempdir()?;
+	let args = &Args {
+		itp: true,
+		..Args::default()
+	};
+	extract(args, &itc, Output::At(tmpdir.path().to_path_buf()))?;
+	let file = std::fs::File::open(tmpdir.path().join("cradle.itc.json"))?;
+	let itc2 = create(args, serde_json::from_reader(file)?, tmpdir.path())?;
+	assert_eq!(itc2.frames[0].unknown, 5);
+
+	let csv = extract_flat(args, &itc, Output::In(tmpdir.path().join("a.itc")))?;
+	assert_eq!(create_flat(&csv)?, itc);
+	Ok(())
+}
+
+#[test]
+fn test_animate() -> Result<(), eyre::Error> {
+	let frame = |w, h, offset, scale, order| -> eyre::Result<cradle::itc::Frame> {
+		let itp = Itp::new(ItpRevision::V3, ImageData::Argb32(vec![Raster::new(w, h)]));
+		Ok(cradle::itc::Frame {
+			itp: Some(cradle::itp::write(&itp)?),
+			unknown: 0,
+			offset,
+			scale,
+			order,
+		})
+	};
+	let mut itc = cradle::itc::Itc::default();
+	itc.frames[0] = frame(2, 1, (0.5, 0.0), (1.0, 1.0), 1)?;
+	itc.frames[3] = frame(1, 1, (0.0, 0.0), (2.0, 2.0), 0)?;
+
+	let args = &Args {
+		itc_apply_scale: true,
+		..Args::default()
+	};
+	let mut data = Vec::new();
+	animate(args, &itc, 10., &mut data)?;
+	let png = ::png::Decoder::new(data.as_slice()).read_info()?;
+	// Wide enough for the offset frame on both sides, and tall enough for the scaled one
+	assert_eq!((png.info().width, png.info().height), (4, 2));
+	assert_eq!(png.info().animation_control.unwrap().num_frames, 2);
+	Ok(())
+}
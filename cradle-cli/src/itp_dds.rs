@@ -1,56 +1,131 @@
 use std::io::{Read, Write};
 
 use cradle::{
-	itp::{ImageData, Itp, ItpRevision, Palette},
+	color::{linear_to_srgb, premultiply, rgb9e5_to_float, swap_channels, yuv_to_argb},
+	itp::{mip_dimensions, Argb16Mode, ImageData, Itp, ItpRevision, Palette},
 	raster::Raster,
 };
 use cradle_dds as dds;
 
+use camino::Utf8Path;
 use strength_reduce::StrengthReducedU64 as SR64;
 
-use crate::Args;
+use crate::{Args, DdsFormat, PaletteBits, Rotation};
 
-pub fn itp_to_dds(args: &Args, mut write: impl Write, itp: &Itp) -> eyre::Result<()> {
-	let _ = args;
+pub fn itp_to_dds(args: &Args, write: impl Write, itp: &Itp) -> eyre::Result<()> {
+	write_dds(args, write, itp, None)
+}
+
+/// Like [`itp_to_dds`], but creates the file at `path`.
+///
+/// With `--dds-keep-reserved`, the reserved header fields of the dds file being replaced, if any,
+/// are carried over.
+pub fn itp_to_dds_file(args: &Args, path: &Utf8Path, itp: &Itp) -> eyre::Result<()> {
+	let original = if args.dds_keep_reserved && path.exists() {
+		Some(dds::Dds::read(&mut std::fs::File::open(path)?)?)
+	} else {
+		None
+	};
+	let f = crate::util::create_file(args, path)?;
+	write_dds(args, f, itp, original.as_ref())
+}
+
+fn write_dds(
+	args: &Args,
+	mut write: impl Write,
+	itp: &Itp,
+	original: Option<&dds::Dds>,
+) -> eyre::Result<()> {
 	let Itp {
 		status: _,
 		ref data,
 	} = *itp;
+	let rotated;
+	let data = if let Some(rotation) = args.rotate {
+		rotated = rotate(data.clone(), rotation)?;
+		&rotated
+	} else {
+		data
+	};
 	let mut header = dds::Dds {
 		width: data.width() as u32,
 		height: data.height() as u32,
 		..dds::Dds::default()
 	};
+	if let Some(original) = original {
+		header.reserved = original.reserved;
+		header.reserved2 = original.reserved2;
+	}
 
 	let nmip = data.mipmaps();
 	if nmip != 1 {
 		header.flags |= dds::DDSD::MIPMAPCOUNT;
 		header.mip_map_count = nmip as u32;
 	}
+	header.caps = dds::compute_caps(&header);
+
+	if args.premultiply && !matches!(data, ImageData::Indexed(..) | ImageData::Argb32(..)) {
+		eyre::bail!("premultiplied alpha is only supported for uncompressed images");
+	}
 
 	match &data {
 		ImageData::Indexed(pal, data) => {
-			let pal = match pal {
-				Palette::Embedded(pal) => pal,
-				Palette::External(_) => eyre::bail!("external palette is not currently supported"),
+			let Some(pal) = pal.colors() else {
+				eyre::bail!("external palette is not currently supported")
 			};
-			header.pixel_format.flags |= dds::DDPF::PALETTEINDEXED8;
-			header.pixel_format.bpp = 8;
+			let mut pal = pal.to_vec();
+			if args.premultiply {
+				header.pixel_format.flags |= dds::DDPF::ALPHAPREMULT;
+				pal.iter_mut().for_each(|a| *a = premultiply(*a));
+			}
+			let bits = palette_bits(args, data);
+			header.pixel_format.flags |= match bits {
+				1 => dds::DDPF::PALETTEINDEXED1,
+				2 => dds::DDPF::PALETTEINDEXED2,
+				4 => dds::DDPF::PALETTEINDEXED4,
+				_ => dds::DDPF::PALETTEINDEXED8,
+			};
+			header.pixel_format.bpp = bits as u32;
 			header.write(&mut write)?;
-			let mut pal2 = [0; 256];
-			pal2[..pal.len()].copy_from_slice(pal);
+			// Entries past what the indices can reach are unused, so they are left out
+			let mut pal2 = vec![0; 1 << bits];
+			let n = pal.len().min(pal2.len());
+			pal2[..n].copy_from_slice(&pal[..n]);
+			swap_channels(&mut pal2);
 			write.write_all(
 				&pal2
 					.iter()
-					.flat_map(|a| {
-						let [b, g, r, a] = u32::to_le_bytes(*a);
-						[r, g, b, a]
-					})
+					.flat_map(|a| a.to_le_bytes())
 					.collect::<Vec<_>>(),
 			)?;
-			write_data(write, data, u8::to_le_bytes)
+			if bits == 8 {
+				write_data(write, data, u8::to_le_bytes)
+			} else {
+				write_packed(write, data, bits)
+			}
+		}
+		ImageData::Argb16(mode, data) => {
+			let pf = &mut header.pixel_format;
+			pf.bpp = 16;
+			(pf.rmask, pf.gmask, pf.bmask, pf.amask) = argb16_masks(*mode);
+			if pf.amask == 0 {
+				pf.flags &= !dds::DDPF::ALPHAPIXELS;
+			}
+			header.write(&mut write)?;
+			write_data(write, data, u16::to_le_bytes)
+		}
+		ImageData::Argb32(data) if args.premultiply => {
+			// Alpha mode can only be expressed in the dx10 header
+			header.pixel_format.flags |= dds::DDPF::FOURCC;
+			header.pixel_format.four_cc = *b"DX10";
+			header.dx10 = Some(dds::Dx10Header {
+				dxgi_format: dds::DXGI_FORMAT::B8G8R8A8_UNORM,
+				misc_flag2: dds::ALPHA_MODE::PREMULTIPLIED,
+				..dds::Dx10Header::default()
+			});
+			header.write(&mut write)?;
+			write_data(write, data, |a| premultiply(a).to_le_bytes())
 		}
-		ImageData::Argb16(_, _) => eyre::bail!("16-bit color is not currently supported"),
 		ImageData::Argb32(data) => {
 			header.write(&mut write)?;
 			write_data(write, data, u32::to_le_bytes)
@@ -58,6 +133,10 @@ pub fn itp_to_dds(args: &Args, mut write: impl Write, itp: &Itp) -> eyre::Result
 		ImageData::Bc1(data) => {
 			header.pixel_format.flags |= dds::DDPF::FOURCC;
 			header.pixel_format.four_cc = *b"DXT1";
+			// Readers use this to tell DXT1 with 1-bit alpha from opaque DXT1
+			if !itp.data.bc1_has_alpha() {
+				header.pixel_format.flags &= !dds::DDPF::ALPHAPIXELS;
+			}
 			header.write(&mut write)?;
 			write_data(write, data, u64::to_le_bytes)
 		}
@@ -87,19 +166,169 @@ pub fn itp_to_dds(args: &Args, mut write: impl Write, itp: &Itp) -> eyre::Result
 }
 
 pub fn dds_to_itp(args: &Args, mut read: impl Read) -> eyre::Result<Itp> {
-	let _ = args;
+	let dds = read_header(args, &mut read)?;
+	let slices = array_size(&dds);
+	eyre::ensure!(
+		slices == 1,
+		"this dds is an array of {slices} textures, use --dds-array to convert it to itc"
+	);
+	to_itp(args, read_slice(args, read, &dds)?)
+}
+
+/// Reads `name.dds` as the first level, followed by `name.1.dds`, `name.2.dds`, and so on for as
+/// long as they exist, each holding one further mip level.
+pub fn dds_files_to_itp(args: &Args, path: &Utf8Path) -> eyre::Result<Itp> {
+	let mut data = dds_to_itp(args, std::fs::File::open(path)?)?.data;
+	for level in 1.. {
+		let mip_path = path.with_extension(format!("{level}.dds"));
+		if !mip_path.exists() {
+			break;
+		}
+		let _span = tracing::info_span!("mip", path = %mip_path).entered();
+		// Otherwise the mip files would be appended after levels that are already there
+		eyre::ensure!(
+			data.mipmaps() == level,
+			"{path} already has mipmaps, so it cannot be combined with mip files"
+		);
+		let mip = dds_to_itp(args, std::fs::File::open(&mip_path)?)?.data;
+		eyre::ensure!(mip.mipmaps() == 1, "mip files must hold a single level");
+		let expected = data.mip_dimensions(level);
+		let size = (mip.width(), mip.height());
+		eyre::ensure!(
+			size == expected,
+			"mip {level} is {}×{}, but should be {}×{}",
+			size.0,
+			size.1,
+			expected.0,
+			expected.1,
+		);
+		push_mip(&mut data, mip)?;
+	}
+	Ok(Itp::new(ItpRevision::V3, data))
+}
+
+fn push_mip(data: &mut ImageData, mip: ImageData) -> eyre::Result<()> {
+	use ImageData as ID;
+	match (data, mip) {
+		(ID::Indexed(p1, d1), ID::Indexed(p2, d2)) if *p1 == p2 => d1.extend(d2),
+		(ID::Argb16(m1, d1), ID::Argb16(m2, d2)) if *m1 == m2 => d1.extend(d2),
+		(ID::Argb32(d1), ID::Argb32(d2)) => d1.extend(d2),
+		(ID::Bc1(d1), ID::Bc1(d2)) => d1.extend(d2),
+		(ID::Bc2(d1), ID::Bc2(d2)) => d1.extend(d2),
+		(ID::Bc3(d1), ID::Bc3(d2)) => d1.extend(d2),
+		(ID::Bc7(d1), ID::Bc7(d2)) => d1.extend(d2),
+		(data, mip) => eyre::bail!(
+			"mip is {}, but the first level is {}",
+			mip.format_name(),
+			data.format_name()
+		),
+	}
+	Ok(())
+}
+
+/// Reads a texture array, returning one itp per array slice.
+pub fn dds_to_itps(args: &Args, mut read: impl Read) -> eyre::Result<Vec<Itp>> {
+	let dds = read_header(args, &mut read)?;
+	let slices = array_size(&dds);
+	let mut data = Vec::new();
+	read.read_to_end(&mut data)?;
+	// All slices have the same dimensions and format, and thus the same size
+	eyre::ensure!(
+		data.len() % slices == 0,
+		"cannot split {} bytes into {slices} array slices",
+		data.len()
+	);
+	let size = data.len() / slices;
+	(0..slices)
+		.map(|i| {
+			let slice = &data[i * size..(i + 1) * size];
+			to_itp(args, read_slice(args, slice, &dds)?)
+		})
+		.collect()
+}
+
+fn read_header(args: &Args, mut read: impl Read) -> eyre::Result<dds::Dds> {
 	let mut dds = dds::Dds::read(&mut read)?;
+	// Some old tools only set the pitch or linear size, which is not enough to know the shape
+	eyre::ensure!(
+		dds.width != 0 && dds.height != 0,
+		"dds header has a size of {}×{}, so the image cannot be read; \
+		 if the header was written by an unusual tool, also check its format, \
+		 which can be overridden with --dds-format",
+		dds.width,
+		dds.height,
+	);
 	un_dxgi(&mut dds);
+	if let Some(format) = args.dds_format {
+		force_format(&mut dds, format);
+	}
+	Ok(dds)
+}
+
+/// Replaces the header's pixel format, for files whose header is wrong.
+fn force_format(dds: &mut dds::Dds, format: DdsFormat) {
+	let pf = &mut dds.pixel_format;
+	let four_cc = match format {
+		DdsFormat::Bc1 => b"DXT1",
+		DdsFormat::Bc2 => b"DXT3",
+		DdsFormat::Bc3 => b"DXT5",
+		DdsFormat::Bc7 => {
+			let dx10 = dds.dx10.get_or_insert_with(dds::Dx10Header::default);
+			dx10.dxgi_format = dds::DXGI_FORMAT::BC7_UNORM;
+			b"DX10"
+		}
+		DdsFormat::Argb32 => {
+			pf.flags = dds::DDPF::RGB | dds::DDPF::ALPHAPIXELS;
+			(pf.bpp, pf.rmask, pf.gmask, pf.bmask, pf.amask) =
+				(32, 0x00FF0000, 0x0000FF00, 0x000000FF, 0xFF000000);
+			return;
+		}
+	};
+	pf.flags = dds::DDPF::FOURCC;
+	pf.four_cc = *four_cc;
+}
+
+fn array_size(dds: &dds::Dds) -> usize {
+	dds.dx10
+		.as_ref()
+		.map_or(1, |dx10| dx10.array_size.max(1) as usize)
+}
+
+fn to_itp(args: &Args, data: ImageData) -> eyre::Result<Itp> {
+	let data = match args.rotate {
+		Some(rotation) => rotate(data, rotation)?,
+		None => data,
+	};
+	Ok(Itp::new(ItpRevision::V3, data))
+}
+
+fn read_slice(args: &Args, mut read: impl Read, dds: &dds::Dds) -> eyre::Result<ImageData> {
 	let pf = &dds.pixel_format;
-	let data = if pf.flags & dds::DDPF::PALETTEINDEXED8 != 0 {
-		let mut palette = [0; 4 * 256];
+	let palette_bits = if pf.flags & dds::DDPF::PALETTEINDEXED8 != 0 {
+		Some(8)
+	} else if pf.flags & dds::DDPF::PALETTEINDEXED4 != 0 {
+		Some(4)
+	} else if pf.flags & dds::DDPF::PALETTEINDEXED2 != 0 {
+		Some(2)
+	} else if pf.flags & dds::DDPF::PALETTEINDEXED1 != 0 {
+		Some(1)
+	} else {
+		None
+	};
+	let data = if let Some(bits) = palette_bits {
+		let mut palette = vec![0; 4 << bits];
 		read.read_exact(&mut palette)?;
 		let mut palette = palette
 			.array_chunks()
 			.copied()
-			.map(|[r, g, b, a]| u32::from_le_bytes([b, g, r, a]))
+			.map(u32::from_le_bytes)
 			.collect::<Vec<_>>();
-		let data = read_data(read, &dds, 1, u8::from_le_bytes)?;
+		swap_channels(&mut palette);
+		let data = if bits == 8 {
+			read_data(read, dds, 1, u8::from_le_bytes)?
+		} else {
+			read_packed(read, dds, bits)?
+		};
 
 		let max = data
 			.iter()
@@ -114,69 +343,120 @@ pub fn dds_to_itp(args: &Args, mut read: impl Read) -> eyre::Result<Itp> {
 		ImageData::Indexed(Palette::Embedded(palette), data)
 	} else if pf.flags & dds::DDPF::FOURCC != 0 {
 		match &pf.four_cc {
-			b"DXT1" => ImageData::Bc1(read_data(read, &dds, 4, u64::from_le_bytes)?),
-			b"DXT3" => ImageData::Bc2(read_data(read, &dds, 4, u128::from_le_bytes)?),
-			b"DXT5" => ImageData::Bc3(read_data(read, &dds, 4, u128::from_le_bytes)?),
+			b"DXT1" => ImageData::Bc1(read_blocks(read, dds, u64::from_le_bytes)?),
+			b"DXT3" => ImageData::Bc2(read_blocks(read, dds, u128::from_le_bytes)?),
+			b"DXT5" => ImageData::Bc3(read_blocks(read, dds, u128::from_le_bytes)?),
+			b"YUY2" => read_yuy2(read, dds)?,
 			b"DX10" => {
 				let dx10 = dds.dx10.as_ref().unwrap();
 				use dds::DXGI_FORMAT as D;
 				match dx10.dxgi_format {
 					D::BC1_TYPELESS | D::BC1_UNORM | D::BC1_UNORM_SRGB => {
-						ImageData::Bc1(read_data(read, &dds, 4, u64::from_le_bytes)?)
+						ImageData::Bc1(read_blocks(read, dds, u64::from_le_bytes)?)
 					}
 					D::BC2_TYPELESS | D::BC2_UNORM | D::BC2_UNORM_SRGB => {
-						ImageData::Bc2(read_data(read, &dds, 4, u128::from_le_bytes)?)
+						ImageData::Bc2(read_blocks(read, dds, u128::from_le_bytes)?)
 					}
 					D::BC3_TYPELESS | D::BC3_UNORM | D::BC3_UNORM_SRGB => {
-						ImageData::Bc3(read_data(read, &dds, 4, u128::from_le_bytes)?)
+						ImageData::Bc3(read_blocks(read, dds, u128::from_le_bytes)?)
 					}
 					D::BC7_TYPELESS | D::BC7_UNORM | D::BC7_UNORM_SRGB => {
-						ImageData::Bc7(read_data(read, &dds, 4, u128::from_le_bytes)?)
+						ImageData::Bc7(read_blocks(read, dds, u128::from_le_bytes)?)
 					}
+					D::AYUV => ImageData::Argb32(read_data(read, dds, 1, |[v, u, y, a]| {
+						yuv_to_argb(y, u, v, a)
+					})?),
+					D::YUY2 => read_yuy2(read, dds)?,
+					// There is no floating-point ImageData, so clamp it for preview
+					D::R9G9B9E5_SHAREDEXP => ImageData::Argb32(read_data(read, dds, 1, |d| {
+						let [r, g, b] = rgb9e5_to_float(u32::from_le_bytes(d)).map(linear_to_srgb);
+						u32::from_le_bytes([b, g, r, 0xFF])
+					})?),
 					_ => eyre::bail!("I don't understand this dds (dxgi)"),
 				}
 			}
 			_ => eyre::bail!("I don't understand this dds (fourcc)"),
 		}
-	} else if pf.flags & dds::DDPF::RGB != 0 {
+	} else if let Some(mode) = argb16_mode(pf).filter(|_| args.dds_argb16) {
+		ImageData::Argb16(mode, read_data(read, dds, 1, u16::from_le_bytes)?)
+	} else if pf.flags & (dds::DDPF::RGB | dds::DDPF::ALPHA) != 0 {
+		// Alpha-only formats, used for masks, have no color masks, so their color reads as black
 		let cmask = (
 			sr64(pf.rmask),
 			sr64(pf.gmask),
 			sr64(pf.bmask),
 			sr64(pf.amask),
 		);
+		// Formats without an alpha channel are opaque
+		let opaque = if pf.amask == 0 { 0xFF000000 } else { 0 };
 		match pf.bpp {
-			32 => ImageData::Argb32(read_data(read, &dds, 1, |d| {
-				mask(cmask, u32::from_le_bytes(d))
+			32 => ImageData::Argb32(read_data(read, dds, 1, |d| {
+				mask(cmask, u32::from_le_bytes(d)) | opaque
 			})?),
-			16 => ImageData::Argb32(read_data(read, &dds, 1, |d| {
-				mask(cmask, u16::from_le_bytes(d) as u32)
+			16 => ImageData::Argb32(read_data(read, dds, 1, |d| {
+				mask(cmask, u16::from_le_bytes(d) as u32) | opaque
 			})?),
-			8 => ImageData::Argb32(read_data(read, &dds, 1, |d| {
-				mask(cmask, u8::from_le_bytes(d) as u32)
+			8 => ImageData::Argb32(read_data(read, dds, 1, |d| {
+				mask(cmask, u8::from_le_bytes(d) as u32) | opaque
 			})?),
 			_ => eyre::bail!("I don't understand this dds (bbp)"),
 		}
 	} else {
 		eyre::bail!("I don't understand this dds")
 	};
+	Ok(data)
+}
 
-	Ok(Itp::new(ItpRevision::V3, data))
+fn rotate(data: ImageData, rotation: Rotation) -> eyre::Result<ImageData> {
+	Ok(match data {
+		ImageData::Indexed(pal, data) => {
+			ImageData::Indexed(pal, data.iter().map(|a| rotation.apply(a)).collect())
+		}
+		ImageData::Argb16(mode, data) => {
+			ImageData::Argb16(mode, data.iter().map(|a| rotation.apply(a)).collect())
+		}
+		ImageData::Argb32(data) => {
+			ImageData::Argb32(data.iter().map(|a| rotation.apply(a)).collect())
+		}
+		_ => eyre::bail!("rotation is only supported for uncompressed images"),
+	})
 }
 
 fn write_data<T: Copy, const N: usize>(
-	mut write: impl Write,
+	write: impl Write,
 	data: &[Raster<T>],
-	to_le_bytes: impl FnMut(T) -> [u8; N],
+	mut to_le_bytes: impl FnMut(T) -> [u8; N],
 ) -> eyre::Result<()> {
-	Ok(write.write_all(
-		&data
-			.iter()
-			.flat_map(|a| a.as_slice())
-			.copied()
-			.flat_map(to_le_bytes)
-			.collect::<Vec<_>>(),
-	)?)
+	// Large textures can be hundreds of megabytes, so avoid collecting everything first
+	let mut write = std::io::BufWriter::new(write);
+	for raster in data {
+		for &pixel in raster.as_slice() {
+			write.write_all(&to_le_bytes(pixel))?;
+		}
+	}
+	write.flush()?;
+	Ok(())
+}
+
+fn read_blocks<T, const N: usize>(
+	mut read: impl Read,
+	dds: &dds::Dds,
+	from_le_bytes: impl FnMut([u8; N]) -> T,
+) -> eyre::Result<Vec<Raster<T>>> {
+	let expected = (0..dds.mip_map_count as usize)
+		.map(|i| {
+			let (w, h) = mip_dimensions(dds.width as usize, dds.height as usize, i, 4);
+			w / 4 * (h / 4) * N
+		})
+		.sum::<usize>();
+	let mut data = Vec::new();
+	read.read_to_end(&mut data)?;
+	eyre::ensure!(
+		data.len() == expected,
+		"wrong size of block data: expected {expected} bytes, but got {}",
+		data.len()
+	);
+	read_data(data.as_slice(), dds, 4, from_le_bytes)
 }
 
 fn read_data<T, const N: usize>(
@@ -187,19 +467,130 @@ fn read_data<T, const N: usize>(
 ) -> eyre::Result<Vec<Raster<T>>> {
 	let mut out = Vec::new();
 	for i in 0..dds.mip_map_count as usize {
-		let w = (dds.width as usize >> i) / scale;
-		let h = (dds.height as usize >> i) / scale;
-		let mut data = vec![0; w * h * N];
+		let (w, h) = mip_dimensions(dds.width as usize, dds.height as usize, i, scale);
+		let (w, h) = (w / scale, h / scale);
+		// Pitch only describes the top level, and only for uncompressed formats
+		let has_pitch = i == 0 && scale == 1 && dds.flags & dds::DDSD::PITCH != 0;
+		let pitch = if has_pitch {
+			(dds.pitch as usize).max(w * N)
+		} else {
+			w * N
+		};
+		let mut data = vec![0; pitch * h];
 		read.read_exact(&mut data)?;
-		out.push(Raster::new_with(
+		out.push(Raster::try_new_with(
 			w,
 			h,
-			data.array_chunks().map(|a| from_le_bytes(*a)).collect(),
-		))
+			data.chunks(pitch.max(1))
+				.flat_map(|row| row[..w * N].array_chunks::<N>())
+				.map(|a| from_le_bytes(*a))
+				.collect(),
+		)?)
 	}
 	Ok(out)
 }
 
+/// Reads 4:2:2 YUV data, where each pair of pixels is stored as `[y0, u, y1, v]`.
+fn read_yuy2(read: impl Read, dds: &dds::Dds) -> eyre::Result<ImageData> {
+	let data = read_data(read, dds, 1, |a: [u8; 2]| a)?;
+	let mut out = Vec::new();
+	for r in &data {
+		eyre::ensure!(r.width() % 2 == 0, "yuy2 width must be even");
+		let pixels = r
+			.as_slice()
+			.array_chunks()
+			.flat_map(|&[[y0, u], [y1, v]]| {
+				[yuv_to_argb(y0, u, v, 0xFF), yuv_to_argb(y1, u, v, 0xFF)]
+			})
+			.collect();
+		out.push(Raster::new_with(r.width(), r.height(), pixels));
+	}
+	Ok(ImageData::Argb32(out))
+}
+
+fn palette_bits(args: &Args, data: &[Raster<u8>]) -> usize {
+	if args.palette_bits == PaletteBits::Eight {
+		return 8;
+	}
+	let max = data
+		.iter()
+		.flat_map(|a| a.as_slice())
+		.map(|a| *a as usize + 1)
+		.max()
+		.unwrap_or_default();
+	match max {
+		0..=2 => 1,
+		3..=4 => 2,
+		5..=16 => 4,
+		_ => 8,
+	}
+}
+
+/// Writes indexed data with less than 8 bits per pixel, leftmost pixel in the high bits.
+fn write_packed(mut write: impl Write, data: &[Raster<u8>], bits: usize) -> eyre::Result<()> {
+	let mut out = Vec::new();
+	for raster in data {
+		for row in raster.as_slice().chunks(raster.width().max(1)) {
+			out.extend(row.chunks(8 / bits).map(|px| {
+				px.iter()
+					.enumerate()
+					.fold(0, |b, (i, &p)| b | p << (8 - bits * (i + 1)))
+			}));
+		}
+	}
+	Ok(write.write_all(&out)?)
+}
+
+fn read_packed(mut read: impl Read, dds: &dds::Dds, bits: usize) -> eyre::Result<Vec<Raster<u8>>> {
+	let mask = (1u8 << bits) - 1;
+	let mut out = Vec::new();
+	for i in 0..dds.mip_map_count as usize {
+		let (w, h) = mip_dimensions(dds.width as usize, dds.height as usize, i, 1);
+		let row_size = (w * bits).div_ceil(8);
+		let has_pitch = i == 0 && dds.flags & dds::DDSD::PITCH != 0;
+		let pitch = if has_pitch {
+			(dds.pitch as usize).max(row_size)
+		} else {
+			row_size
+		};
+		let mut data = vec![0; pitch * h];
+		read.read_exact(&mut data)?;
+		out.push(Raster::try_new_with(
+			w,
+			h,
+			data.chunks(pitch.max(1))
+				.flat_map(|row| {
+					row.iter()
+						.flat_map(move |&b| {
+							(0..8 / bits).rev().map(move |i| (b >> (i * bits)) & mask)
+						})
+						.take(w)
+				})
+				.collect(),
+		)?)
+	}
+	Ok(out)
+}
+
+/// The channel masks of a 16-bit layout, in the order red, green, blue, alpha.
+fn argb16_masks(mode: Argb16Mode) -> (u32, u32, u32, u32) {
+	match mode {
+		Argb16Mode::Mode1 => (0x7C00, 0x03E0, 0x001F, 0x8000),
+		Argb16Mode::Mode2 => (0x0F00, 0x00F0, 0x000F, 0xF000),
+		Argb16Mode::Mode3 => (0xF800, 0x07E0, 0x001F, 0x0000),
+	}
+}
+
+/// Finds the 16-bit layout that an uncompressed dds is stored in, if itp can store it as is.
+fn argb16_mode(pf: &dds::PixelFormat) -> Option<Argb16Mode> {
+	if pf.flags & dds::DDPF::RGB == 0 || pf.bpp != 16 {
+		return None;
+	}
+	[Argb16Mode::Mode1, Argb16Mode::Mode2, Argb16Mode::Mode3]
+		.into_iter()
+		.find(|&mode| argb16_masks(mode) == (pf.rmask, pf.gmask, pf.bmask, pf.amask))
+}
+
 fn un_dxgi(dds: &mut dds::Dds) {
 	let pf = &mut dds.pixel_format;
 	if pf.flags & dds::DDPF::FOURCC != 0 && pf.four_cc == *b"DX10" {
@@ -232,7 +623,7 @@ fn un_dxgi(dds: &mut dds::Dds) {
 			_ => return,
 		};
 		(pf.bpp, pf.rmask, pf.gmask, pf.bmask, pf.amask) = mask;
-		pf.flags &= !dds::DDPF::FOURCC;
+		pf.flags = pf.flags & !dds::DDPF::FOURCC | dds::DDPF::RGB;
 	}
 }
 
@@ -282,3 +673,484 @@ fn test_parse_all(bytes: &[u8]) -> Result<(), eyre::Error> {
 	assert!(dds_data == dds_data2);
 	Ok(())
 }
+
+#[test]
+fn test_pitch() -> eyre::Result<()> {
+	let header = dds::Dds {
+		flags: dds::DDSD::DEFAULT | dds::DDSD::PITCH,
+		width: 30,
+		height: 2,
+		pitch: 128,
+		..dds::Dds::default()
+	};
+	let mut data = Vec::new();
+	header.write(&mut data)?;
+	for y in 0..2u32 {
+		for x in 0..30u32 {
+			data.extend((0xFF000000 | y << 8 | x).to_le_bytes());
+		}
+		data.extend([0xAA; 8]);
+	}
+
+	let itp = dds_to_itp(&Args::default(), data.as_slice())?;
+	let ImageData::Argb32(mips) = &itp.data else {
+		eyre::bail!("expected argb32")
+	};
+	assert_eq!(mips[0].width(), 30);
+	assert_eq!(mips[0].height(), 2);
+	assert_eq!(mips[0][[29, 0]], 0xFF00001D);
+	assert_eq!(mips[0][[0, 1]], 0xFF000100);
+	assert_eq!(mips[0][[29, 1]], 0xFF00011D);
+	Ok(())
+}
+
+#[test]
+fn test_palette_bits() -> eyre::Result<()> {
+	let args = &Args {
+		palette_bits: PaletteBits::Auto,
+		..Args::default()
+	};
+	let pal = vec![0xFF000000, 0xFFFF0000, 0xFF00FF00, 0xFF0000FF];
+	let pixels = (0..15).map(|i| (i * 7 % 4) as u8).collect();
+	let itp = Itp::new(
+		ItpRevision::V3,
+		ImageData::Indexed(Palette::Embedded(pal), vec![Raster::new_with(5, 3, pixels)]),
+	);
+	let mut data = Vec::new();
+	itp_to_dds(args, &mut data, &itp)?;
+	let dds = dds::Dds::read(&mut data.as_slice())?;
+	assert_ne!(dds.pixel_format.flags & dds::DDPF::PALETTEINDEXED2, 0);
+	assert_eq!(dds.pixel_format.bpp, 2);
+
+	let itp2 = dds_to_itp(args, data.as_slice())?;
+	assert_eq!(itp.data, itp2.data);
+	Ok(())
+}
+
+#[test]
+fn test_palette_bits_large_palette() -> eyre::Result<()> {
+	let args = &Args {
+		palette_bits: PaletteBits::Auto,
+		..Args::default()
+	};
+	let pal = (0..256)
+		.map(|i| 0xFF000000 | i * 0x010101)
+		.collect::<Vec<u32>>();
+	let pixels = (0..15).map(|i| (i * 7 % 4) as u8).collect::<Vec<_>>();
+	let itp = Itp::new(
+		ItpRevision::V3,
+		ImageData::Indexed(
+			Palette::Embedded(pal.clone()),
+			vec![Raster::new_with(5, 3, pixels.clone())],
+		),
+	);
+	let mut data = Vec::new();
+	itp_to_dds(args, &mut data, &itp)?;
+	let dds = dds::Dds::read(&mut data.as_slice())?;
+	assert_ne!(dds.pixel_format.flags & dds::DDPF::PALETTEINDEXED2, 0);
+	assert_eq!(dds.pixel_format.bpp, 2);
+
+	let ImageData::Indexed(Palette::Embedded(pal2), mips) = dds_to_itp(args, data.as_slice())?.data
+	else {
+		eyre::bail!("expected indexed")
+	};
+	assert_eq!(pal2, pal[..4]);
+	assert_eq!(mips[0].as_slice(), pixels);
+	Ok(())
+}
+
+#[test]
+fn test_premultiply() -> eyre::Result<()> {
+	let args = &Args {
+		premultiply: true,
+		..Args::default()
+	};
+	let pixels = Raster::new_with(1, 1, vec![0x80FFFFFF]);
+	let itp = Itp::new(ItpRevision::V3, ImageData::Argb32(vec![pixels]));
+	let mut data = Vec::new();
+	itp_to_dds(args, &mut data, &itp)?;
+	let dds = dds::Dds::read(&mut data.as_slice())?;
+	let dx10 = dds.dx10.unwrap();
+	assert_eq!(
+		dx10.misc_flag2 & dds::ALPHA_MODE::MASK,
+		dds::ALPHA_MODE::PREMULTIPLIED
+	);
+
+	let ImageData::Argb32(mips) = dds_to_itp(args, data.as_slice())?.data else {
+		eyre::bail!("expected argb32")
+	};
+	assert_eq!(mips[0].as_slice(), [0x80808080]);
+	Ok(())
+}
+
+#[test]
+fn test_block_count() -> eyre::Result<()> {
+	let mut header = dds::Dds {
+		width: 4,
+		height: 4,
+		..dds::Dds::default()
+	};
+	header.pixel_format.flags |= dds::DDPF::FOURCC;
+	header.pixel_format.four_cc = *b"DXT1";
+	let mut data = Vec::new();
+	header.write(&mut data)?;
+	data.extend([0; 8]);
+	assert!(dds_to_itp(&Args::default(), data.as_slice()).is_ok());
+
+	data.extend([0; 8]);
+	let err = dds_to_itp(&Args::default(), data.as_slice()).unwrap_err();
+	assert!(err.to_string().contains("expected 8 bytes, but got 16"));
+	Ok(())
+}
+
+#[test]
+fn test_yuv() -> eyre::Result<()> {
+	let mut header = dds::Dds {
+		width: 2,
+		height: 1,
+		..dds::Dds::default()
+	};
+	header.pixel_format.flags |= dds::DDPF::FOURCC;
+	header.pixel_format.four_cc = *b"DX10";
+	header.dx10 = Some(dds::Dx10Header {
+		dxgi_format: dds::DXGI_FORMAT::AYUV,
+		..dds::Dx10Header::default()
+	});
+	let mut data = Vec::new();
+	header.write(&mut data)?;
+	data.extend([240, 90, 81, 0xFF]);
+	data.extend([128, 128, 235, 0x80]);
+	let ImageData::Argb32(mips) = dds_to_itp(&Args::default(), data.as_slice())?.data else {
+		eyre::bail!("expected argb32")
+	};
+	assert_eq!(mips[0].as_slice(), [0xFFFF0000, 0x80FFFFFF]);
+
+	header.dx10.as_mut().unwrap().dxgi_format = dds::DXGI_FORMAT::YUY2;
+	let mut data = Vec::new();
+	header.write(&mut data)?;
+	data.extend([81, 90, 16, 240]);
+	let ImageData::Argb32(mips) = dds_to_itp(&Args::default(), data.as_slice())?.data else {
+		eyre::bail!("expected argb32")
+	};
+	assert_eq!(mips[0].as_slice(), [0xFFFF0000, 0xFFB30000]);
+	Ok(())
+}
+
+#[test]
+fn test_argb16() -> eyre::Result<()> {
+	for (mode, pixels) in [
+		(Argb16Mode::Mode1, [0x0000, 0x7C00, 0x83E0, 0xFFFF]),
+		(Argb16Mode::Mode2, [0x0000, 0xF00F, 0x8F21, 0xFFFF]),
+		(Argb16Mode::Mode3, [0x0000, 0xF800, 0x07E0, 0x8010]),
+	] {
+		let pixels = Raster::new_with(2, 2, pixels.to_vec());
+		let itp = Itp::new(ItpRevision::V3, ImageData::Argb16(mode, vec![pixels]));
+		let mut data = Vec::new();
+		itp_to_dds(&Args::default(), &mut data, &itp)?;
+		let dds = dds::Dds::read(&mut data.as_slice())?;
+		assert_eq!(dds.pixel_format.bpp, 16);
+
+		// The dds reader expands everything to 32-bit, so compare against that
+		let itp2 = dds_to_itp(&Args::default(), data.as_slice())?;
+		assert_eq!(itp2.data, itp.argb16_to_argb32().unwrap().data);
+
+		let mut data2 = Vec::new();
+		itp_to_dds(&Args::default(), &mut data2, &itp2)?;
+		assert_eq!(
+			dds_to_itp(&Args::default(), data2.as_slice())?.data,
+			itp2.data
+		);
+	}
+	Ok(())
+}
+
+#[test]
+fn test_dds_argb16() -> eyre::Result<()> {
+	let mut header = dds::Dds {
+		width: 2,
+		height: 1,
+		..dds::Dds::default()
+	};
+	// As written by most tools, with no other pixel format flags
+	header.pixel_format.flags = dds::DDPF::FOURCC;
+	header.pixel_format.four_cc = *b"DX10";
+	header.dx10 = Some(dds::Dx10Header {
+		dxgi_format: dds::DXGI_FORMAT::B4G4R4A4_UNORM,
+		..dds::Dx10Header::default()
+	});
+	let mut data = Vec::new();
+	header.write(&mut data)?;
+	data.extend([0x21, 0x8F, 0x0F, 0xF0]);
+
+	let args = Args {
+		dds_argb16: true,
+		..Args::default()
+	};
+	let itp = dds_to_itp(&args, data.as_slice())?;
+	let pixels = Raster::new_with(2, 1, vec![0x8F21, 0xF00F]);
+	assert_eq!(itp.data, ImageData::Argb16(Argb16Mode::Mode2, vec![pixels]));
+
+	// Without the flag, it is expanded as before
+	let itp = dds_to_itp(&Args::default(), data.as_slice())?;
+	assert!(matches!(itp.data, ImageData::Argb32(_)));
+	Ok(())
+}
+
+#[test]
+fn test_mip_files() -> eyre::Result<()> {
+	let tmpdir = camino_tempfile::tempdir()?;
+	let path = tmpdir.path().join("a.dds");
+	let write_level = |name: &str, size: usize, color: u32| -> eyre::Result<()> {
+		let data = ImageData::Argb32(vec![Raster::splat(size, size, color)]);
+		let f = std::fs::File::create(tmpdir.path().join(name))?;
+		itp_to_dds(&Args::default(), f, &Itp::new(ItpRevision::V3, data))
+	};
+	write_level("a.dds", 4, 0xFFFF0000)?;
+	write_level("a.1.dds", 2, 0xFF00FF00)?;
+	write_level("a.2.dds", 1, 0xFF0000FF)?;
+
+	let itp = dds_files_to_itp(&Args::default(), &path)?;
+	let ImageData::Argb32(mips) = &itp.data else {
+		eyre::bail!("expected argb32")
+	};
+	assert_eq!(mips.len(), 3);
+	assert_eq!(mips[1].as_slice(), [0xFF00FF00; 4]);
+	assert_eq!(mips[2].as_slice(), [0xFF0000FF]);
+	assert_eq!(itp.status.mipmap, cradle::itp::MipmapType::Mipmap_1);
+
+	write_level("a.1.dds", 3, 0xFF00FF00)?;
+	assert!(dds_files_to_itp(&Args::default(), &path).is_err());
+
+	// The first file must not have mipmaps of its own
+	write_level("a.1.dds", 2, 0xFF00FF00)?;
+	let mips = vec![Raster::splat(4, 4, 0xFFFF0000u32), Raster::splat(2, 2, 0)];
+	let itp = Itp::new(ItpRevision::V3, ImageData::Argb32(mips));
+	itp_to_dds(&Args::default(), std::fs::File::create(&path)?, &itp)?;
+	assert!(dds_files_to_itp(&Args::default(), &path).is_err());
+	Ok(())
+}
+
+#[test]
+fn test_alpha_only() -> eyre::Result<()> {
+	let header = dds::Dds {
+		width: 2,
+		height: 2,
+		pixel_format: dds::PixelFormat {
+			flags: dds::DDPF::ALPHA,
+			bpp: 8,
+			rmask: 0,
+			gmask: 0,
+			bmask: 0,
+			amask: 0xFF,
+			..dds::PixelFormat::default()
+		},
+		..dds::Dds::default()
+	};
+	let mut data = Vec::new();
+	header.write(&mut data)?;
+	data.extend([0x00, 0x80, 0xFF, 0x10]);
+	let ImageData::Argb32(mips) = dds_to_itp(&Args::default(), data.as_slice())?.data else {
+		eyre::bail!("expected argb32")
+	};
+	assert_eq!(
+		mips[0].as_slice(),
+		[0x00000000, 0x80000000, 0xFF000000, 0x10000000]
+	);
+	Ok(())
+}
+
+#[test]
+fn test_bc1_alpha() -> eyre::Result<()> {
+	let opaque = cradle_dxt::encode_bc1_solid(0xFF123456);
+	let transparent = cradle_dxt::encode_bc1_solid(0x40123456);
+	for (block, alpha) in [(opaque, false), (transparent, true)] {
+		let itp = Itp::new(
+			ItpRevision::V3,
+			ImageData::Bc1(vec![Raster::splat(1, 1, block)]),
+		);
+		let mut data = Vec::new();
+		itp_to_dds(&Args::default(), &mut data, &itp)?;
+		let header = dds::Dds::read(&mut data.as_slice())?;
+		assert_eq!(
+			header.pixel_format.flags & dds::DDPF::ALPHAPIXELS != 0,
+			alpha
+		);
+	}
+	Ok(())
+}
+
+#[test]
+fn test_keep_reserved() -> eyre::Result<()> {
+	let tmpdir = camino_tempfile::tempdir()?;
+	let path = tmpdir.path().join("a.dds");
+	let mut header = dds::Dds {
+		width: 1,
+		height: 1,
+		..dds::Dds::default()
+	};
+	header.reserved[9] = u32::from_le_bytes(*b"NVTT");
+	header.reserved[10] = 0x20008;
+	header.reserved2 = 7;
+	let mut data = Vec::new();
+	header.write(&mut data)?;
+	data.extend(0xFF123456u32.to_le_bytes());
+	std::fs::write(&path, &data)?;
+
+	let itp = dds_to_itp(&Args::default(), data.as_slice())?;
+	let args = Args {
+		dds_keep_reserved: true,
+		..Args::default()
+	};
+	itp_to_dds_file(&args, &path, &itp)?;
+	let header2 = dds::Dds::read(&mut std::fs::File::open(&path)?)?;
+	assert_eq!(header2.reserved, header.reserved);
+	assert_eq!(header2.reserved2, 7);
+
+	itp_to_dds_file(&Args::default(), &path, &itp)?;
+	let header3 = dds::Dds::read(&mut std::fs::File::open(&path)?)?;
+	assert_eq!(header3.reserved, [0; 11]);
+
+	// Nothing to keep when there is no file yet
+	let path = tmpdir.path().join("b.dds");
+	itp_to_dds_file(&args, &path, &itp)?;
+	assert_eq!(
+		dds::Dds::read(&mut std::fs::File::open(&path)?)?.reserved,
+		[0; 11]
+	);
+	Ok(())
+}
+
+#[test]
+fn test_array() -> eyre::Result<()> {
+	let mut header = dds::Dds {
+		width: 1,
+		height: 1,
+		..dds::Dds::default()
+	};
+	header.pixel_format.flags |= dds::DDPF::FOURCC;
+	header.pixel_format.four_cc = *b"DX10";
+	header.dx10 = Some(dds::Dx10Header {
+		dxgi_format: dds::DXGI_FORMAT::B8G8R8A8_UNORM,
+		array_size: 3,
+		..dds::Dx10Header::default()
+	});
+	let mut data = Vec::new();
+	header.write(&mut data)?;
+	for argb in [0xFF0000FFu32, 0xFF00FF00, 0xFFFF0000] {
+		data.extend(argb.to_le_bytes());
+	}
+
+	let itps = dds_to_itps(&Args::default(), data.as_slice())?;
+	assert_eq!(itps.len(), 3);
+	for (itp, argb) in itps.iter().zip([0xFF0000FF, 0xFF00FF00, 0xFFFF0000]) {
+		assert_eq!(
+			itp.data,
+			ImageData::Argb32(vec![Raster::new_with(1, 1, vec![argb])])
+		);
+	}
+	assert!(dds_to_itp(&Args::default(), data.as_slice()).is_err());
+
+	let itc = crate::itc::from_itps(&Args::default(), itps)?;
+	assert_eq!(itc.frames().count(), 3);
+	assert_eq!(itc.frames[2].order, 2);
+	Ok(())
+}
+
+#[test]
+fn test_write_data() -> eyre::Result<()> {
+	let data = [
+		Raster::new_with(4, 2, (0..8u32).map(|i| i * 0x01020304).collect()),
+		Raster::new_with(2, 1, vec![0xDEADBEEF, 0x12345678]),
+	];
+	let expected = data
+		.iter()
+		.flat_map(|a| a.as_slice())
+		.flat_map(|a| a.to_le_bytes())
+		.collect::<Vec<_>>();
+	let mut out = Vec::new();
+	write_data(&mut out, &data, u32::to_le_bytes)?;
+	assert_eq!(out, expected);
+	Ok(())
+}
+
+#[test]
+fn test_dds_format() -> eyre::Result<()> {
+	// 16 bytes is one BC2/BC3/BC7 block, but labeled as two BC1 blocks
+	let mut header = dds::Dds {
+		width: 8,
+		height: 4,
+		..dds::Dds::default()
+	};
+	header.pixel_format.flags |= dds::DDPF::FOURCC;
+	header.pixel_format.four_cc = *b"DXT1";
+	let mut data = Vec::new();
+	header.write(&mut data)?;
+	header.width = 4;
+	let mut data4 = Vec::new();
+	header.write(&mut data4)?;
+	let block = [
+		0xFFu8, 0x00, 0, 0, 0, 0, 0, 0, 0x1F, 0x00, 0x00, 0xF8, 0, 0, 0, 0,
+	];
+	data.extend(block);
+	data4.extend(block);
+
+	assert!(matches!(
+		dds_to_itp(&Args::default(), data.as_slice())?.data,
+		ImageData::Bc1(_)
+	));
+
+	let args = &Args {
+		dds_format: Some(DdsFormat::Bc3),
+		..Args::default()
+	};
+	assert!(dds_to_itp(args, data.as_slice()).is_err());
+	let ImageData::Bc3(mips) = dds_to_itp(args, data4.as_slice())?.data else {
+		eyre::bail!("expected bc3")
+	};
+	assert_eq!(mips[0].as_slice(), [u128::from_le_bytes(block)]);
+	Ok(())
+}
+
+#[test]
+fn test_rgb9e5() -> eyre::Result<()> {
+	let mut header = dds::Dds {
+		width: 2,
+		height: 1,
+		..dds::Dds::default()
+	};
+	header.pixel_format.flags |= dds::DDPF::FOURCC;
+	header.pixel_format.four_cc = *b"DX10";
+	header.dx10 = Some(dds::Dx10Header {
+		dxgi_format: dds::DXGI_FORMAT::R9G9B9E5_SHAREDEXP,
+		..dds::Dx10Header::default()
+	});
+	let mut data = Vec::new();
+	header.write(&mut data)?;
+	data.extend((16u32 << 27 | 256).to_le_bytes());
+	data.extend((17u32 << 27 | 256 << 18 | 64 << 9).to_le_bytes());
+	let ImageData::Argb32(mips) = dds_to_itp(&Args::default(), data.as_slice())?.data else {
+		eyre::bail!("expected argb32")
+	};
+	assert_eq!(mips[0].as_slice(), [0xFFFF0000, 0xFF00BCFF]);
+	Ok(())
+}
+
+#[test]
+fn test_zero_size() -> eyre::Result<()> {
+	let mut header = dds::Dds {
+		width: 0,
+		height: 4,
+		pitch: 64,
+		..dds::Dds::default()
+	};
+	header.flags |= dds::DDSD::LINEARSIZE;
+	header.pixel_format.flags |= dds::DDPF::FOURCC;
+	header.pixel_format.four_cc = *b"DXT1";
+	let mut data = Vec::new();
+	header.write(&mut data)?;
+	data.extend([0; 64]);
+	let err = dds_to_itp(&Args::default(), data.as_slice()).unwrap_err();
+	assert!(err.to_string().contains("size of 0×4"));
+	assert!(err.to_string().contains("--dds-format"));
+	Ok(())
+}
@@ -0,0 +1,249 @@
+use std::io::Write;
+
+use cradle::{
+	itp::{ImageData, Itp},
+	raster::Raster,
+};
+
+const IDENTIFIER: [u8; 12] = *b"\xABKTX 20\xBB\r\n\x1A\n";
+
+mod vk {
+	pub const B8G8R8A8_UNORM: u32 = 44;
+	pub const BC1_RGBA_UNORM_BLOCK: u32 = 133;
+	pub const BC2_UNORM_BLOCK: u32 = 135;
+	pub const BC3_UNORM_BLOCK: u32 = 137;
+	pub const BC7_UNORM_BLOCK: u32 = 145;
+}
+
+/// Khronos data format color models, and channel ids within them.
+mod df {
+	pub const MODEL_RGBSDA: u8 = 1;
+	pub const MODEL_BC1A: u8 = 128;
+	pub const MODEL_BC2: u8 = 129;
+	pub const MODEL_BC3: u8 = 130;
+	pub const MODEL_BC7: u8 = 134;
+
+	pub const CHANNEL_R: u8 = 0;
+	pub const CHANNEL_G: u8 = 1;
+	pub const CHANNEL_B: u8 = 2;
+	pub const CHANNEL_COLOR: u8 = 0;
+	pub const CHANNEL_BC1A_ALPHA_PRESENT: u8 = 1;
+	pub const CHANNEL_ALPHA: u8 = 15;
+}
+
+/// Describes where a channel is within a texel block: bit offset, bit length, and channel id.
+type Sample = (u16, u8, u8);
+
+struct Format {
+	vk_format: u32,
+	color_model: u8,
+	/// Texel block size, in pixels.
+	block: u8,
+	/// Texel block size, in bytes.
+	bytes: u8,
+	/// The sampleUpper of each sample: the maximum channel value for uncompressed formats, or
+	/// all ones for block-compressed ones.
+	upper: u32,
+	samples: &'static [Sample],
+}
+
+/// Writes the image as a KTX2 container, without supercompression.
+///
+/// Only block-compressed and 32-bit images are supported, since other formats would need
+/// converting anyway; use png for those.
+pub fn itp_to_ktx2(mut write: impl Write, itp: &Itp) -> eyre::Result<()> {
+	use df::*;
+	let (format, levels) = match &itp.data {
+		ImageData::Argb32(data) => (
+			Format {
+				vk_format: vk::B8G8R8A8_UNORM,
+				color_model: MODEL_RGBSDA,
+				block: 1,
+				bytes: 4,
+				upper: 255,
+				samples: &[
+					(0, 8, CHANNEL_B),
+					(8, 8, CHANNEL_G),
+					(16, 8, CHANNEL_R),
+					(24, 8, CHANNEL_ALPHA),
+				],
+			},
+			levels(data, u32::to_le_bytes),
+		),
+		ImageData::Bc1(data) => (
+			Format {
+				vk_format: vk::BC1_RGBA_UNORM_BLOCK,
+				color_model: MODEL_BC1A,
+				block: 4,
+				bytes: 8,
+				upper: u32::MAX,
+				samples: &[(0, 64, CHANNEL_BC1A_ALPHA_PRESENT)],
+			},
+			levels(data, u64::to_le_bytes),
+		),
+		ImageData::Bc2(data) => (
+			Format {
+				vk_format: vk::BC2_UNORM_BLOCK,
+				color_model: MODEL_BC2,
+				block: 4,
+				bytes: 16,
+				upper: u32::MAX,
+				samples: &[(0, 64, CHANNEL_ALPHA), (64, 64, CHANNEL_COLOR)],
+			},
+			levels(data, u128::to_le_bytes),
+		),
+		ImageData::Bc3(data) => (
+			Format {
+				vk_format: vk::BC3_UNORM_BLOCK,
+				color_model: MODEL_BC3,
+				block: 4,
+				bytes: 16,
+				upper: u32::MAX,
+				samples: &[(0, 64, CHANNEL_ALPHA), (64, 64, CHANNEL_COLOR)],
+			},
+			levels(data, u128::to_le_bytes),
+		),
+		ImageData::Bc7(data) => (
+			Format {
+				vk_format: vk::BC7_UNORM_BLOCK,
+				color_model: MODEL_BC7,
+				block: 4,
+				bytes: 16,
+				upper: u32::MAX,
+				samples: &[(0, 128, CHANNEL_COLOR)],
+			},
+			levels(data, u128::to_le_bytes),
+		),
+		_ => eyre::bail!("ktx2 export only supports argb32 and block-compressed images"),
+	};
+
+	let dfd = data_format_descriptor(&format);
+	let index_end = IDENTIFIER.len() + 9 * 4 + 4 * 4 + 2 * 8;
+	let dfd_offset = index_end + levels.len() * 3 * 8;
+	let data_start = dfd_offset + dfd.len();
+
+	// Mip data is stored smallest first, each level aligned to the block size
+	let align = format.bytes.max(4) as usize;
+	let mut offsets = vec![0; levels.len()];
+	let mut pos = data_start;
+	for (i, level) in levels.iter().enumerate().rev() {
+		pos = pos.next_multiple_of(align);
+		offsets[i] = pos;
+		pos += level.len();
+	}
+
+	let mut f = Vec::with_capacity(pos);
+	f.extend(IDENTIFIER);
+	for v in [
+		format.vk_format,
+		1, // typeSize
+		itp.data.width() as u32,
+		itp.data.height() as u32,
+		0, // pixelDepth
+		0, // layerCount
+		1, // faceCount
+		levels.len() as u32,
+		0, // supercompressionScheme
+	] {
+		f.extend(v.to_le_bytes());
+	}
+	f.extend((dfd_offset as u32).to_le_bytes());
+	f.extend((dfd.len() as u32).to_le_bytes());
+	f.extend(0u32.to_le_bytes()); // kvdByteOffset
+	f.extend(0u32.to_le_bytes()); // kvdByteLength
+	f.extend(0u64.to_le_bytes()); // sgdByteOffset
+	f.extend(0u64.to_le_bytes()); // sgdByteLength
+	for (level, offset) in levels.iter().zip(&offsets) {
+		f.extend((*offset as u64).to_le_bytes());
+		f.extend((level.len() as u64).to_le_bytes());
+		f.extend((level.len() as u64).to_le_bytes());
+	}
+	f.extend(dfd);
+	for (level, offset) in levels.iter().zip(&offsets).rev() {
+		f.resize(*offset, 0);
+		f.extend(level);
+	}
+
+	write.write_all(&f)?;
+	Ok(())
+}
+
+fn levels<T: Copy, const N: usize>(
+	data: &[Raster<T>],
+	to_le_bytes: fn(T) -> [u8; N],
+) -> Vec<Vec<u8>> {
+	data.iter()
+		.map(|level| {
+			level
+				.as_slice()
+				.iter()
+				.flat_map(|a| to_le_bytes(*a))
+				.collect()
+		})
+		.collect()
+}
+
+/// Builds a data format descriptor with a single basic descriptor block.
+fn data_format_descriptor(format: &Format) -> Vec<u8> {
+	let block_size = 24 + 16 * format.samples.len();
+	let mut f = Vec::with_capacity(4 + block_size);
+	f.extend((4 + block_size as u32).to_le_bytes());
+	f.extend(0u32.to_le_bytes()); // vendorId and descriptorType
+	f.extend(2u16.to_le_bytes()); // versionNumber
+	f.extend((block_size as u16).to_le_bytes());
+	f.push(format.color_model);
+	f.push(1); // colorPrimaries: BT.709
+	f.push(1); // transferFunction: linear
+	f.push(0); // flags: straight alpha
+	f.extend([format.block - 1, format.block - 1, 0, 0]);
+	f.extend([format.bytes, 0, 0, 0, 0, 0, 0, 0]);
+	for &(offset, length, channel) in format.samples {
+		f.extend(offset.to_le_bytes());
+		f.push(length - 1);
+		f.push(channel);
+		f.extend([0; 4]); // samplePosition
+		f.extend(0u32.to_le_bytes()); // sampleLower
+		f.extend(format.upper.to_le_bytes()); // sampleUpper
+	}
+	f
+}
+
+#[test]
+fn test_ktx2() -> eyre::Result<()> {
+	use cradle::itp::ItpRevision;
+	let u32_at = |f: &[u8], pos: usize| u32::from_le_bytes(f[pos..pos + 4].try_into().unwrap());
+	let u64_at = |f: &[u8], pos: usize| u64::from_le_bytes(f[pos..pos + 8].try_into().unwrap());
+
+	let mips = vec![Raster::splat(2, 2, 1u128), Raster::splat(1, 1, 2u128)];
+	let itp = Itp::new(ItpRevision::V3, ImageData::Bc7(mips));
+	let mut f = Vec::new();
+	itp_to_ktx2(&mut f, &itp)?;
+
+	assert_eq!(f[..12], IDENTIFIER);
+	assert_eq!(u32_at(&f, 12), vk::BC7_UNORM_BLOCK);
+	assert_eq!((u32_at(&f, 20), u32_at(&f, 24)), (8, 8));
+	assert_eq!(u32_at(&f, 40), 2);
+
+	let dfd = u32_at(&f, 48) as usize;
+	assert_eq!(f[dfd + 12], df::MODEL_BC7);
+	assert_eq!(u32_at(&f, dfd + 28 + 12), u32::MAX);
+
+	// The first level is the largest, but is stored last
+	let (off0, len0) = (u64_at(&f, 80) as usize, u64_at(&f, 88) as usize);
+	let (off1, len1) = (u64_at(&f, 104) as usize, u64_at(&f, 112) as usize);
+	assert_eq!((len0, len1), (64, 16));
+	assert!(off1 < off0);
+	assert_eq!(off0 % 16, 0);
+	assert_eq!(f[off0..off0 + 16], 1u128.to_le_bytes());
+	assert_eq!(f[off1..off1 + 16], 2u128.to_le_bytes());
+	assert_eq!(off0 + len0, f.len());
+
+	let itp = Itp::new(ItpRevision::V3, ImageData::Argb32(vec![Raster::new(3, 3)]));
+	let mut f = Vec::new();
+	itp_to_ktx2(&mut f, &itp)?;
+	assert_eq!(u32_at(&f, 12), vk::B8G8R8A8_UNORM);
+	assert_eq!(u32_at(&f, 40), 1);
+	let dfd = u32_at(&f, 48) as usize;
+	assert_eq!(u32_at(&f, dfd + 28 + 12), 255);
+	Ok(())
+}
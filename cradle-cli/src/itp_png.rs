@@ -8,28 +8,67 @@ use crate::Args;
 
 pub fn itp_to_png(args: &Args, itp: &Itp) -> eyre::Result<png::Png> {
 	use ImageData as ID;
-	Ok(match &itp.data {
+	let mut png = match &itp.data {
 		ID::Indexed(pal, data) => {
-			let pal = match pal {
-				Palette::Embedded(pal) => pal,
-				Palette::External(_) => eyre::bail!("external palette is not currently supported"),
+			let Some(pal) = pal.colors() else {
+				eyre::bail!("external palette is not currently supported")
 			};
 			if args.png_no_palette {
 				Png::Argb32(map(args, data, |i| i.map(|a| pal[*a as usize])))
 			} else {
-				Png::Indexed(pal.clone(), map(args, data, |i| i.clone()))
+				Png::Indexed(pal.to_vec(), map(args, data, |i| i.clone()))
 			}
 		}
-		ID::Argb16(_, _) => eyre::bail!("16-bit color is not currently supported"),
+		ID::Argb16(mode, data) => Png::Argb32(map(args, data, |i| {
+			i.map(|a| cradle::color::unpack_argb16(*mode, *a))
+		})),
 		ID::Argb32(data) => Png::Argb32(map(args, data, |i| i.clone())),
-		ID::Bc1(data) => Png::Argb32(map(args, data, |i| decode(i, cradle_dxt::decode_bc1))),
-		ID::Bc2(data) => Png::Argb32(map(args, data, |i| decode(i, cradle_dxt::decode_bc2))),
-		ID::Bc3(data) => Png::Argb32(map(args, data, |i| decode(i, cradle_dxt::decode_bc3))),
-		ID::Bc7(data) => Png::Argb32(map(args, data, |i| decode(i, cradle_dxt::decode_bc7))),
-	})
+		ID::Bc1(data) => Png::Argb32(try_map(args, data, |i| {
+			decode(args, i, cradle_dxt::decode_bc1)
+		})?),
+		ID::Bc2(data) => Png::Argb32(try_map(args, data, |i| {
+			decode(args, i, cradle_dxt::decode_bc2)
+		})?),
+		ID::Bc3(data) => Png::Argb32(try_map(args, data, |i| {
+			decode(args, i, cradle_dxt::decode_bc3)
+		})?),
+		ID::Bc7(data) => Png::Argb32(try_map(args, data, |i| {
+			decode(args, i, cradle_dxt::decode_bc7)
+		})?),
+	};
+	if let Some(rotation) = args.rotate {
+		png.rotate(rotation);
+	}
+	if args.premultiply {
+		png.premultiply();
+	}
+	if let Some(channel) = args.channel {
+		png = png.channel(channel.index());
+	}
+	Ok(force_rgba(args, png))
 }
 
+/// Expands indexed and grayscale images to 32-bit color, if `--force-rgba` is given.
+pub fn force_rgba(args: &Args, png: Png) -> Png {
+	match png {
+		Png::Indexed(..) | Png::Gray(_) if args.force_rgba => Png::Argb32(png.to_argb32()),
+		png => png,
+	}
+}
+
+/// Converts a png to an itp with 32-bit or indexed color.
+///
+/// Images that were originally 16-bit are not converted back, since that would be lossy.
 pub fn png_to_itp(args: &Args, png: &png::Png) -> Itp {
+	let rotated;
+	let png = if let Some(rotation) = args.rotate {
+		let mut png = png.clone();
+		png.rotate(rotation);
+		rotated = png;
+		&rotated
+	} else {
+		png
+	};
 	let data = match png {
 		Png::Argb32(data) => ImageData::Argb32(map(args, data, |i| i.clone())),
 		Png::Indexed(pal, data) if args.png_no_palette => ImageData::Argb32(map(args, data, |i| {
@@ -39,26 +78,127 @@ pub fn png_to_itp(args: &Args, png: &png::Png) -> Itp {
 			Palette::Embedded(pal.clone()),
 			map(args, data, |i| i.clone()),
 		),
+		Png::Gray(_) => ImageData::Argb32(map(args, &png.to_argb32(), |i| i.clone())),
 	};
 	Itp::new(ItpRevision::V3, data)
 }
 
 fn map<T, U>(
 	args: &Args,
-	mut data: &[Raster<T>],
+	data: &[Raster<T>],
 	f: impl FnMut(&Raster<T>) -> Raster<U>,
 ) -> Vec<Raster<U>> {
+	mips(args, data).iter().map(f).collect()
+}
+
+fn try_map<T, U>(
+	args: &Args,
+	data: &[Raster<T>],
+	f: impl FnMut(&Raster<T>) -> eyre::Result<Raster<U>>,
+) -> eyre::Result<Vec<Raster<U>>> {
+	mips(args, data).iter().map(f).collect()
+}
+
+fn mips<'a, T>(args: &Args, data: &'a [Raster<T>]) -> &'a [Raster<T>] {
 	if data.len() > 1 && !args.png_mipmap {
 		tracing::warn!("discarding mipmaps");
-		data = &data[..1];
+		&data[..1]
+	} else {
+		data
 	}
-	data.iter().map(f).collect()
 }
 
-fn decode<T: Copy>(r: &Raster<T>, f: impl FnMut(T) -> [u32; 16]) -> Raster<u32> {
+fn decode<T: Copy>(
+	args: &Args,
+	r: &Raster<T>,
+	f: impl FnMut(T) -> [u32; 16],
+) -> eyre::Result<Raster<u32>> {
 	let mut data = r.as_slice().iter().copied().flat_map(f).collect::<Vec<_>>();
-	cradle::permute::unswizzle(&mut data, r.height() * 4, r.width() * 4, 4, 4);
-	Raster::new_with(r.width() * 4, r.height() * 4, data)
+	if !args.no_swizzle {
+		cradle::permute::try_unswizzle(&mut data, r.height() * 4, r.width() * 4, 4, 4)?;
+	}
+	Ok(Raster::new_with(r.width() * 4, r.height() * 4, data))
+}
+
+#[test]
+fn test_no_swizzle() -> eyre::Result<()> {
+	let blocks = Raster::new_with(2, 1, vec![0u32, 1]);
+	let args = Args {
+		no_swizzle: true,
+		..Args::default()
+	};
+	let raw = decode(&args, &blocks, |b| [b; 16])?;
+	assert_eq!(raw.as_slice()[..16], [0; 16]);
+	assert_eq!(raw.as_slice()[16..], [1; 16]);
+
+	let pixels = decode(&Args::default(), &blocks, |b| [b; 16])?;
+	assert_eq!(pixels.as_slice()[..8], [0, 0, 0, 0, 1, 1, 1, 1]);
+	Ok(())
+}
+
+#[test]
+fn test_channel() -> eyre::Result<()> {
+	let pixels = Raster::new_with(3, 1, vec![0x11223344, 0x80FFFFFF, 0xFF000000]);
+	let itp = Itp::new(ItpRevision::V3, ImageData::Argb32(vec![pixels]));
+	let args = &Args {
+		channel: Some(crate::Channel::A),
+		..Args::default()
+	};
+	let png = itp_to_png(args, &itp)?;
+	assert_eq!(
+		png,
+		Png::Gray(vec![Raster::new_with(3, 1, vec![0x11, 0x80, 0xFF])])
+	);
+
+	let mut data = Vec::new();
+	png::write(&mut data, &png)?;
+	let decoder = ::png::Decoder::new(data.as_slice()).read_info()?;
+	assert_eq!(decoder.info().color_type, ::png::ColorType::Grayscale);
+	Ok(())
+}
+
+#[test]
+fn test_force_rgba() -> eyre::Result<()> {
+	let pal = Palette::Embedded(vec![0xFF000000, 0x80FF0000]);
+	let pixels = Raster::new_with(2, 1, vec![1, 0]);
+	let itp = Itp::new(ItpRevision::V3, ImageData::Indexed(pal, vec![pixels]));
+	let args = &Args {
+		force_rgba: true,
+		..Args::default()
+	};
+	let png = itp_to_png(args, &itp)?;
+	assert_eq!(
+		png,
+		Png::Argb32(vec![Raster::new_with(2, 1, vec![0x80FF0000, 0xFF000000])])
+	);
+
+	let mut data = Vec::new();
+	png::write(&mut data, &png)?;
+	let decoder = ::png::Decoder::new(data.as_slice()).read_info()?;
+	assert_eq!(decoder.info().color_type, ::png::ColorType::Rgba);
+
+	assert!(matches!(
+		itp_to_png(&Args::default(), &itp)?,
+		Png::Indexed(..)
+	));
+	Ok(())
+}
+
+#[test]
+fn test_argb16() -> eyre::Result<()> {
+	use cradle::itp::Argb16Mode;
+	// None of the samples are 16-bit, so build one in memory and round-trip it through the file format
+	let pixels = Raster::new_with(2, 1, vec![0x8F21, 0xF00F]);
+	let itp = Itp::new(
+		ItpRevision::V1,
+		ImageData::Argb16(Argb16Mode::Mode2, vec![pixels]),
+	);
+	let itp = cradle::itp::read(&cradle::itp::write(&itp)?)?;
+	let Png::Argb32(data) = itp_to_png(&Args::default(), &itp)? else {
+		eyre::bail!("expected argb32")
+	};
+	assert_eq!(data[0].as_slice(), [0x88FF2211, 0xFF0000FF]);
+	Ok(())
 }
 
 #[cfg(test)]
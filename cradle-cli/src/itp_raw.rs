@@ -0,0 +1,139 @@
+use camino::{Utf8Path, Utf8PathBuf};
+
+use crate::{util::Output, Args};
+
+/// The chunks of a revision 3 itp, exactly as they were in the file.
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct RawSpec {
+	chunks: Vec<RawChunk>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+struct RawChunk {
+	/// For reference only; the fourcc that is written is the one in `head`.
+	fourcc: String,
+	/// The bytes of the chunk, up to the payload if that is in `path`.
+	#[serde(with = "hex")]
+	head: Vec<u8>,
+	#[serde(default, skip_serializing_if = "Option::is_none")]
+	path: Option<Utf8PathBuf>,
+}
+
+/// Both IDAT and IPAL have a 16-byte header before their, usually compressed, payload.
+const PAYLOAD_START: usize = 16;
+
+/// Writes each chunk of an itp without decoding it, so this works even if the payloads are broken.
+///
+/// IDAT and IPAL payloads are written to their own files, and everything else to a json file that
+/// [`assemble`] turns back into the identical itp.
+pub fn extract(args: &Args, data: &[u8], output: Output) -> eyre::Result<Utf8PathBuf> {
+	let ranges = cradle::itp::chunk_ranges(data)?;
+	eyre::ensure!(!ranges.is_empty(), "only revision 3 itps have chunks");
+	let json_out = output.with_extension("raw.json");
+	let stem = json_out.file_name().unwrap_or_default();
+	let stem = stem.split_once('.').map_or(stem, |a| a.0);
+
+	let mut chunks = Vec::new();
+	for range in ranges {
+		let chunk = &data[range];
+		let fourcc = &chunk[..4];
+		let name = match fourcc {
+			b"IDAT" => {
+				let mip = u16::from_le_bytes([chunk[14], chunk[15]]);
+				Some(format!("{stem}.mip{mip}.idat"))
+			}
+			b"IPAL" => Some(format!("{stem}.ipal")),
+			_ => None,
+		};
+		let (head, path) = match name {
+			Some(name) if chunk.len() >= PAYLOAD_START => {
+				let path = json_out.with_file_name(&name);
+				crate::util::write_file(args, &path, &chunk[PAYLOAD_START..])?;
+				(&chunk[..PAYLOAD_START], Some(Utf8PathBuf::from(name)))
+			}
+			_ => (chunk, None),
+		};
+		chunks.push(RawChunk {
+			fourcc: String::from_utf8_lossy(fourcc).into_owned(),
+			head: head.to_vec(),
+			path,
+		});
+	}
+
+	crate::Spec::write(
+		args,
+		&json_out,
+		crate::util::MyFormatter::new(2),
+		RawSpec { chunks },
+	)?;
+	Ok(json_out)
+}
+
+/// Puts the chunks back together, with payload paths relative to `dir`.
+pub fn assemble(spec: &RawSpec, dir: &Utf8Path) -> eyre::Result<Vec<u8>> {
+	let mut f = b"ITP\xFF".to_vec();
+	for chunk in &spec.chunks {
+		f.extend(&chunk.head);
+		if let Some(path) = &chunk.path {
+			f.extend(std::fs::read(dir.join(path))?);
+		}
+	}
+	Ok(f)
+}
+
+mod hex {
+	use serde::{de::Error, Deserialize, Deserializer, Serializer};
+
+	pub fn serialize<S: Serializer>(bytes: &[u8], s: S) -> Result<S::Ok, S::Error> {
+		s.collect_str(&bytes.iter().map(|b| format!("{b:02X}")).collect::<String>())
+	}
+
+	pub fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Vec<u8>, D::Error> {
+		let s = String::deserialize(d)?;
+		(0..s.len())
+			.step_by(2)
+			.map(|i| {
+				s.get(i..i + 2)
+					.and_then(|a| u8::from_str_radix(a, 16).ok())
+					.ok_or_else(|| D::Error::custom(format!("invalid hex: {s}")))
+			})
+			.collect()
+	}
+}
+
+#[test]
+fn test_extract_raw() -> eyre::Result<()> {
+	let tmpdir = camino_tempfile::tempdir()?;
+	for bytes in [
+		&include_bytes!("../../samples/itp/nayuta__m_record.itp")[..],
+		&include_bytes!("../../samples/itp/ao__extra020.itp")[..],
+	] {
+		let output = Output::In(tmpdir.path().join("a.itp"));
+		let json = extract(&Args::default(), bytes, output)?;
+		assert_eq!(json, tmpdir.path().join("a.raw.json"));
+		assert!(tmpdir.path().join("a.mip0.idat").exists());
+
+		let crate::Spec::Raw(spec) = serde_json::from_reader(std::fs::File::open(&json)?)? else {
+			eyre::bail!("expected a raw spec")
+		};
+		assert_eq!(assemble(&spec, tmpdir.path())?, bytes);
+	}
+	assert!(tmpdir.path().join("a.ipal").exists());
+
+	// Only the chunk headers need to make sense
+	let mut bytes = include_bytes!("../../samples/itp/ao__extra020.itp").to_vec();
+	let pos = bytes.windows(4).position(|a| a == b"IDAT").unwrap();
+	bytes[pos + PAYLOAD_START..pos + PAYLOAD_START + 16].fill(0xFF);
+	assert!(cradle::itp::read(&bytes).is_err());
+	let output = Output::In(tmpdir.path().join("c.itp"));
+	let json = extract(&Args::default(), &bytes, output)?;
+	let crate::Spec::Raw(spec) = serde_json::from_reader(std::fs::File::open(&json)?)? else {
+		eyre::bail!("expected a raw spec")
+	};
+	assert_eq!(assemble(&spec, tmpdir.path())?, bytes);
+
+	let bytes = include_bytes!("../../samples/itp/3rd_evo__ea.itp");
+	let output = Output::In(tmpdir.path().join("b.itp"));
+	assert!(extract(&Args::default(), bytes, output).is_err());
+	Ok(())
+}
@@ -6,19 +6,47 @@ use clap::ValueHint;
 use eyre_span::emit;
 use strict_result::*;
 
+mod cache;
+mod formats;
+mod gif;
+mod info;
 mod itc;
 mod itp_dds;
+mod itp_ktx2;
 mod itp_png;
+mod itp_raw;
 mod png;
+#[cfg(test)]
+mod test_util;
 mod util;
+mod validate;
 
 #[derive(Debug, Clone, Parser)]
-#[command(arg_required_else_help = true)]
+#[command(arg_required_else_help = true, subcommand_negates_reqs = true)]
 struct Cli {
+	#[command(subcommand)]
+	command: Option<Command>,
+
 	/// Where to place resulting files (default is same directory as inputs)
 	#[clap(long, short, value_hint = ValueHint::DirPath)]
 	output: Option<Utf8PathBuf>,
 
+	/// File name pattern for the resulting files, such as `{stem}_converted.{ext}`
+	///
+	/// `{stem}` is the input name without extension, `{ext}` is the output extension, and
+	/// `{index}` is the position of the input on the command line. Not used when --output names a
+	/// single file.
+	#[clap(long)]
+	name_template: Option<util::NameTemplate>,
+
+	/// Only print errors
+	#[clap(long, short, global = true)]
+	quiet: bool,
+
+	/// Print log messages as JSON lines, for consumption by other tools
+	#[clap(long, global = true)]
+	log_json: bool,
+
 	#[clap(flatten)]
 	args: Args,
 
@@ -27,16 +55,53 @@ struct Cli {
 	file: Vec<Utf8PathBuf>,
 }
 
+#[derive(Debug, Clone, clap::Subcommand)]
+enum Command {
+	/// Check that every itp and itc file in the given directories can be read and written back
+	///
+	/// Prints each failure with its reason, and a summary at the end.
+	Validate {
+		/// The files or directories to check
+		#[clap(value_hint = ValueHint::AnyPath, required = true)]
+		path: Vec<Utf8PathBuf>,
+	},
+
+	/// Print a short summary of each file's format, dimensions, and other details
+	///
+	/// Supports itp, itc, dds, and png files.
+	Info {
+		/// The files to describe
+		#[clap(value_hint = ValueHint::FilePath, required = true)]
+		path: Vec<Utf8PathBuf>,
+	},
+
+	/// List the supported file types, itp revisions, and pixel formats
+	ListFormats,
+}
+
 #[derive(Debug, Clone, Default, clap::Args)]
 struct Args {
 	/// Convert images to dds instead of png
 	#[clap(long)]
 	dds: bool,
 
+	/// Convert images to ktx2 instead of png
+	///
+	/// Only block-compressed and 32-bit images are supported.
+	#[clap(long, conflicts_with = "dds")]
+	ktx2: bool,
+
 	/// When extracting itc, do not convert the individual images
 	#[clap(long)]
 	itp: bool,
 
+	/// When extracting itc, write the frames as loose itp files next to a csv file
+	///
+	/// The csv lists each frame's offset and scale in playback order, and can be converted back
+	/// into an itc. Any palette is written as name.itc.pal.
+	#[clap(long)]
+	flat: bool,
+
 	/// When extracting itc, do not create a subdirectory
 	///
 	/// Normally the converted files will be placed at ch00000/index.json and ch00000/0.png,
@@ -44,16 +109,141 @@ struct Args {
 	#[clap(long)]
 	no_dir: bool,
 
+	/// Read dds files as this format, regardless of what the header says
+	///
+	/// The dimensions and mipmap count are still taken from the header.
+	#[clap(long, value_enum)]
+	dds_format: Option<DdsFormat>,
+
+	/// Keep 16-bit dds images as 16-bit itp, instead of expanding them to 32-bit
+	///
+	/// This only applies to the layouts itp can store: B5G5R5A1, B4G4R4A4, and B5G6R5.
+	#[clap(long, conflicts_with = "argb16")]
+	dds_argb16: bool,
+
+	/// When converting name.dds to itp, read further mip levels from name.1.dds, name.2.dds, etc.
+	#[clap(long)]
+	dds_mip_files: bool,
+
+	/// When writing a dds file over an existing one, keep its reserved header fields
+	///
+	/// Some tools store their own data there, which is otherwise lost when going through itp.
+	#[clap(long)]
+	dds_keep_reserved: bool,
+
+	/// Convert dds texture arrays to itc, with one frame per array slice
+	#[clap(long)]
+	dds_array: bool,
+
 	/// Do not read or write indexed images from png files
 	#[clap(long)]
 	png_no_palette: bool,
 
+	/// Always write png files as 32-bit RGBA
+	///
+	/// Indexed and grayscale images are expanded, including itc frames and split planes. This is
+	/// for tools that cannot read other kinds of png.
+	#[clap(long)]
+	force_rgba: bool,
+
+	/// Embed this ICC color profile in png files that are written
+	#[clap(long, value_hint = ValueHint::FilePath)]
+	icc: Option<Utf8PathBuf>,
+
 	/// Read and write mipmaps as APNG frames
 	///
 	/// This is mostly for debugging purposes.
 	#[clap(long)]
 	png_mipmap: bool,
 
+	/// Do not rearrange decoded BC blocks into pixel order when writing png
+	///
+	/// Each 4×4 block is instead written as 16 consecutive pixels, in the order they are stored,
+	/// which makes the image look scrambled. This is mostly for debugging purposes.
+	#[clap(long)]
+	no_swizzle: bool,
+
+	/// Rotate images clockwise after decoding, before encoding
+	///
+	/// Only supported for uncompressed images when writing itp or dds.
+	#[clap(long, value_enum)]
+	rotate: Option<Rotation>,
+
+	/// Write each channel as a separate grayscale png, or combine them when reading
+	///
+	/// The channels are named `.r.png`, `.g.png`, `.b.png`, and `.a.png`. When converting to itp,
+	/// give any one of them, and all four are read.
+	#[clap(long, conflicts_with = "channel")]
+	split_planes: bool,
+
+	/// Crop away transparent borders when writing png
+	///
+	/// The position of the kept region and the original size are written to a `.trim.json` file.
+	#[clap(long)]
+	trim: bool,
+
+	/// Write only the given channel, as a grayscale png
+	///
+	/// This is useful for inspecting textures that pack unrelated data into each channel.
+	#[clap(long, value_enum)]
+	channel: Option<Channel>,
+
+	/// Multiply color by alpha when writing png or dds
+	///
+	/// Some engines expect textures with premultiplied alpha. Only supported for uncompressed
+	/// images. In dds, this is marked with the premultiplied alpha mode.
+	#[clap(long)]
+	premultiply: bool,
+
+	/// When reading itp, also write each mip's decompressed pixel data to name.mipN.bin
+	///
+	/// The data is written before unswizzling. This is mostly for studying unknown pixel formats.
+	#[clap(long)]
+	dump_idat: bool,
+
+	/// When reading itp, write its chunks as they are in the file, without decoding anything
+	///
+	/// IDAT and IPAL payloads are written to separate files, and the rest to name.raw.json, which
+	/// can be converted back into an identical itp. Only revision 3 itps have chunks.
+	#[clap(long)]
+	extract_raw: bool,
+
+	/// Store 32-bit images as 16-bit color when converting to itp
+	#[clap(long, value_enum)]
+	argb16: Option<Argb16Layout>,
+
+	/// Remap colors to the nearest ones in this palette when converting to itp
+	///
+	/// The palette is taken from an indexed png or itp, or a JASC .pal file. The result is always
+	/// indexed with exactly that palette.
+	#[clap(long, value_hint = ValueHint::FilePath, conflicts_with = "argb16")]
+	palette_from: Option<Utf8PathBuf>,
+
+	/// Use Floyd–Steinberg dithering when reducing color depth, to avoid banding
+	#[clap(long)]
+	dither: bool,
+
+	/// Generate mipmaps when converting 32-bit images to itp
+	///
+	/// Inputs that already have mipmaps are rejected. This requires itp revision 3.
+	#[clap(long)]
+	mipmaps: bool,
+
+	/// How to average colors when generating mipmaps
+	///
+	/// `srgb` averages in linear light, which keeps fine detail from getting darker.
+	/// `linear` averages the stored values directly.
+	#[clap(long, value_enum, default_value_t)]
+	mip_gamma: MipGamma,
+
+	/// Replace the color of fully transparent pixels when converting 32-bit images to itp
+	///
+	/// `black` clears it, and `bleed` spreads the color of the nearest visible pixels into it.
+	/// Either compresses better than leftover colors, and `bleed` also avoids dark halos when
+	/// the texture is filtered.
+	#[clap(long, value_enum)]
+	clean_alpha: Option<CleanAlpha>,
+
 	/// Itp revision to write
 	///
 	/// Older revisions are more compatible, but cannot represent all pixel formats.
@@ -65,16 +255,225 @@ struct Args {
 	#[clap(long, value_parser = 1..=3, verbatim_doc_comment)]
 	itp_revision: Option<u16>,
 
+	/// Compression to use when writing itp
+	///
+	/// By default, small indexed images are uncompressed and everything else uses bz1, like most
+	/// game files. C77 can be read, but not written.
+	#[clap(long, value_enum)]
+	compression: Option<Compression>,
+
+	/// Whether the game should blend the image using its alpha channel, when writing itp
+	///
+	/// By default this is left unspecified. Revision 1 cannot specify it, so revision 2 is used
+	/// instead unless --itp-revision says otherwise.
+	#[clap(long)]
+	use_alpha: Option<bool>,
+
+	/// Bits per pixel used for indexed dds
+	///
+	/// `auto` picks the smallest size that fits the palette, which makes small-palette sprites
+	/// considerably smaller, but is less widely supported than 8-bit.
+	#[clap(long, value_enum, default_value_t)]
+	palette_bits: PaletteBits,
+
+	/// Rewrite itp files with the given revision, instead of converting them to png or dds
+	///
+	/// The output is placed at name.v1.itp etc.
+	#[clap(long, value_parser = 1..=3)]
+	to_revision: Option<u16>,
+
+	/// Rewrite itp files with whichever compression and format gives the smallest file
+	///
+	/// The pixels and revision are left unchanged. The output is placed at name.repack.itp.
+	#[clap(long, conflicts_with = "to_revision")]
+	repack: bool,
+
+	/// When rewriting itp files with --to-revision, store the pixels unswizzled
+	///
+	/// Images converted from png or dds are always stored unswizzled, so this only affects existing
+	/// itp files.
+	#[clap(long)]
+	force_linear: bool,
+
+	/// Leave out the IHAS chunk when rewriting itp files with --to-revision
+	///
+	/// The chunk is otherwise copied as is. Its contents cannot be recomputed, since the hash
+	/// algorithm is unknown, so it may not match the rewritten file.
+	#[clap(long)]
+	drop_hash: bool,
+
+	/// When converting itc, also write an animated png preview of the frames
+	///
+	/// The preview is placed at name.anim.png. Its speed is taken from the `fps` field of the json
+	/// file, if any.
+	#[clap(long)]
+	animate: bool,
+
+	/// Convert up to this many itc frames in parallel, when extracting or creating itc
+	///
+	/// The output is the same regardless of the number of jobs.
+	#[clap(long, short, visible_alias = "threads")]
+	jobs: Option<usize>,
+
+	/// Ignore unknown flags and pixel formats in itp headers instead of failing
+	///
+	/// The image is read as if the unknown flags were absent, and unknown pixel formats as linear,
+	/// which may or may not be correct.
+	#[clap(long)]
+	lenient: bool,
+
+	/// Fail instead of overwriting files that already exist
+	///
+	/// By default, existing files are overwritten.
+	#[clap(long)]
+	no_clobber: bool,
+
 	/// Do not pad/crop the frames to equal size
 	///
 	/// Only supported in png; --itp and --dds invalidate this.
 	#[clap(long)]
 	itc_no_pad: bool,
+
+	/// Stretch itc frames by their scale when converting to png, and shrink them back when
+	/// converting from png
+	///
+	/// This shows the frames as they are displayed, but is lossy for scales below 1.
+	#[clap(long)]
+	itc_apply_scale: bool,
+
+	/// Store identical frames only once when creating itc
+	#[clap(long)]
+	dedupe_frames: bool,
+
+	/// Reuse earlier results when converting png or dds to itp
+	///
+	/// Results are stored in this directory, keyed by the contents of the input and the other
+	/// arguments, so unchanged inputs are not converted again.
+	#[clap(long, value_hint = ValueHint::DirPath)]
+	cache_dir: Option<Utf8PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+enum PaletteBits {
+	Auto,
+	#[default]
+	#[value(name = "8")]
+	Eight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Channel {
+	R,
+	G,
+	B,
+	A,
+}
+
+impl Channel {
+	/// The channels, with the names used by `--split-planes`.
+	const PLANES: [(Channel, &'static str); 4] = [
+		(Channel::R, "r"),
+		(Channel::G, "g"),
+		(Channel::B, "b"),
+		(Channel::A, "a"),
+	];
+
+	/// Index of the channel's byte in a little-endian ARGB pixel.
+	fn index(self) -> usize {
+		match self {
+			Channel::B => 0,
+			Channel::G => 1,
+			Channel::R => 2,
+			Channel::A => 3,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum DdsFormat {
+	Bc1,
+	Bc2,
+	Bc3,
+	Bc7,
+	Argb32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Argb16Layout {
+	#[value(name = "1555")]
+	A1555,
+	#[value(name = "4444")]
+	A4444,
+	#[value(name = "565")]
+	A565,
+}
+
+impl Argb16Layout {
+	fn mode(self) -> cradle::itp::Argb16Mode {
+		use cradle::itp::Argb16Mode as A16;
+		match self {
+			Argb16Layout::A1555 => A16::Mode1,
+			Argb16Layout::A4444 => A16::Mode2,
+			Argb16Layout::A565 => A16::Mode3,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Compression {
+	None,
+	Bz1,
+	Bz2,
+}
+
+impl Compression {
+	fn compression_type(self) -> cradle::itp::CompressionType {
+		use cradle::itp::CompressionType as CT;
+		match self {
+			Compression::None => CT::None,
+			Compression::Bz1 => CT::Bz_1,
+			Compression::Bz2 => CT::Bz_2,
+		}
+	}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+enum MipGamma {
+	#[default]
+	Srgb,
+	Linear,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum CleanAlpha {
+	Black,
+	Bleed,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum Rotation {
+	#[value(name = "90")]
+	R90,
+	#[value(name = "180")]
+	R180,
+	#[value(name = "270")]
+	R270,
+}
+
+impl Rotation {
+	fn apply<T: Clone>(self, raster: &cradle::raster::Raster<T>) -> cradle::raster::Raster<T> {
+		match self {
+			Rotation::R90 => raster.rotate90(),
+			Rotation::R180 => raster.rotate180(),
+			Rotation::R270 => raster.rotate270(),
+		}
+	}
 }
 
 impl Cli {
-	fn output<'a>(&'a self, path: &'a Utf8Path) -> eyre::Result<util::Output> {
-		util::Output::from_output_flag(self.output.as_deref(), path, self.file.len())
+	fn output<'a>(&'a self, path: &'a Utf8Path, index: usize) -> eyre::Result<util::Output> {
+		let output = util::Output::from_output_flag(self.output.as_deref(), path, self.file.len())?;
+		Ok(output.with_template(self.name_template.as_ref(), index))
 	}
 }
 
@@ -82,73 +481,200 @@ impl Cli {
 #[serde(tag = "type", rename_all = "snake_case")]
 enum Spec {
 	Itc(itc::ItcSpec),
+	Raw(itp_raw::RawSpec),
 }
 
 impl Spec {
 	fn write(
+		args: &Args,
 		path: impl AsRef<Utf8Path>,
 		formatter: impl serde_json::ser::Formatter,
 		data: impl Into<Spec>,
 	) -> eyre::Result<()> {
 		use serde::Serialize;
-		let mut ser = serde_json::Serializer::with_formatter(
-			std::fs::File::create(path.as_ref())?,
-			formatter,
-		);
+		let mut ser =
+			serde_json::Serializer::with_formatter(util::create_file(args, path)?, formatter);
 		data.into().serialize(&mut ser)?;
 		Ok(())
 	}
 }
 
 fn main() -> eyre::Result<()> {
-	init_tracing()?;
 	let cli = Cli::parse();
+	init_tracing(&cli)?;
 
-	for file in &cli.file {
-		emit(process(&cli, file));
+	if let Some(Command::Validate { path }) = &cli.command {
+		let report = validate::validate(path)?;
+		tracing::info!("{} ok, {} failed", report.ok, report.failed.len());
+		eyre::ensure!(report.failed.is_empty(), "some files failed validation");
+		return Ok(());
+	}
+
+	if let Some(Command::ListFormats) = &cli.command {
+		print!("{}", formats::list_formats()?);
+		return Ok(());
+	}
+
+	if let Some(Command::Info { path }) = &cli.command {
+		for file in path {
+			let _span = tracing::info_span!("info", path = %file).entered();
+			emit(print_info(file));
+		}
+		return Ok(());
 	}
 
+	for (index, file) in cli.file.iter().enumerate() {
+		emit(process(&cli, index, file));
+	}
+
+	Ok(())
+}
+
+fn print_info(file: &Utf8Path) -> eyre::Result<()> {
+	let text = info::info(file, &std::fs::read(file)?)?;
+	println!("{file}");
+	for line in text.lines() {
+		println!("  {line}");
+	}
 	Ok(())
 }
 
-fn init_tracing() -> Result<(), eyre::Error> {
+fn init_tracing(cli: &Cli) -> Result<(), eyre::Error> {
 	use tracing_error::ErrorLayer;
 	use tracing_subscriber::prelude::*;
-	use tracing_subscriber::{fmt, EnvFilter};
-	let fmt_layer = fmt::layer().with_writer(std::io::stderr).with_target(false);
-	let filter_layer = EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new("info"))?;
+	use tracing_subscriber::EnvFilter;
+	let filter_layer = if cli.quiet {
+		EnvFilter::try_new("error")?
+	} else {
+		EnvFilter::try_from_default_env().or_else(|_| EnvFilter::try_new("info"))?
+	};
 	tracing_subscriber::registry()
 		.with(filter_layer)
-		.with(fmt_layer)
+		.with(log_layer(cli.log_json, || util::LogWriter))
 		.with(ErrorLayer::default())
 		.init();
 	eyre_span::install()?;
 	Ok(())
 }
 
+fn log_layer<S, W>(json: bool, writer: W) -> Box<dyn tracing_subscriber::Layer<S> + Send + Sync>
+where
+	S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+	W: for<'w> tracing_subscriber::fmt::MakeWriter<'w> + Send + Sync + 'static,
+{
+	use tracing_subscriber::{fmt, Layer};
+	let layer = fmt::layer().with_writer(writer).with_target(false);
+	if json {
+		layer.json().boxed()
+	} else {
+		layer.boxed()
+	}
+}
+
 #[tracing::instrument(skip_all, fields(path=%raw_file))]
-fn process(cli: &Cli, raw_file: &Utf8Path) -> eyre::Result<()> {
+fn process(cli: &Cli, index: usize, raw_file: &Utf8Path) -> eyre::Result<()> {
 	let file = &effective_input_file(raw_file)?;
 	if file != raw_file {
 		tracing::info!("using {file}");
 	}
 	let ext = file.extension().unwrap_or("");
-	let output = cli.output(file)?;
+	let output = cli.output(file, index)?;
 	let args = &cli.args;
 	match ext {
 		"itp" => {
-			let data = std::fs::read(file)?;
-			let itp = tracing::info_span!("parse_itp")
-				.in_scope(|| Ok(cradle::itp::read(&data)?))
+			let data = util::decompress(std::fs::read(file)?)?;
+			if args.extract_raw {
+				let output = itp_raw::extract(args, &data, output)?;
+				tracing::info!("wrote to {output}");
+				return Ok(());
+			}
+			let (itp, raw) = tracing::info_span!("parse_itp")
+				.in_scope(|| {
+					if args.dump_idat {
+						Ok(cradle::itp::read_raw(&data)?)
+					} else if args.lenient {
+						let (itp, ignored) = cradle::itp::read_lenient(&data)?;
+						if ignored.flags != 0 {
+							tracing::warn!("ignoring unknown itp flags {:08X}", ignored.flags);
+						}
+						if let Some(pixel_format) = ignored.pixel_format {
+							tracing::warn!(
+								"unknown pixel format {pixel_format}, reading as linear"
+							);
+						}
+						if let Some(mip_count) = ignored.mip_count {
+							tracing::warn!(
+								"header declares {mip_count} mips, but there are {}",
+								itp.data.mipmaps()
+							);
+						}
+						Ok((itp, Vec::new()))
+					} else {
+						Ok((cradle::itp::read(&data)?, Vec::new()))
+					}
+				})
 				.strict()?;
+			if args.repack {
+				let (_, repacked) =
+					tracing::info_span!("repack").in_scope(|| cradle::itp::repack(&itp))?;
+				// The game's compressor may beat ours, in which case the file is fine as it is
+				let repacked = if repacked.len() < data.len() {
+					repacked
+				} else {
+					data
+				};
+				let output = output.with_extension("repack.itp");
+				util::write_file(args, &output, repacked)?;
+				tracing::info!("wrote to {output}");
+				return Ok(());
+			}
+			for (n, raw) in raw.iter().enumerate() {
+				let output = output
+					.with_extension("itp")
+					.with_extension(&format!("mip{n}.bin"));
+				util::write_file(args, &output, raw)?;
+				tracing::info!("wrote to {output}");
+			}
 			let output = from_itp(args, &itp, output)?;
 			tracing::info!("wrote to {output}");
 		}
 
+		"dds" if args.dds_array => {
+			let data = std::fs::File::open(file)?;
+			let itps =
+				tracing::info_span!("parse_dds").in_scope(|| itp_dds::dds_to_itps(args, &data))?;
+			let itc = itc::from_itps(args, itps)?;
+			let output = output.with_extension("itc");
+			util::write_file(args, &output, cradle::itc::write(&itc)?)?;
+			tracing::info!("wrote to {output}");
+		}
+
+		"gif" => {
+			let data = std::fs::File::open(file)?;
+			let frames = tracing::info_span!("parse_gif").in_scope(|| gif::read(data))?;
+			let itps = frames
+				.into_iter()
+				.map(|frame| {
+					let data = cradle::itp::ImageData::Argb32(vec![frame]);
+					cradle::itp::Itp::new(cradle::itp::ItpRevision::V3, data)
+				})
+				.collect();
+			let itc = itc::from_itps(args, itps)?;
+			let output = output.with_extension("itc");
+			util::write_file(args, &output, cradle::itc::write(&itc)?)?;
+			tracing::info!("wrote to {output}");
+		}
+
 		"dds" | "png" => {
-			let data = to_itp(args, file)?;
+			let data = cache::cached(args, file, || to_itp(args, file))?;
+			let output = if args.split_planes && ext == "png" {
+				// Strip off the channel name
+				cli.output(&file.with_extension(""), index)?
+			} else {
+				output
+			};
 			let output = output.with_extension("itp");
-			std::fs::write(&output, data)?;
+			util::write_file(args, &output, data)?;
 			tracing::info!("wrote to {output}");
 		}
 
@@ -157,37 +683,78 @@ fn process(cli: &Cli, raw_file: &Utf8Path) -> eyre::Result<()> {
 			let itc = tracing::info_span!("parse_itc")
 				.in_scope(|| Ok(cradle::itc::read(&data)?))
 				.strict()?;
-			let output = crate::itc::extract(args, &itc, output)?;
+			if args.animate {
+				write_animation(args, &itc, itc::DEFAULT_FPS, &output.with_extension("itc"))?;
+			}
+			let output = if args.flat {
+				crate::itc::extract_flat(args, &itc, output)?
+			} else {
+				crate::itc::extract(args, &itc, output)?
+			};
 			tracing::info!("wrote to {output}");
 		}
 
 		"json" => {
 			let output = if file == raw_file {
 				// to strip off the duplicate .ext.json suffix
-				cli.output(&raw_file.with_extension(""))?
+				cli.output(&raw_file.with_extension(""), index)?
 			} else {
 				// but if it's a dir, there's no such suffix
-				cli.output(raw_file)?
+				cli.output(raw_file, index)?
 			};
 			let spec = tracing::info_span!("parse_json")
 				.in_scope(|| Ok(serde_json::from_reader(std::fs::File::open(file)?)?))
 				.strict()?;
 			let output = match spec {
 				Spec::Itc(spec) => {
+					let fps = spec.fps.unwrap_or(itc::DEFAULT_FPS);
 					let itc = itc::create(args, spec, file.parent().unwrap())?;
 					let output = output.with_extension("itc");
-					std::fs::write(&output, cradle::itc::write(&itc)?)?;
+					util::write_file(args, &output, cradle::itc::write(&itc)?)?;
+					if args.animate {
+						write_animation(args, &itc, fps, &output)?;
+					}
+					output
+				}
+				Spec::Raw(spec) => {
+					let data = itp_raw::assemble(&spec, file.parent().unwrap())?;
+					if let Err(e) = cradle::itp::read(&data) {
+						tracing::warn!("assembled itp is not valid: {e}");
+					}
+					let output = output.with_extension("itp");
+					util::write_file(args, &output, data)?;
 					output
 				}
 			};
 			tracing::info!("wrote to {output}");
 		}
 
+		"csv" => {
+			let output = cli.output(&file.with_extension(""), index)?;
+			let itc = itc::create_flat(file)?;
+			let output = output.with_extension("itc");
+			util::write_file(args, &output, cradle::itc::write(&itc)?)?;
+			tracing::info!("wrote to {output}");
+		}
+
 		_ => eyre::bail!("unknown file extension"),
 	}
 	Ok(())
 }
 
+fn write_animation(
+	args: &Args,
+	itc: &cradle::itc::Itc,
+	fps: f32,
+	itc_path: &Utf8Path,
+) -> eyre::Result<()> {
+	let output = itc_path.with_extension("anim.png");
+	let f = util::create_file(args, &output)?;
+	tracing::info_span!("animate").in_scope(|| itc::animate(args, itc, fps, f))?;
+	tracing::info!("wrote to {output}");
+	Ok(())
+}
+
 fn effective_input_file(file: &Utf8Path) -> eyre::Result<Utf8PathBuf> {
 	if file.is_dir() {
 		let files = file.read_dir_utf8()?.collect::<Result<Vec<_>, _>>()?;
@@ -214,61 +781,628 @@ fn from_itp(
 	itp: &cradle::itp::Itp,
 	output: util::Output,
 ) -> eyre::Result<Utf8PathBuf> {
-	if args.dds {
+	if let Some(revision) = args.to_revision {
+		let mut itp = itp.clone();
+		apply_status_args(args, &mut itp);
+		let Some(itp) = itp.to_revision(itp_revision(revision)) else {
+			eyre::bail!("itp revision {revision} cannot represent this file")
+		};
+		let output = output.with_extension(&format!("v{revision}.itp"));
+		util::write_file(args, &output, cradle::itp::write(&itp)?)?;
+		Ok(output)
+	} else if args.dds {
 		let output = output.with_extension("dds");
-		let f = std::fs::File::create(&output)?;
-		itp_dds::itp_to_dds(args, f, itp)?;
+		itp_dds::itp_to_dds_file(args, &output, itp)?;
 		Ok(output)
-	} else {
+	} else if args.ktx2 {
+		let output = output.with_extension("ktx2");
+		let f = util::create_file(args, &output)?;
+		itp_ktx2::itp_to_ktx2(f, itp)?;
+		Ok(output)
+	} else if args.split_planes {
 		let output = output.with_extension("png");
-		let f = std::fs::File::create(&output)?;
 		let png = itp_png::itp_to_png(args, itp)?;
-		png::write(f, &png)?;
+		let icc = util::icc_profile(args)?;
+		for (channel, name) in Channel::PLANES {
+			let output = output.with_extension(&format!("{name}.png"));
+			png::write_with_icc(
+				util::create_file(args, &output)?,
+				&itp_png::force_rgba(args, png.channel(channel.index())),
+				icc.as_deref(),
+			)?;
+		}
+		Ok(output.with_extension("r.png"))
+	} else {
+		let output = output.with_extension("png");
+		let f = util::create_file(args, &output)?;
+		let mut png = itp_png::itp_to_png(args, itp)?;
+		if args.trim {
+			let (width, height) = (png.width(), png.height());
+			let (x, y) = itc::trim(&mut png);
+			let trim = serde_json::json!({ "x": x, "y": y, "width": width, "height": height });
+			util::write_file(args, output.with_extension("trim.json"), trim.to_string())?;
+		}
+		png::write_with_icc(f, &png, util::icc_profile(args)?.as_deref())?;
 		Ok(output)
 	}
 }
 
 fn to_itp(args: &Args, path: &Utf8Path) -> eyre::Result<Vec<u8>> {
 	let data = match path.extension() {
+		Some("itp") => util::decompress(std::fs::read(path)?)?,
+		_ => cradle::itp::write(&image_to_itp(args, path)?)?,
+	};
+	Ok(data)
+}
+
+fn image_to_itp(args: &Args, path: &Utf8Path) -> eyre::Result<cradle::itp::Itp> {
+	let mut itp = match path.extension() {
+		Some("png") if args.split_planes => {
+			let png = tracing::info_span!("parse_png").in_scope(|| read_planes(path))?;
+			itp_png::png_to_itp(args, &png)
+		}
+
 		Some("png") => {
 			let data = std::fs::File::open(path)?;
-			let mut itp = tracing::info_span!("parse_png")
+			tracing::info_span!("parse_png")
 				.in_scope(|| Ok(itp_png::png_to_itp(args, &png::read(&data)?)))
-				.strict()?;
-			guess_itp_revision(args, &mut itp);
-			cradle::itp::write(&itp)?
+				.strict()?
+		}
+
+		Some("dds") if args.dds_mip_files => {
+			tracing::info_span!("parse_dds").in_scope(|| itp_dds::dds_files_to_itp(args, path))?
 		}
 
 		Some("dds") => {
 			let data = std::fs::File::open(path)?;
-			let mut itp =
-				tracing::info_span!("parse_dds").in_scope(|| itp_dds::dds_to_itp(args, &data))?;
-			guess_itp_revision(args, &mut itp);
-			cradle::itp::write(&itp)?
+			tracing::info_span!("parse_dds").in_scope(|| itp_dds::dds_to_itp(args, &data))?
 		}
 
-		Some("itp") => std::fs::read(path)?,
-
 		_ => eyre::bail!("unknown file extension"),
 	};
-	Ok(data)
+	clean_alpha(args, &mut itp)?;
+	if args.mipmaps {
+		let cradle::itp::ImageData::Argb32(data) = &itp.data else {
+			eyre::bail!("mipmaps can only be generated for 32-bit images")
+		};
+		eyre::ensure!(
+			data.len() == 1,
+			"{path} already has mipmaps, so they cannot be generated"
+		);
+		let srgb = args.mip_gamma == MipGamma::Srgb;
+		let data = cradle::color::mipmaps(&data[0], srgb);
+		itp = cradle::itp::Itp::new(
+			itp.status.itp_revision,
+			cradle::itp::ImageData::Argb32(data),
+		);
+	}
+	if let Some(path) = &args.palette_from {
+		let pal = read_palette(path)?;
+		remap_palette(&mut itp, &pal)?;
+	}
+	if let Some(layout) = args.argb16 {
+		let cradle::itp::ImageData::Argb32(data) = &itp.data else {
+			eyre::bail!("only 32-bit images can be converted to 16-bit")
+		};
+		let mode = layout.mode();
+		let data = data
+			.iter()
+			.map(|r| {
+				if args.dither {
+					cradle::color::dither_argb16(mode, r)
+				} else {
+					r.map(|&a| cradle::color::pack_argb16(mode, a))
+				}
+			})
+			.collect();
+		itp = cradle::itp::Itp::new(
+			itp.status.itp_revision,
+			cradle::itp::ImageData::Argb16(mode, data),
+		);
+	}
+	apply_status_args(args, &mut itp);
+	choose_compression(args, &mut itp);
+	guess_itp_revision(args, &mut itp);
+	Ok(itp)
+}
+
+/// Applies `--use-alpha`, `--force-linear` and `--drop-hash` to an itp about to be written.
+fn apply_status_args(args: &Args, itp: &mut cradle::itp::Itp) {
+	if args.use_alpha.is_some() {
+		itp.status.use_alpha = args.use_alpha;
+	}
+	if args.force_linear {
+		itp.status.pixel_format = cradle::itp::PixelFormatType::Linear;
+	}
+	if args.drop_hash {
+		itp.status.hash = None;
+	}
+}
+
+/// Applies `--clean-alpha`, if given.
+fn clean_alpha(args: &Args, itp: &mut cradle::itp::Itp) -> eyre::Result<()> {
+	let Some(mode) = args.clean_alpha else {
+		return Ok(());
+	};
+	let cradle::itp::ImageData::Argb32(data) = &mut itp.data else {
+		eyre::bail!("transparent pixels can only be cleaned in 32-bit images")
+	};
+	for level in data {
+		match mode {
+			CleanAlpha::Black => cradle::color::clear_transparent(level),
+			CleanAlpha::Bleed => cradle::color::bleed_transparent(level),
+		}
+	}
+	Ok(())
+}
+
+/// Reads the palette of an indexed png or itp, or a JASC .pal file.
+fn read_palette(path: &Utf8Path) -> eyre::Result<Vec<u32>> {
+	let pal = match path.extension() {
+		Some("png") => match png::read(std::fs::File::open(path)?)? {
+			png::Png::Indexed(pal, _) => pal,
+			_ => eyre::bail!("{path} is not indexed"),
+		},
+		Some("itp") => {
+			let itp = cradle::itp::read(&util::decompress(std::fs::read(path)?)?)?;
+			let cradle::itp::ImageData::Indexed(pal, _) = &itp.data else {
+				eyre::bail!("{path} is not indexed")
+			};
+			let Some(pal) = pal.colors() else {
+				eyre::bail!("{path} has an external palette")
+			};
+			pal.to_vec()
+		}
+		Some("pal") => parse_jasc_pal(&std::fs::read_to_string(path)?)?,
+		_ => eyre::bail!("unknown palette file extension"),
+	};
+	eyre::ensure!(!pal.is_empty(), "{path} has an empty palette");
+	Ok(pal)
+}
+
+/// Parses a JASC-PAL file: a header, a color count, and a line of `r g b` for each color.
+fn parse_jasc_pal(text: &str) -> eyre::Result<Vec<u32>> {
+	let mut lines = text.lines();
+	eyre::ensure!(lines.next() == Some("JASC-PAL"), "not a JASC-PAL file");
+	eyre::ensure!(lines.next() == Some("0100"), "unknown JASC-PAL version");
+	let n = lines.next().unwrap_or_default().trim().parse::<usize>()?;
+	let pal = lines
+		.take(n)
+		.map(|line| {
+			let rgb = line
+				.split_whitespace()
+				.map(str::parse)
+				.collect::<Result<Vec<u8>, _>>()?;
+			let &[r, g, b] = rgb.as_slice() else {
+				eyre::bail!("invalid JASC-PAL color: {line}")
+			};
+			Ok(u32::from_le_bytes([b, g, r, 0xFF]))
+		})
+		.collect::<eyre::Result<Vec<_>>>()?;
+	eyre::ensure!(pal.len() == n, "JASC-PAL file is truncated");
+	Ok(pal)
+}
+
+/// Replaces the image's colors with the nearest colors in `pal`, making it indexed.
+fn remap_palette(itp: &mut cradle::itp::Itp, pal: &[u32]) -> eyre::Result<()> {
+	use cradle::color::nearest_color;
+	use cradle::itp::{ImageData, Palette};
+	eyre::ensure!(pal.len() <= 256, "palette has more than 256 colors");
+	let data = match &itp.data {
+		ImageData::Indexed(old, data) => {
+			let Some(old) = old.colors() else {
+				eyre::bail!("cannot remap an external palette")
+			};
+			let map = old
+				.iter()
+				.map(|&c| nearest_color(pal, c) as u8)
+				.collect::<Vec<_>>();
+			data.iter()
+				.map(|r| r.map(|&i| map.get(i as usize).copied().unwrap_or(0)))
+				.collect()
+		}
+		ImageData::Argb32(data) => data
+			.iter()
+			.map(|r| r.map(|&c| nearest_color(pal, c) as u8))
+			.collect(),
+		_ => eyre::bail!("only indexed and 32-bit images can be remapped"),
+	};
+	let data = ImageData::Indexed(Palette::Embedded(pal.to_vec()), data);
+	*itp = cradle::itp::Itp::new(itp.status.itp_revision, data);
+	Ok(())
+}
+
+/// Reads and combines the four grayscale pngs written by `--split-planes`, given any one of them.
+fn read_planes(path: &Utf8Path) -> eyre::Result<png::Png> {
+	let base = path.with_extension("");
+	eyre::ensure!(
+		Channel::PLANES
+			.iter()
+			.any(|(_, name)| base.extension() == Some(name)),
+		"{path} is not named like a channel plane, such as .r.png"
+	);
+	let mut planes: [Vec<cradle::raster::Raster<u8>>; 4] = Default::default();
+	for (channel, name) in Channel::PLANES {
+		let path = base.with_extension(&format!("{name}.png"));
+		// Grayscale is read as ARGB, so take any of the color channels
+		let plane = png::read(std::fs::File::open(&path)?)?.channel(Channel::R.index());
+		let png::Png::Gray(data) = plane else {
+			unreachable!()
+		};
+		planes[channel.index()] = data;
+	}
+	eyre::ensure!(
+		planes.iter().all(|p| p.len() == planes[0].len()),
+		"channel planes have different numbers of frames"
+	);
+	let mut data = Vec::new();
+	for i in 0..planes[0].len() {
+		let frame = planes.each_ref().map(|p| p[i].clone());
+		let size = (frame[0].width(), frame[0].height());
+		eyre::ensure!(
+			frame.iter().all(|p| (p.width(), p.height()) == size),
+			"channel planes have different sizes"
+		);
+		data.push(cradle::raster::Raster::from_channels(frame));
+	}
+	Ok(png::Png::Argb32(data))
+}
+
+/// Sets the compression from `--compression`, or the default for the image.
+///
+/// This should be done before [`guess_itp_revision`], since not all revisions support all
+/// compression types.
+fn choose_compression(args: &Args, itp: &mut cradle::itp::Itp) {
+	itp.status.compression = match args.compression {
+		Some(compression) => compression.compression_type(),
+		None => cradle::itp::ItpStatus::default_compression(&itp.data),
+	};
 }
 
 fn guess_itp_revision(args: &Args, itp: &mut cradle::itp::Itp) {
-	use cradle::itp::ItpRevision as IR;
+	use cradle::itp::{Argb16Mode, BaseFormatType as BFT, ImageData, ItpRevision as IR};
 	itp.status.itp_revision = match args.itp_revision {
-		Some(1) => IR::V1,
-		Some(2) => IR::V2,
-		Some(3) => IR::V3,
-		Some(_) => unreachable!(),
-		None => match &itp.data {
-			cradle::itp::ImageData::Indexed(_, _) => IR::V1,
-			cradle::itp::ImageData::Argb16(_, _) => unimplemented!(),
-			cradle::itp::ImageData::Argb32(_) => IR::V2,
-			cradle::itp::ImageData::Bc1(_) => IR::V2,
-			cradle::itp::ImageData::Bc2(_) => IR::V2,
-			cradle::itp::ImageData::Bc3(_) => IR::V2,
-			cradle::itp::ImageData::Bc7(_) => IR::V3,
+		Some(revision) => itp_revision(revision),
+		None => match itp.data.base_format() {
+			BFT::Indexed1 => IR::V1,
+			BFT::Argb16 if matches!(itp.data, ImageData::Argb16(Argb16Mode::Mode2, _)) => IR::V1,
+			BFT::Bc7 => IR::V3,
+			_ => IR::V2,
 		},
+	};
+	for revision in [IR::V2, IR::V3] {
+		if args.itp_revision.is_some() || itp.status.is_representable() {
+			break;
+		}
+		itp.status.itp_revision = itp.status.itp_revision.max(revision);
+	}
+}
+
+fn itp_revision(revision: u16) -> cradle::itp::ItpRevision {
+	use cradle::itp::ItpRevision as IR;
+	match revision {
+		1 => IR::V1,
+		2 => IR::V2,
+		3 => IR::V3,
+		_ => unreachable!(),
+	}
+}
+
+#[test]
+fn test_remap_palette() -> eyre::Result<()> {
+	use cradle::itp::{ImageData, Itp, ItpRevision, Palette};
+	use cradle::raster::Raster;
+	let pal = vec![0xFF000000, 0xFF808080, 0xFFFFFFFF];
+	let pixels = Raster::new_with(3, 1, vec![2, 0, 1]);
+	let itp = Itp::new(
+		ItpRevision::V3,
+		ImageData::Indexed(Palette::Embedded(pal.clone()), vec![pixels]),
+	);
+	let mut same = itp.clone();
+	remap_palette(&mut same, &pal)?;
+	assert_eq!(same, itp);
+
+	let coarse = [0xFFFFFFFF, 0xFF000000];
+	let pixels = Raster::new_with(4, 1, vec![0xFF101010, 0xFF909090, 0xFF606060, 0xFFFFFFFF]);
+	let mut itp = Itp::new(ItpRevision::V3, ImageData::Argb32(vec![pixels]));
+	remap_palette(&mut itp, &coarse)?;
+	let ImageData::Indexed(pal, data) = &itp.data else {
+		panic!("not indexed")
+	};
+	assert_eq!(pal.colors(), Some(&coarse[..]));
+	assert_eq!(data[0].as_slice(), [1, 0, 1, 0]);
+
+	let text = "JASC-PAL\r\n0100\r\n2\r\n255 0 0\r\n0 0 255\r\n";
+	assert_eq!(parse_jasc_pal(text)?, [0xFFFF0000, 0xFF0000FF]);
+	assert!(parse_jasc_pal("JASC-PAL\n0100\n3\n0 0 0\n").is_err());
+	Ok(())
+}
+
+#[test]
+fn test_compression() -> eyre::Result<()> {
+	use cradle::itp::CompressionType as CT;
+	use cradle::raster::Raster;
+	let tmpdir = camino_tempfile::tempdir()?;
+	let path = tmpdir.path().join("a.png");
+	let img = png::Png::Argb32(vec![Raster::new(16, 16)]);
+	png::write(std::fs::File::create(&path)?, &img)?;
+
+	let itp = image_to_itp(&Args::default(), &path)?;
+	assert_eq!(itp.status.compression, CT::Bz_1);
+	assert_eq!(cradle::itp::read(&cradle::itp::write(&itp)?)?, itp);
+
+	let args = &Args {
+		compression: Some(Compression::None),
+		..Args::default()
+	};
+	assert_eq!(image_to_itp(args, &path)?.status.compression, CT::None);
+
+	let args = &Args {
+		compression: Some(Compression::Bz2),
+		..Args::default()
+	};
+	let itp = image_to_itp(args, &path)?;
+	assert_eq!(itp.status.compression, CT::Bz_2);
+	assert_eq!(cradle::itp::read(&cradle::itp::write(&itp)?)?, itp);
+	Ok(())
+}
+
+#[test]
+fn test_force_linear() -> eyre::Result<()> {
+	use cradle::itp::{ImageData, Itp, ItpRevision, PixelFormatType};
+	use cradle::raster::Raster;
+	let tmpdir = camino_tempfile::tempdir()?;
+	let pixels = Raster::new_with(16, 16, (0..256).collect());
+	let mut itp = Itp::new(ItpRevision::V3, ImageData::Argb32(vec![pixels.clone()]));
+	itp.status.pixel_format = PixelFormatType::Pfp_3;
+	let in_order = pixels
+		.as_slice()
+		.iter()
+		.flat_map(|a| a.to_le_bytes())
+		.collect::<Vec<u8>>();
+
+	let args = &Args {
+		to_revision: Some(3),
+		..Args::default()
+	};
+	let output = from_itp(args, &itp, util::Output::In(tmpdir.path().join("a.itp")))?;
+	let (_, raw) = cradle::itp::read_raw(&std::fs::read(&output)?)?;
+	assert_ne!(raw[0], in_order);
+
+	let args = &Args {
+		to_revision: Some(3),
+		force_linear: true,
+		..Args::default()
+	};
+	let output = from_itp(args, &itp, util::Output::In(tmpdir.path().join("b.itp")))?;
+	let (itp2, raw) = cradle::itp::read_raw(&std::fs::read(&output)?)?;
+	assert_eq!(raw[0], in_order);
+	assert_eq!(itp2.status.pixel_format, PixelFormatType::Linear);
+	assert_eq!(itp2.data, itp.data);
+	Ok(())
+}
+
+#[test]
+fn test_drop_hash() -> eyre::Result<()> {
+	let tmpdir = camino_tempfile::tempdir()?;
+	let itp = cradle::itp::read(include_bytes!("../../samples/itp/ao__extra020.itp"))?;
+	assert!(itp.status.hash.is_some());
+	for drop_hash in [false, true] {
+		let args = &Args {
+			to_revision: Some(3),
+			drop_hash,
+			..Args::default()
+		};
+		let output = from_itp(args, &itp, util::Output::In(tmpdir.path().join("a.itp")))?;
+		let itp2 = cradle::itp::read(&std::fs::read(&output)?)?;
+		assert_eq!(itp2.status.hash.is_none(), drop_hash);
 	}
+	Ok(())
+}
+
+#[test]
+fn test_trim() -> eyre::Result<()> {
+	use cradle::itp::{ImageData, Itp, ItpRevision};
+	use cradle::raster::Raster;
+	let tmpdir = camino_tempfile::tempdir()?;
+	let mut data = Raster::splat(8, 4, 0x00000000u32);
+	data[[2, 1]] = 0xFFFF0000;
+	data[[4, 2]] = 0xFF00FF00;
+	let itp = Itp::new(ItpRevision::V3, ImageData::Argb32(vec![data]));
+	let args = &Args {
+		trim: true,
+		..Args::default()
+	};
+	let output = from_itp(args, &itp, util::Output::In(tmpdir.path().join("a.itp")))?;
+
+	let img = png::read(std::fs::File::open(&output)?)?;
+	assert_eq!((img.width(), img.height()), (3, 2));
+	let trim: serde_json::Value =
+		serde_json::from_slice(&std::fs::read(output.with_extension("trim.json"))?)?;
+	assert_eq!(
+		trim,
+		serde_json::json!({ "x": 2, "y": 1, "width": 8, "height": 4 })
+	);
+	Ok(())
+}
+
+#[test]
+fn test_split_planes() -> eyre::Result<()> {
+	use cradle::itp::{ImageData, Itp, ItpRevision};
+	use cradle::raster::Raster;
+	let tmpdir = camino_tempfile::tempdir()?;
+	let pixels = Raster::new_with(3, 1, vec![0x11223344, 0x80FFFFFF, 0x00000000]);
+	let itp = Itp::new(ItpRevision::V3, ImageData::Argb32(vec![pixels]));
+	let args = &Args {
+		split_planes: true,
+		..Args::default()
+	};
+	let output = from_itp(args, &itp, util::Output::In(tmpdir.path().join("a.itp")))?;
+	assert_eq!(output, tmpdir.path().join("a.r.png"));
+	let a = png::read(std::fs::File::open(tmpdir.path().join("a.a.png"))?)?;
+	assert_eq!(
+		a.channel(Channel::R.index()),
+		png::Png::Gray(vec![Raster::new_with(3, 1, vec![0x11, 0x80, 0x00])])
+	);
+
+	let itp2 = image_to_itp(args, &tmpdir.path().join("a.g.png"))?;
+	assert_eq!(itp2.data, itp.data);
+	Ok(())
+}
+
+#[test]
+fn test_use_alpha() -> eyre::Result<()> {
+	let tmpdir = camino_tempfile::tempdir()?;
+	let path = tmpdir.path().join("a.png");
+	let img = png::Png::Argb32(vec![cradle::raster::Raster::new(2, 2)]);
+	png::write(std::fs::File::create(&path)?, &img)?;
+
+	for (revision, use_alpha) in [(3, false), (3, true), (2, true)] {
+		let args = &Args {
+			itp_revision: Some(revision),
+			use_alpha: Some(use_alpha),
+			..Args::default()
+		};
+		let data = to_itp(args, &path)?;
+		if revision == 3 {
+			let pos = data.windows(4).position(|a| a == b"IALP").unwrap();
+			assert_eq!(data[pos + 12..pos + 14], (use_alpha as u16).to_le_bytes());
+		}
+		let itp = cradle::itp::read(&data)?;
+		assert_eq!(itp.status.use_alpha, Some(use_alpha));
+	}
+
+	let path = tmpdir.path().join("b.png");
+	let img = png::Png::Indexed(vec![0], vec![cradle::raster::Raster::new(2, 2)]);
+	png::write(std::fs::File::create(&path)?, &img)?;
+	let args = &Args {
+		use_alpha: Some(true),
+		..Args::default()
+	};
+	let itp = cradle::itp::read(&to_itp(args, &path)?)?;
+	assert_eq!(itp.status.itp_revision, cradle::itp::ItpRevision::V2);
+	assert_eq!(itp.status.use_alpha, Some(true));
+	Ok(())
+}
+
+#[test]
+fn test_clean_alpha() -> eyre::Result<()> {
+	let tmpdir = camino_tempfile::tempdir()?;
+	let path = tmpdir.path().join("a.png");
+	let pixels = vec![0xFF123456, 0x00FFFFFF, 0x80ABCDEF, 0x00FFFFFF];
+	let img = png::Png::Argb32(vec![cradle::raster::Raster::new_with(2, 2, pixels.clone())]);
+	png::write(std::fs::File::create(&path)?, &img)?;
+
+	for (mode, transparent) in [(CleanAlpha::Black, 0), (CleanAlpha::Bleed, 0x005F81A3)] {
+		let args = Args {
+			clean_alpha: Some(mode),
+			..Args::default()
+		};
+		let itp = image_to_itp(&args, &path)?;
+		let cradle::itp::ImageData::Argb32(data) = &itp.data else {
+			eyre::bail!("expected argb32")
+		};
+		assert_eq!(
+			data[0].as_slice(),
+			[pixels[0], transparent, pixels[2], transparent]
+		);
+	}
+	Ok(())
+}
+
+#[test]
+fn test_mipmaps() -> eyre::Result<()> {
+	let tmpdir = camino_tempfile::tempdir()?;
+	let path = tmpdir.path().join("a.png");
+	let img = png::Png::Argb32(vec![cradle::raster::Raster::new(8, 4)]);
+	png::write(std::fs::File::create(&path)?, &img)?;
+
+	let args = &Args {
+		mipmaps: true,
+		..Args::default()
+	};
+	let itp = cradle::itp::read(&to_itp(args, &path)?)?;
+	assert_eq!(itp.data.mipmaps(), 3);
+	assert_eq!(itp.status.itp_revision, cradle::itp::ItpRevision::V3);
+
+	let path = tmpdir.path().join("b.dds");
+	itp_dds::itp_to_dds(&Args::default(), std::fs::File::create(&path)?, &itp)?;
+	assert!(to_itp(args, &path).is_err());
+	Ok(())
+}
+
+#[test]
+fn test_argb16() -> eyre::Result<()> {
+	let tmpdir = camino_tempfile::tempdir()?;
+	let path = tmpdir.path().join("a.png");
+	let pixels = (0..64).map(|i| 0xFF000000 | i * 0x010101).collect();
+	let img = png::Png::Argb32(vec![cradle::raster::Raster::new_with(8, 8, pixels)]);
+	png::write(std::fs::File::create(&path)?, &img)?;
+
+	for dither in [false, true] {
+		let args = &Args {
+			argb16: Some(Argb16Layout::A4444),
+			dither,
+			..Args::default()
+		};
+		let itp = cradle::itp::read(&to_itp(args, &path)?)?;
+		let cradle::itp::ImageData::Argb16(mode, _) = itp.data else {
+			eyre::bail!("expected argb16")
+		};
+		assert_eq!(mode, cradle::itp::Argb16Mode::Mode2);
+		assert_eq!(itp.status.itp_revision, cradle::itp::ItpRevision::V1);
+	}
+
+	// 1555 keeps these colors exactly, but only one bit of their alpha
+	let pixels = vec![0xFFFF0000, 0x8000FF00, 0x400000FF, 0x00FFFFFF];
+	let raster = cradle::raster::Raster::new_with(2, 2, pixels);
+	png::write(
+		std::fs::File::create(&path)?,
+		&png::Png::Argb32(vec![raster.clone()]),
+	)?;
+	let args = &Args {
+		argb16: Some(Argb16Layout::A1555),
+		..Args::default()
+	};
+	let itp = cradle::itp::read(&to_itp(args, &path)?)?;
+	let png::Png::Argb32(mips) = itp_png::itp_to_png(args, &itp)? else {
+		eyre::bail!("expected argb32")
+	};
+	assert_ne!(mips[0], raster);
+	assert!(test_util::raster_eq_rgb(&mips[0], &raster));
+	Ok(())
+}
+
+#[test]
+fn test_log_json() {
+	use std::sync::{Arc, Mutex};
+	use tracing_subscriber::prelude::*;
+
+	#[derive(Clone, Default)]
+	struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+	impl std::io::Write for Buffer {
+		fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+			self.0.lock().unwrap().extend_from_slice(data);
+			Ok(data.len())
+		}
+
+		fn flush(&mut self) -> std::io::Result<()> {
+			Ok(())
+		}
+	}
+
+	let buf = Buffer::default();
+	let writer = buf.clone();
+	let subscriber = tracing_subscriber::registry().with(log_layer(true, move || writer.clone()));
+	tracing::subscriber::with_default(subscriber, || {
+		let _span = tracing::info_span!("process", path = "a.itp").entered();
+		tracing::info!("wrote to {}", "a.png");
+	});
+
+	let out = String::from_utf8(buf.0.lock().unwrap().clone()).unwrap();
+	let line = out.lines().next().unwrap();
+	let json: serde_json::Value = serde_json::from_str(line).unwrap();
+	assert_eq!(json["level"], "INFO");
+	assert_eq!(json["fields"]["message"], "wrote to a.png");
 }
@@ -1,11 +1,16 @@
 use std::io::{Read, Write};
 
-use cradle::raster::Raster;
+use cradle::{
+	color::{premultiply, swap_channels},
+	itp::mip_dimensions,
+	raster::Raster,
+};
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum Png {
 	Argb32(Vec<Raster<u32>>),
 	Indexed(Vec<u32>, Vec<Raster<u8>>),
+	Gray(Vec<Raster<u8>>),
 }
 
 impl Png {
@@ -13,6 +18,59 @@ impl Png {
 		match self {
 			Png::Argb32(d) => d[0].width(),
 			Png::Indexed(_, d) => d[0].width(),
+			Png::Gray(d) => d[0].width(),
+		}
+	}
+
+	pub fn rotate(&mut self, rotation: crate::Rotation) {
+		match self {
+			Png::Argb32(data) => data.iter_mut().for_each(|a| *a = rotation.apply(a)),
+			Png::Indexed(_, data) => data.iter_mut().for_each(|a| *a = rotation.apply(a)),
+			Png::Gray(data) => data.iter_mut().for_each(|a| *a = rotation.apply(a)),
+		}
+	}
+
+	/// Multiplies color by alpha, in the pixels or the palette.
+	pub fn premultiply(&mut self) {
+		match self {
+			Png::Argb32(data) => {
+				for frame in data {
+					for argb in frame.as_mut_slice() {
+						*argb = premultiply(*argb);
+					}
+				}
+			}
+			Png::Indexed(pal, _) => {
+				for argb in pal {
+					*argb = premultiply(*argb);
+				}
+			}
+			Png::Gray(_) => {}
+		}
+	}
+
+	/// Extracts one channel as a grayscale image, with 0 to 3 meaning blue, green, red, and alpha.
+	pub fn channel(&self, channel: usize) -> Png {
+		Png::Gray(
+			self.to_argb32()
+				.iter()
+				.map(|a| a.split_channels().into_iter().nth(channel).unwrap())
+				.collect(),
+		)
+	}
+
+	/// Converts to 32-bit color, expanding palettes and grayscale.
+	pub fn to_argb32(&self) -> Vec<Raster<u32>> {
+		match self {
+			Png::Argb32(data) => data.clone(),
+			Png::Indexed(pal, data) => data
+				.iter()
+				.map(|a| a.map(|&i| pal.get(i as usize).copied().unwrap_or_default()))
+				.collect(),
+			Png::Gray(data) => data
+				.iter()
+				.map(|a| a.map(|&k| 0xFF000000 | k as u32 * 0x010101))
+				.collect(),
 		}
 	}
 
@@ -20,26 +78,63 @@ impl Png {
 		match self {
 			Png::Argb32(d) => d[0].height(),
 			Png::Indexed(_, d) => d[0].height(),
+			Png::Gray(d) => d[0].height(),
 		}
 	}
 }
 
 pub fn write(w: impl Write, img: &Png) -> eyre::Result<()> {
+	write_with_icc(w, img, None)
+}
+
+/// Like [`write`], but also embeds a color profile in an iCCP chunk, if one is given.
+pub fn write_with_icc(mut w: impl Write, img: &Png, icc: Option<&[u8]>) -> eyre::Result<()> {
+	let Some(icc) = icc else {
+		return encode(w, img);
+	};
+	let mut data = Vec::new();
+	encode(&mut data, img)?;
+	// iCCP must come before PLTE and IDAT, so place it right after IHDR, which is always first
+	let ihdr_end = 8 + 8 + 13 + 4;
+	w.write_all(&data[..ihdr_end])?;
+	w.write_all(&iccp_chunk(icc)?)?;
+	w.write_all(&data[ihdr_end..])?;
+	Ok(())
+}
+
+fn iccp_chunk(icc: &[u8]) -> eyre::Result<Vec<u8>> {
+	let mut body = b"iCCP".to_vec();
+	body.extend(ICC_PROFILE_NAME.as_bytes());
+	body.push(0); // name terminator
+	body.push(0); // compression method: zlib
+	let mut zlib = flate2::write::ZlibEncoder::new(body, flate2::Compression::default());
+	zlib.write_all(icc)?;
+	let body = zlib.finish()?;
+
+	let mut crc = flate2::Crc::new();
+	crc.update(&body);
+	let mut chunk = Vec::with_capacity(body.len() + 8);
+	chunk.extend((body.len() as u32 - 4).to_be_bytes());
+	chunk.extend(&body);
+	chunk.extend(crc.sum().to_be_bytes());
+	Ok(chunk)
+}
+
+fn encode(w: impl Write, img: &Png) -> eyre::Result<()> {
 	let mut png = png::Encoder::new(w, img.width() as u32, img.height() as u32);
 	match img {
 		Png::Argb32(data) => {
 			png.set_color(png::ColorType::Rgba);
 			png.set_depth(png::BitDepth::Eight);
-			write_frames(data, png, |&argb| {
-				let [b, g, r, a] = argb.to_le_bytes();
-				[r, g, b, a]
-			})
+			write_frames(data, png, argb_to_rgba)
 		}
 		Png::Indexed(palette, data) => {
+			let mut palette = palette.clone();
+			swap_channels(&mut palette);
 			let mut pal = Vec::with_capacity(3 * palette.len());
 			let mut alp = Vec::with_capacity(palette.len());
-			for argb in palette {
-				let [b, g, r, a] = u32::to_le_bytes(*argb);
+			for &argb in &palette {
+				let [r, g, b, a] = u32::to_le_bytes(argb);
 				pal.push(r);
 				pal.push(g);
 				pal.push(b);
@@ -49,15 +144,49 @@ pub fn write(w: impl Write, img: &Png) -> eyre::Result<()> {
 			png.set_depth(png::BitDepth::Eight);
 			png.set_palette(pal);
 			png.set_trns(alp);
-			write_frames(data, png, |&i| [i])
+			png.add_text_chunk(PALETTE_KEYWORD.to_owned(), format_palette(&palette))?;
+			write_frames(data, png, |frame| frame.to_vec())
 		}
+		Png::Gray(data) => {
+			png.set_color(png::ColorType::Grayscale);
+			png.set_depth(png::BitDepth::Eight);
+			write_frames(data, png, |frame| frame.to_vec())
+		}
+	}
+}
+
+/// Writes an animated png, showing each frame for `delay.0 / delay.1` seconds.
+pub fn write_animated(
+	w: impl Write,
+	frames: &[Raster<u32>],
+	delay: (u16, u16),
+) -> eyre::Result<()> {
+	let Some(first) = frames.first() else {
+		eyre::bail!("no frames to animate")
+	};
+	let mut png = png::Encoder::new(w, first.width() as u32, first.height() as u32);
+	png.set_color(png::ColorType::Rgba);
+	png.set_depth(png::BitDepth::Eight);
+	png.set_animated(frames.len() as u32, 0)?;
+	png.set_frame_delay(delay.0, delay.1)?;
+	let mut png = png.write_header()?;
+	for frame in frames {
+		png.write_image_data(&argb_to_rgba(frame.as_slice()))?;
 	}
+	png.finish()?;
+	Ok(())
 }
 
-fn write_frames<T, const N: usize>(
+fn argb_to_rgba(data: &[u32]) -> Vec<u8> {
+	let mut data = data.to_vec();
+	swap_channels(&mut data);
+	data.iter().flat_map(|a| a.to_le_bytes()).collect()
+}
+
+fn write_frames<T>(
 	data: &[Raster<T>],
 	mut png: png::Encoder<impl Write>,
-	mut f: impl FnMut(&T) -> [u8; N],
+	mut f: impl FnMut(&[T]) -> Vec<u8>,
 ) -> Result<(), eyre::Error> {
 	let nmips = data.len();
 	if nmips > 1 {
@@ -71,7 +200,7 @@ fn write_frames<T, const N: usize>(
 		if !std::mem::take(&mut first) {
 			png.set_frame_dimension(frame.width() as u32, frame.height() as u32)?;
 		}
-		png.write_image_data(&frame.as_slice().iter().flat_map(&mut f).collect::<Vec<_>>())?;
+		png.write_image_data(&f(frame.as_slice()))?;
 	}
 	png.finish()?;
 	Ok(())
@@ -79,9 +208,15 @@ fn write_frames<T, const N: usize>(
 
 pub fn read(f: impl Read) -> eyre::Result<Png> {
 	let png = png::Decoder::new(f).read_info()?;
+	let depth = png.info().bit_depth;
+	let packed = png.info().color_type == png::ColorType::Indexed
+		&& matches!(
+			depth,
+			png::BitDepth::One | png::BitDepth::Two | png::BitDepth::Four
+		);
 	eyre::ensure!(
-		png.info().bit_depth == png::BitDepth::Eight,
-		"only 8-bit png is supported"
+		depth == png::BitDepth::Eight || packed,
+		"only 8-bit png is supported, or 1/2/4-bit indexed"
 	);
 
 	let pal = png.info().palette.as_ref().map(|pal| {
@@ -94,10 +229,20 @@ pub fn read(f: impl Read) -> eyre::Result<Png> {
 				*rgb = *rgb & 0xFFFFFF | (*a as u32) << 24;
 			}
 		}
-		pal
+		match stored_palette(png.info()) {
+			Some(stored) if stored.get(..pal.len()) == Some(pal.as_slice()) => stored,
+			Some(_) => {
+				tracing::warn!("palette has been edited, ignoring the original palette");
+				pal
+			}
+			None => pal,
+		}
 	});
 
 	Ok(match png.info().color_type {
+		png::ColorType::Indexed if packed => {
+			Png::Indexed(pal.unwrap(), read_packed_frames(png, depth as usize)?)
+		}
 		png::ColorType::Indexed => Png::Indexed(pal.unwrap(), read_frames(png, |[a]| a)?),
 		png::ColorType::Grayscale => {
 			Png::Argb32(read_frames(png, |[k]| u32::from_le_bytes([k, k, k, 0xFF]))?)
@@ -108,12 +253,51 @@ pub fn read(f: impl Read) -> eyre::Result<Png> {
 		png::ColorType::Rgb => Png::Argb32(read_frames(png, |[r, g, b]| {
 			u32::from_le_bytes([b, g, r, 0xFF])
 		})?),
-		png::ColorType::Rgba => Png::Argb32(read_frames(png, |[r, g, b, a]| {
-			u32::from_le_bytes([b, g, r, a])
-		})?),
+		png::ColorType::Rgba => {
+			let mut frames = read_frames(png, u32::from_le_bytes)?;
+			for frame in &mut frames {
+				swap_channels(frame.as_mut_slice());
+			}
+			Png::Argb32(frames)
+		}
 	})
 }
 
+/// Keyword of a text chunk holding the full palette.
+///
+/// Image editors and optimizers tend to drop unused palette entries, which itp files may rely on,
+/// so the palette is also stored here and restored on read, as long as the remaining entries are
+/// unchanged.
+const PALETTE_KEYWORD: &str = "Cradle palette";
+
+/// Name given to embedded color profiles. It is only descriptive.
+const ICC_PROFILE_NAME: &str = "ICC profile";
+
+/// Formats a palette as hex, in the same RGBA byte order as the png palette.
+fn format_palette(rgba: &[u32]) -> String {
+	rgba.iter()
+		.map(|c| format!("{:08X}", c.swap_bytes()))
+		.collect()
+}
+
+fn stored_palette(info: &png::Info) -> Option<Vec<u32>> {
+	let text = &info
+		.uncompressed_latin1_text
+		.iter()
+		.find(|t| t.keyword == PALETTE_KEYWORD)?
+		.text;
+	if !text.is_ascii() || text.len() % 8 != 0 {
+		return None;
+	}
+	let mut pal = (0..text.len())
+		.step_by(8)
+		.map(|i| u32::from_str_radix(&text[i..i + 8], 16).map(u32::swap_bytes))
+		.collect::<Result<Vec<_>, _>>()
+		.ok()?;
+	swap_channels(&mut pal);
+	Some(pal)
+}
+
 fn read_frames<R: Read, T, const N: usize>(
 	mut png: png::Reader<R>,
 	mut sample: impl FnMut([u8; N]) -> T,
@@ -122,13 +306,8 @@ fn read_frames<R: Read, T, const N: usize>(
 	let mut buf = vec![0; png.output_buffer_size()];
 	let mut out = Vec::new();
 	for n in 0..n_frames {
-		let frame = png.next_frame(&mut buf)?;
-		eyre::ensure!(frame.width == png.info().width >> n, "invalid frame width");
-		eyre::ensure!(
-			frame.height == png.info().height >> n,
-			"invalid frame height"
-		);
-		out.push(Raster::new_with(
+		let frame = next_frame(&mut png, &mut buf, n)?;
+		out.push(Raster::try_new_with(
 			frame.width as usize,
 			frame.height as usize,
 			buf[..frame.buffer_size()]
@@ -136,7 +315,127 @@ fn read_frames<R: Read, T, const N: usize>(
 				.copied()
 				.map(&mut sample)
 				.collect(),
-		))
+		)?)
+	}
+	Ok(out)
+}
+
+/// Reads frames with less than 8 bits per pixel, unpacking each into one byte.
+fn read_packed_frames<R: Read>(
+	mut png: png::Reader<R>,
+	bits: usize,
+) -> eyre::Result<Vec<Raster<u8>>> {
+	let n_frames = png.info().animation_control.map_or(1, |ac| ac.num_frames);
+	let mut buf = vec![0; png.output_buffer_size()];
+	let mut out = Vec::new();
+	let mask = (1u8 << bits) - 1;
+	for n in 0..n_frames {
+		let frame = next_frame(&mut png, &mut buf, n)?;
+		let width = frame.width as usize;
+		let data = buf[..frame.buffer_size()]
+			.chunks(frame.line_size)
+			.flat_map(|row| {
+				row.iter()
+					.flat_map(move |&b| (0..8 / bits).rev().map(move |i| (b >> (i * bits)) & mask))
+					.take(width)
+			})
+			.collect();
+		out.push(Raster::try_new_with(width, frame.height as usize, data)?)
 	}
 	Ok(out)
 }
+
+fn next_frame<R: Read>(
+	png: &mut png::Reader<R>,
+	buf: &mut [u8],
+	n: u32,
+) -> eyre::Result<png::OutputInfo> {
+	let frame = png.next_frame(buf)?;
+	let info = png.info();
+	let (w, h) = mip_dimensions(info.width as usize, info.height as usize, n as usize, 1);
+	eyre::ensure!(frame.width as usize == w, "invalid frame width");
+	eyre::ensure!(frame.height as usize == h, "invalid frame height");
+	Ok(frame)
+}
+
+#[test]
+fn test_read_4bit() -> eyre::Result<()> {
+	let mut data = Vec::new();
+	let mut png = png::Encoder::new(&mut data, 3, 2);
+	png.set_color(png::ColorType::Indexed);
+	png.set_depth(png::BitDepth::Four);
+	png.set_palette((0..6).flat_map(|i| [i * 40, 0, 0]).collect::<Vec<u8>>());
+	let mut png = png.write_header()?;
+	png.write_image_data(&[0x01, 0x20, 0x34, 0x50])?;
+	png.finish()?;
+
+	let Png::Indexed(pal, frames) = read(data.as_slice())? else {
+		eyre::bail!("expected indexed png")
+	};
+	assert_eq!(pal.len(), 6);
+	assert_eq!(frames.len(), 1);
+	assert_eq!(frames[0].as_slice(), &[0, 1, 2, 3, 4, 5]);
+	Ok(())
+}
+
+#[test]
+fn test_icc() -> eyre::Result<()> {
+	use std::io::Read as _;
+	let img = Png::Indexed(vec![0xFF000000], vec![Raster::new(2, 1)]);
+	let icc = b"not really a color profile";
+	let mut data = Vec::new();
+	write_with_icc(&mut data, &img, Some(icc))?;
+
+	let pos = data.windows(4).position(|a| a == b"iCCP").unwrap();
+	let plte = data.windows(4).position(|a| a == b"PLTE").unwrap();
+	assert!(pos < plte);
+	let len = u32::from_be_bytes(data[pos - 4..pos].try_into()?) as usize;
+	let body = &data[pos + 4..pos + 4 + len];
+	let name_end = ICC_PROFILE_NAME.len();
+	assert_eq!(&body[..name_end], ICC_PROFILE_NAME.as_bytes());
+	assert_eq!(body[name_end..name_end + 2], [0, 0]);
+	let mut profile = Vec::new();
+	flate2::read::ZlibDecoder::new(&body[name_end + 2..]).read_to_end(&mut profile)?;
+	assert_eq!(profile, icc);
+
+	// The decoder checks the chunk's crc
+	assert_eq!(read(data.as_slice())?, img);
+	Ok(())
+}
+
+#[test]
+fn test_unused_palette() -> eyre::Result<()> {
+	let pal = vec![0xFF000000, 0x80FF0000, 0xFF00FF00, 0x00000000, 0x12345678];
+	let img = Png::Indexed(pal.clone(), vec![Raster::new_with(2, 1, vec![0, 1])]);
+	let mut data = Vec::new();
+	write(&mut data, &img)?;
+	assert_eq!(read(data.as_slice())?, img);
+
+	// Simulate an optimizer dropping the unused entries
+	let mut rgba = pal.clone();
+	swap_channels(&mut rgba);
+	let text = format_palette(&rgba);
+	let trimmed = |plte: Vec<u8>| -> eyre::Result<Vec<u8>> {
+		let mut data = Vec::new();
+		let mut png = png::Encoder::new(&mut data, 2, 1);
+		png.set_color(png::ColorType::Indexed);
+		png.set_depth(png::BitDepth::Eight);
+		png.set_palette(plte);
+		png.set_trns(vec![0xFF, 0x80]);
+		png.add_text_chunk(PALETTE_KEYWORD.to_owned(), text.clone())?;
+		let mut png = png.write_header()?;
+		png.write_image_data(&[0, 1])?;
+		png.finish()?;
+		Ok(data)
+	};
+	let data = trimmed(vec![0, 0, 0, 0xFF, 0, 0])?;
+	assert_eq!(read(data.as_slice())?, img);
+
+	// If the remaining entries were changed, the stored palette no longer applies
+	let data = trimmed(vec![0, 0, 0, 0, 0xFF, 0])?;
+	let Png::Indexed(pal2, _) = read(data.as_slice())? else {
+		eyre::bail!("expected indexed png")
+	};
+	assert_eq!(pal2, [0xFF000000, 0x8000FF00]);
+	Ok(())
+}
@@ -0,0 +1,21 @@
+use cradle::raster::Raster;
+
+/// Compares two rasters while ignoring the alpha channel.
+///
+/// For use in tests involving formats where alpha is stored lossily, such as 1555.
+pub fn raster_eq_rgb(a: &Raster<u32>, b: &Raster<u32>) -> bool {
+	a.width() == b.width()
+		&& a.height() == b.height()
+		&& std::iter::zip(a.as_slice(), b.as_slice()).all(|(a, b)| a & 0xFFFFFF == b & 0xFFFFFF)
+}
+
+#[test]
+fn test_raster_eq_rgb() {
+	let a = Raster::new_with(2, 1, vec![0xFF123456, 0x00ABCDEF]);
+	let b = Raster::new_with(2, 1, vec![0x80123456, 0xFFABCDEF]);
+	let c = Raster::new_with(2, 1, vec![0xFF123456, 0xFFABCDEE]);
+	let d = Raster::new_with(1, 2, vec![0xFF123456, 0x00ABCDEF]);
+	assert!(raster_eq_rgb(&a, &b));
+	assert!(!raster_eq_rgb(&a, &c));
+	assert!(!raster_eq_rgb(&a, &d));
+}
@@ -1,3 +1,4 @@
+use std::cell::RefCell;
 use std::io::{self, Write};
 
 use camino::{Utf8Path, Utf8PathBuf};
@@ -6,6 +7,12 @@ use camino::{Utf8Path, Utf8PathBuf};
 pub enum Output {
 	At(Utf8PathBuf),
 	In(Utf8PathBuf),
+	Template {
+		dir: Utf8PathBuf,
+		stem: String,
+		index: usize,
+		template: NameTemplate,
+	},
 }
 
 impl Output {
@@ -13,6 +20,7 @@ impl Output {
 		match self {
 			Output::At(path) => path.to_path_buf(),
 			Output::In(path) => path.with_file_name(name),
+			Output::Template { dir, .. } => dir.join(name),
 		}
 	}
 
@@ -20,6 +28,25 @@ impl Output {
 		match self {
 			Output::At(path) => path.to_path_buf(),
 			Output::In(path) => path.with_extension(ext),
+			Output::Template {
+				stem,
+				index,
+				template,
+				..
+			} => self.with_name(&template.format(stem, ext, *index)),
+		}
+	}
+
+	/// Names the output according to `template`, if it would otherwise be derived from the input name.
+	pub fn with_template(self, template: Option<&NameTemplate>, index: usize) -> Self {
+		match (self, template) {
+			(Output::In(path), Some(template)) => Output::Template {
+				dir: path.parent().unwrap_or(Utf8Path::new("")).to_path_buf(),
+				stem: path.file_stem().unwrap_or_default().to_owned(),
+				index,
+				template: template.clone(),
+			},
+			(output, _) => output,
 		}
 	}
 
@@ -51,6 +78,194 @@ impl Output {
 	}
 }
 
+/// Creates a file for writing, or fails if it exists and `--no-clobber` is given.
+pub fn create_file(args: &crate::Args, path: impl AsRef<Utf8Path>) -> eyre::Result<std::fs::File> {
+	let path = path.as_ref();
+	if args.no_clobber {
+		match std::fs::File::options()
+			.write(true)
+			.create_new(true)
+			.open(path)
+		{
+			Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+				eyre::bail!("{path} already exists")
+			}
+			f => Ok(f?),
+		}
+	} else {
+		Ok(std::fs::File::create(path)?)
+	}
+}
+
+/// Like [`std::fs::write`], but respects `--no-clobber`.
+pub fn write_file(
+	args: &crate::Args,
+	path: impl AsRef<Utf8Path>,
+	data: impl AsRef<[u8]>,
+) -> eyre::Result<()> {
+	create_file(args, path)?.write_all(data.as_ref())?;
+	Ok(())
+}
+
+/// Reads the color profile given with `--icc`, if any.
+pub fn icc_profile(args: &crate::Args) -> eyre::Result<Option<Vec<u8>>> {
+	Ok(args.icc.as_ref().map(std::fs::read).transpose()?)
+}
+
+/// Removes a gzip or zlib wrapper, if the data has one.
+///
+/// Some distributions compress files at the filesystem level, separately from the formats' own
+/// compression.
+pub fn decompress(data: Vec<u8>) -> eyre::Result<Vec<u8>> {
+	use std::io::Read;
+	let mut out = Vec::new();
+	match data.as_slice() {
+		[0x1F, 0x8B, ..] => {
+			flate2::read::MultiGzDecoder::new(data.as_slice()).read_to_end(&mut out)?;
+		}
+		// The header is a multiple of 31, which makes false positives unlikely
+		[cmf @ 0x78, flg, ..] if u16::from_be_bytes([*cmf, *flg]) % 31 == 0 => {
+			flate2::read::ZlibDecoder::new(data.as_slice()).read_to_end(&mut out)?;
+		}
+		_ => return Ok(data),
+	}
+	Ok(out)
+}
+
+/// Maps `f` over `items` on up to `jobs` threads, keeping the original order.
+///
+/// Without `jobs`, everything runs on the current thread. Otherwise, what each item logs is held
+/// back until all are done, and then written in the order of the items.
+pub fn par_map<T: Sync, U: Send>(
+	jobs: Option<usize>,
+	items: &[T],
+	f: impl Fn(&T) -> eyre::Result<U> + Sync + Send,
+) -> eyre::Result<Vec<U>> {
+	match jobs {
+		Some(jobs) if jobs > 1 => {
+			use rayon::prelude::*;
+			let pool = rayon::ThreadPoolBuilder::new().num_threads(jobs).build()?;
+			let results = pool.install(|| {
+				items
+					.par_iter()
+					.map(|item| capture_log(|| f(item)))
+					.collect::<Vec<_>>()
+			});
+			let mut out = Vec::with_capacity(results.len());
+			for (result, log) in results {
+				LogWriter.write_all(&log)?;
+				out.push(result);
+			}
+			out.into_iter().collect()
+		}
+		_ => items.iter().map(f).collect(),
+	}
+}
+
+thread_local! {
+	/// Where [`LogWriter`] writes on this thread instead of stderr, if anywhere.
+	static CAPTURED_LOG: RefCell<Option<Vec<u8>>> = const { RefCell::new(None) };
+}
+
+/// Runs `f`, returning what it logged instead of writing it.
+fn capture_log<R>(f: impl FnOnce() -> R) -> (R, Vec<u8>) {
+	let outer = CAPTURED_LOG.replace(Some(Vec::new()));
+	let result = f();
+	let log = CAPTURED_LOG.replace(outer).unwrap_or_default();
+	(result, log)
+}
+
+/// The log output, which is stderr except where [`par_map`] needs to reorder it.
+pub struct LogWriter;
+
+impl Write for LogWriter {
+	fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+		CAPTURED_LOG.with_borrow_mut(|log| match log {
+			Some(log) => {
+				log.extend_from_slice(data);
+				Ok(data.len())
+			}
+			None => io::stderr().write(data),
+		})
+	}
+
+	fn flush(&mut self) -> io::Result<()> {
+		io::stderr().flush()
+	}
+}
+
+/// A file name pattern with `{stem}`, `{ext}`, and `{index}` placeholders.
+///
+/// `{index}` is the position of the input file on the command line, starting at zero.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate(Vec<TemplatePart>);
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum TemplatePart {
+	Literal(String),
+	Stem,
+	Ext,
+	Index,
+}
+
+impl NameTemplate {
+	pub fn format(&self, stem: &str, ext: &str, index: usize) -> String {
+		let mut out = String::new();
+		for part in &self.0 {
+			match part {
+				TemplatePart::Literal(s) => out.push_str(s),
+				TemplatePart::Stem => out.push_str(stem),
+				// Avoid a dangling dot when there is no extension, such as for directories
+				TemplatePart::Ext if ext.is_empty() => {
+					if out.ends_with('.') {
+						out.pop();
+					}
+				}
+				TemplatePart::Ext => out.push_str(ext),
+				TemplatePart::Index => out.push_str(&index.to_string()),
+			}
+		}
+		out
+	}
+}
+
+impl std::str::FromStr for NameTemplate {
+	type Err = String;
+
+	fn from_str(s: &str) -> Result<Self, Self::Err> {
+		let mut parts = Vec::new();
+		let mut rest = s;
+		while let Some(start) = rest.find('{') {
+			if start != 0 {
+				parts.push(TemplatePart::Literal(rest[..start].to_owned()));
+			}
+			let Some(end) = rest[start..].find('}') else {
+				return Err("unclosed `{`".to_owned());
+			};
+			parts.push(match &rest[start + 1..start + end] {
+				"stem" => TemplatePart::Stem,
+				"ext" => TemplatePart::Ext,
+				"index" => TemplatePart::Index,
+				name => return Err(format!("unknown placeholder `{{{name}}}`")),
+			});
+			rest = &rest[start + end + 1..];
+		}
+		if !rest.is_empty() {
+			parts.push(TemplatePart::Literal(rest.to_owned()));
+		}
+		if !parts.contains(&TemplatePart::Ext) {
+			return Err("template must contain `{ext}`".to_owned());
+		}
+		if parts
+			.iter()
+			.any(|p| matches!(p, TemplatePart::Literal(s) if s.contains(std::path::is_separator)))
+		{
+			return Err("template must not contain path separators".to_owned());
+		}
+		Ok(NameTemplate(parts))
+	}
+}
+
 pub struct MyFormatter {
 	level: usize,
 	indent_to: usize,
@@ -153,3 +368,81 @@ impl serde_json::ser::Formatter for MyFormatter {
 		Ok(())
 	}
 }
+
+#[test]
+fn test_name_template() -> eyre::Result<()> {
+	let template = "{stem}_converted.{ext}"
+		.parse::<NameTemplate>()
+		.map_err(|e| eyre::eyre!(e))?;
+	let output = Output::In("in/ch00000.itp".into()).with_template(Some(&template), 0);
+	assert_eq!(output.with_extension("png"), "in/ch00000_converted.png");
+	assert_eq!(output.with_extension(""), "in/ch00000_converted");
+
+	let template = "{index}-{stem}.{ext}"
+		.parse::<NameTemplate>()
+		.map_err(|e| eyre::eyre!(e))?;
+	let output = Output::In("in/ch00000.itp".into()).with_template(Some(&template), 3);
+	assert_eq!(output.with_extension("itc.json"), "in/3-ch00000.itc.json");
+
+	assert!("{stem}.png".parse::<NameTemplate>().is_err());
+	assert!("{stem.{ext}".parse::<NameTemplate>().is_err());
+	assert!("{name}.{ext}".parse::<NameTemplate>().is_err());
+	Ok(())
+}
+
+#[test]
+fn test_decompress() -> eyre::Result<()> {
+	use std::io::Write;
+	let raw = include_bytes!("../../samples/itp/ao__extra020.itp").to_vec();
+
+	let mut gz = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::default());
+	gz.write_all(&raw)?;
+	let gz = gz.finish()?;
+	assert_eq!(decompress(gz)?, raw);
+
+	let mut zlib = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::default());
+	zlib.write_all(&raw)?;
+	let zlib = zlib.finish()?;
+	assert_eq!(
+		cradle::itp::read(&decompress(zlib)?)?,
+		cradle::itp::read(&raw)?
+	);
+
+	assert_eq!(decompress(raw.clone())?, raw);
+	Ok(())
+}
+
+#[test]
+fn test_no_clobber() -> eyre::Result<()> {
+	let tmpdir = camino_tempfile::tempdir()?;
+	let path = tmpdir.path().join("a.png");
+	std::fs::write(&path, b"original")?;
+
+	let args = &crate::Args {
+		no_clobber: true,
+		..crate::Args::default()
+	};
+	let err = write_file(args, &path, b"new").unwrap_err();
+	assert!(err.to_string().contains("already exists"));
+	assert_eq!(std::fs::read(&path)?, b"original");
+
+	write_file(args, tmpdir.path().join("b.png"), b"new")?;
+	write_file(&crate::Args::default(), &path, b"new")?;
+	assert_eq!(std::fs::read(&path)?, b"new");
+	Ok(())
+}
+
+#[test]
+fn test_par_map_log_order() -> eyre::Result<()> {
+	let items = [30, 20, 10, 0];
+	let (result, log) = capture_log(|| {
+		par_map(Some(4), &items, |&ms| {
+			std::thread::sleep(std::time::Duration::from_millis(ms));
+			writeln!(LogWriter, "{ms}")?;
+			Ok(ms)
+		})
+	});
+	assert_eq!(result?, items);
+	assert_eq!(log, b"30\n20\n10\n0\n");
+	Ok(())
+}
@@ -0,0 +1,91 @@
+use std::panic::{catch_unwind, AssertUnwindSafe};
+
+use camino::{Utf8Path, Utf8PathBuf};
+
+/// Extensions of the files that are checked.
+const EXTENSIONS: &[&str] = &["itp", "itc"];
+
+#[derive(Debug, Clone, Default)]
+pub struct Report {
+	pub ok: usize,
+	pub failed: Vec<(Utf8PathBuf, String)>,
+}
+
+/// Recursively finds all supported files under `paths` and checks that they survive a read/write
+/// round trip.
+pub fn validate(paths: &[Utf8PathBuf]) -> eyre::Result<Report> {
+	let mut files = Vec::new();
+	for path in paths {
+		collect(path, &mut files)?;
+	}
+	files.sort();
+
+	let mut report = Report::default();
+	for file in files {
+		let _span = tracing::info_span!("validate", path = %file).entered();
+		let result = std::fs::read(&file)
+			.map_err(eyre::Report::from)
+			.and_then(|data| {
+				catch_unwind(AssertUnwindSafe(|| validate_file(&file, &data)))
+					.unwrap_or_else(|_| Err(eyre::eyre!("panic")))
+			});
+		match result {
+			Ok(()) => report.ok += 1,
+			Err(e) => {
+				tracing::error!("{e}");
+				report.failed.push((file, e.to_string()));
+			}
+		}
+	}
+	Ok(report)
+}
+
+fn collect(path: &Utf8Path, files: &mut Vec<Utf8PathBuf>) -> eyre::Result<()> {
+	if path.is_dir() {
+		for entry in path.read_dir_utf8()? {
+			collect(entry?.path(), files)?;
+		}
+	} else if path
+		.extension()
+		.is_some_and(|ext| EXTENSIONS.contains(&ext))
+	{
+		files.push(path.to_path_buf());
+	}
+	Ok(())
+}
+
+fn validate_file(path: &Utf8Path, data: &[u8]) -> eyre::Result<()> {
+	match path.extension() {
+		Some("itp") => {
+			let itp = cradle::itp::read(data)?;
+			let itp2 = cradle::itp::read(&cradle::itp::write(&itp)?)?;
+			eyre::ensure!(itp == itp2, "itp changed after writing");
+		}
+		Some("itc") => {
+			let itc = cradle::itc::read(data)?;
+			let itc2 = cradle::itc::read(&cradle::itc::write(&itc)?)?;
+			eyre::ensure!(itc == itc2, "itc changed after writing");
+		}
+		_ => unreachable!(),
+	}
+	Ok(())
+}
+
+#[test]
+fn test_validate() -> eyre::Result<()> {
+	let tmpdir = camino_tempfile::tempdir()?;
+	let dir = tmpdir.path();
+	std::fs::create_dir_all(dir.join("a/b"))?;
+	let itp = include_bytes!("../../samples/itp/ao__extra020.itp");
+	let itc = include_bytes!("../../samples/itc/ao__ch00051.itc");
+	std::fs::write(dir.join("a/good.itp"), itp)?;
+	std::fs::write(dir.join("a/b/good.itc"), itc)?;
+	std::fs::write(dir.join("a/b/bad.itp"), &itp[..100])?;
+	std::fs::write(dir.join("a/b/ignored.png"), b"")?;
+
+	let report = validate(&[dir.to_path_buf()])?;
+	assert_eq!(report.ok, 2);
+	assert_eq!(report.failed.len(), 1);
+	assert_eq!(report.failed[0].0, dir.join("a/b/bad.itp"));
+	Ok(())
+}
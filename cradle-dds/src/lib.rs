@@ -112,6 +112,31 @@ impl Default for Dds {
 	}
 }
 
+/// Computes the [`DDSCAPS`] bits that match the rest of the header.
+///
+/// Cubemaps are recognized by [`RESOURCE_MISC::TEXTURECUBE`] or an already present
+/// [`DDSCAPS::CUBEMAP`], and volumes by a depth above 1 or [`RESOURCE_DIMENSION::TEXTURE3D`].
+/// Volumes additionally need [`DDSD::DEPTH`] in the flags, which is not handled here.
+pub fn compute_caps(dds: &Dds) -> u128 {
+	let dx10 = dds.dx10.as_ref();
+	let cube = dds.caps & DDSCAPS::CUBEMAP != 0
+		|| dx10.is_some_and(|dx10| dx10.misc_flag & RESOURCE_MISC::TEXTURECUBE != 0);
+	let volume = dds.depth > 1
+		|| dx10.is_some_and(|dx10| dx10.resource_dimension == RESOURCE_DIMENSION::TEXTURE3D);
+
+	let mut caps = DDSCAPS::TEXTURE;
+	if dds.mip_map_count > 1 {
+		caps |= DDSCAPS::MIPMAP | DDSCAPS::COMPLEX;
+	}
+	if cube {
+		caps |= DDSCAPS::CUBEMAP | DDSCAPS::CUBEMAP_ALLFACES | DDSCAPS::COMPLEX;
+	}
+	if volume {
+		caps |= DDSCAPS::VOLUME | DDSCAPS::COMPLEX;
+	}
+	caps
+}
+
 #[derive(Debug, Clone)]
 pub struct PixelFormat {
 	/// See [`DDPF`] for values.
@@ -251,3 +276,33 @@ trait WriteData: Write {
 	}
 }
 impl<T: Write> WriteData for T {}
+
+#[test]
+fn test_caps() {
+	use DDSCAPS as C;
+	let mut header = Dds {
+		width: 4,
+		height: 4,
+		..Dds::default()
+	};
+	assert_eq!(compute_caps(&header), C::TEXTURE);
+
+	header.mip_map_count = 3;
+	assert_eq!(compute_caps(&header), C::TEXTURE | C::MIPMAP | C::COMPLEX);
+
+	header.mip_map_count = 1;
+	header.pixel_format.flags |= DDPF::FOURCC;
+	header.pixel_format.four_cc = *b"DX10";
+	header.dx10 = Some(Dx10Header {
+		misc_flag: RESOURCE_MISC::TEXTURECUBE,
+		..Dx10Header::default()
+	});
+	assert_eq!(
+		compute_caps(&header),
+		C::TEXTURE | C::COMPLEX | C::CUBEMAP | C::CUBEMAP_ALLFACES
+	);
+
+	header.dx10 = None;
+	header.depth = 4;
+	assert_eq!(compute_caps(&header), C::TEXTURE | C::COMPLEX | C::VOLUME);
+}
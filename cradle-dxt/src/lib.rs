@@ -1,8 +1,15 @@
 mod bc7;
 mod s3tc;
+mod solid;
 
 pub use s3tc::bc1 as decode_bc1;
 pub use s3tc::bc2 as decode_bc2;
 pub use s3tc::bc3 as decode_bc3;
 
 pub use bc7::decode as decode_bc7;
+
+pub use solid::bc1 as encode_bc1_solid;
+pub use solid::bc2 as encode_bc2_solid;
+pub use solid::bc3 as encode_bc3_solid;
+pub use solid::bc7 as encode_bc7_solid;
+pub use solid::solid_color;
@@ -0,0 +1,140 @@
+// Encoding of single-color blocks
+//
+// A block where all pixels are equal can be encoded without any searching, by picking for each
+// channel the pair of endpoints whose interpolation is closest to the wanted value.
+
+use std::sync::OnceLock;
+
+/// Returns the color of the block, if all its pixels are the same.
+#[inline]
+pub fn solid_color(block: &[u32; 16]) -> Option<u32> {
+	block.iter().all(|&c| c == block[0]).then_some(block[0])
+}
+
+/// Encodes a single-color BC1 block.
+///
+/// Colors with alpha below 128 become fully transparent black, since that is all BC1 can express.
+/// Other colors are approximated as closely as the 5:6:5 endpoints allow.
+pub fn bc1(argb: u32) -> u64 {
+	if argb >> 24 < 0x80 {
+		// Three-color mode, with every pixel using the transparent index
+		return 0xFFFFFFFF << 32;
+	}
+	color_block(argb)
+}
+
+/// Encodes a single-color BC2 block. Alpha is rounded to four bits.
+pub fn bc2(argb: u32) -> u128 {
+	let a = (((argb >> 24) + 8) / 17) as u128;
+	let alpha = (0..16).fold(0, |acc, _| acc << 4 | a);
+	alpha | (color_block(argb) as u128) << 64
+}
+
+/// Encodes a single-color BC3 block. Alpha is exact.
+pub fn bc3(argb: u32) -> u128 {
+	let a = (argb >> 24) as u128;
+	// Both endpoints are the same, so index 0 gives exactly that value
+	let alpha = a | a << 8;
+	alpha | (color_block(argb) as u128) << 64
+}
+
+/// Encodes a single-color BC7 block. This is exact for all colors.
+///
+/// Uses mode 5, whose 8-bit alpha endpoints can express any alpha, and whose 7-bit color
+/// endpoints reach every 8-bit value at the first interpolation step.
+pub fn bc7(argb: u32) -> u128 {
+	let [b, g, r, a] = argb.to_le_bytes();
+	let table = bc7_table();
+	let mut block = 1u128 << 5; // mode 5, rotation 0
+	let mut pos = 8;
+	for c in [r, g, b] {
+		let [e0, e1] = table[c as usize];
+		block |= (e0 as u128) << pos | (e1 as u128) << (pos + 7);
+		pos += 14;
+	}
+	block |= (a as u128) << pos | (a as u128) << (pos + 8);
+	pos += 16;
+	// Color index 1 everywhere; the anchor index has one bit less
+	block |= 1 << pos;
+	pos += 1;
+	for i in 0..15 {
+		block |= 1 << (pos + 2 * i);
+	}
+	// Alpha indices are all 0
+	block
+}
+
+fn color_block(argb: u32) -> u64 {
+	let [b, g, r, _] = argb.to_le_bytes();
+	let [r0, r1] = bc1_table::<5>()[r as usize];
+	let [g0, g1] = bc1_table::<6>()[g as usize];
+	let [b0, b1] = bc1_table::<5>()[b as usize];
+	let c0 = (r0 as u16) << 11 | (g0 as u16) << 5 | b0 as u16;
+	let c1 = (r1 as u16) << 11 | (g1 as u16) << 5 | b1 as u16;
+	// The wanted color is at index 2, a third of the way from c0 to c1. That needs four-color
+	// mode, which requires c0 > c1; otherwise swap them and use index 3, which is the same point.
+	let (c0, c1, indices) = match c0.cmp(&c1) {
+		std::cmp::Ordering::Greater => (c0, c1, 0xAAAAAAAAu32),
+		std::cmp::Ordering::Less => (c1, c0, 0xFFFFFFFF),
+		std::cmp::Ordering::Equal => (c0, c1, 0x00000000),
+	};
+	c0 as u64 | (c1 as u64) << 16 | (indices as u64) << 32
+}
+
+/// For each 8-bit value, the pair of `BITS`-bit endpoints whose ⅓ interpolation is closest to it.
+fn bc1_table<const BITS: u32>() -> &'static [[u8; 2]; 256] {
+	static TABLE5: OnceLock<[[u8; 2]; 256]> = OnceLock::new();
+	static TABLE6: OnceLock<[[u8; 2]; 256]> = OnceLock::new();
+	let table = if BITS == 5 { &TABLE5 } else { &TABLE6 };
+	table.get_or_init(|| {
+		let expand = |x: u16| x << (8 - BITS) | x >> (2 * BITS - 8);
+		best_pairs(1 << BITS, |a, b| (2 * expand(a) + expand(b)) / 3)
+	})
+}
+
+/// For each 8-bit value, the pair of 7-bit endpoints whose first 2-bit BC7 interpolation is it.
+fn bc7_table() -> &'static [[u8; 2]; 256] {
+	static TABLE: OnceLock<[[u8; 2]; 256]> = OnceLock::new();
+	TABLE.get_or_init(|| {
+		let expand = |x: u16| x << 1 | x >> 6;
+		best_pairs(128, |a, b| {
+			((64 - 21) * expand(a) + 21 * expand(b) + 32) >> 6
+		})
+	})
+}
+
+fn best_pairs(n: u16, interpolate: impl Fn(u16, u16) -> u16) -> [[u8; 2]; 256] {
+	std::array::from_fn(|v| {
+		let mut best = ([0, 0], u16::MAX);
+		for a in 0..n {
+			for b in 0..n {
+				let err = interpolate(a, b).abs_diff(v as u16);
+				if err < best.1 {
+					best = ([a as u8, b as u8], err);
+				}
+			}
+		}
+		best.0
+	})
+}
+
+#[test]
+fn test_solid() {
+	let colors = [0xFF000000, 0xFFFFFFFF, 0xFFFF0000, 0xFF00FF00, 0xFF0000FF];
+	for argb in colors {
+		assert_eq!(crate::decode_bc1(bc1(argb)), [argb; 16], "{argb:08X}");
+		assert_eq!(crate::decode_bc3(bc3(argb)), [argb; 16], "{argb:08X}");
+	}
+	assert_eq!(crate::decode_bc1(bc1(0x40123456)), [0; 16]);
+	assert_eq!(crate::decode_bc2(bc2(0x88FFFFFF)), [0x88FFFFFF; 16]);
+	assert_eq!(crate::decode_bc3(bc3(0x12FFFFFF)), [0x12FFFFFF; 16]);
+
+	for argb in [0x00000000, 0x12345678, 0x80FF7F01, 0xFEDCBA98, 0xFFFFFFFF] {
+		assert_eq!(crate::decode_bc7(bc7(argb)), [argb; 16], "{argb:08X}");
+	}
+
+	let mut block = [0x12345678; 16];
+	assert_eq!(solid_color(&block), Some(0x12345678));
+	block[15] = 0;
+	assert_eq!(solid_color(&block), None);
+}
@@ -0,0 +1,433 @@
+use crate::itp::Argb16Mode;
+use crate::raster::Raster;
+
+/// Swaps the red and blue channels of each pixel, converting between ARGB and ABGR.
+///
+/// When read as little-endian bytes, this converts between `[b, g, r, a]` and `[r, g, b, a]`.
+pub fn swap_channels(data: &mut [u32]) {
+	#[cfg(feature = "simd")]
+	{
+		use std::simd::Simd;
+		let (head, body, tail) = data.as_simd_mut::<16>();
+		for v in body {
+			let x = *v;
+			*v = x & Simd::splat(0xFF00FF00)
+				| x >> Simd::splat(16) & Simd::splat(0xFF)
+				| (x & Simd::splat(0xFF)) << Simd::splat(16);
+		}
+		swap_channels_scalar(head);
+		swap_channels_scalar(tail);
+	}
+	#[cfg(not(feature = "simd"))]
+	swap_channels_scalar(data);
+}
+
+#[inline(always)]
+fn swap_channels_scalar(data: &mut [u32]) {
+	// Simple enough that it autovectorizes.
+	for x in data {
+		*x = *x & 0xFF00FF00 | *x >> 16 & 0xFF | (*x & 0xFF) << 16;
+	}
+}
+
+/// Multiplies the color channels of an ARGB pixel by its alpha.
+pub fn premultiply(argb: u32) -> u32 {
+	let [b, g, r, a] = argb.to_le_bytes();
+	let mul = |c: u8| ((c as u32 * a as u32 + 127) / 255) as u8;
+	u32::from_le_bytes([mul(b), mul(g), mul(r), a])
+}
+
+/// Expands a 16-bit pixel to 32-bit ARGB.
+///
+/// Each channel's high bits are repeated into the low bits, so that the maximum value maps to 0xFF.
+pub fn unpack_argb16(mode: Argb16Mode, argb: u16) -> u32 {
+	let c = |shift: u32, bits: u32| {
+		let v = (argb as u32 >> shift) & ((1 << bits) - 1);
+		let v = v << (8 - bits);
+		let mut out = v;
+		let mut n = bits;
+		while n < 8 {
+			out |= v >> n;
+			n += bits;
+		}
+		out
+	};
+	let (a, r, g, b) = match mode {
+		Argb16Mode::Mode1 => (c(15, 1), c(10, 5), c(5, 5), c(0, 5)),
+		Argb16Mode::Mode2 => (c(12, 4), c(8, 4), c(4, 4), c(0, 4)),
+		Argb16Mode::Mode3 => (0xFF, c(11, 5), c(5, 6), c(0, 5)),
+	};
+	a << 24 | r << 16 | g << 8 | b
+}
+
+/// Reduces a 32-bit ARGB pixel to 16-bit, rounding each channel to the nearest value.
+///
+/// This is the inverse of [`unpack_argb16`], as far as the precision allows.
+pub fn pack_argb16(mode: Argb16Mode, argb: u32) -> u16 {
+	let [b, g, r, a] = argb.to_le_bytes();
+	let c = |v: u8, shift: u32, bits: u32| {
+		let max = (1 << bits) - 1;
+		((v as u32 * max + 127) / 255) << shift
+	};
+	let v = match mode {
+		Argb16Mode::Mode1 => c(a, 15, 1) | c(r, 10, 5) | c(g, 5, 5) | c(b, 0, 5),
+		Argb16Mode::Mode2 => c(a, 12, 4) | c(r, 8, 4) | c(g, 4, 4) | c(b, 0, 4),
+		Argb16Mode::Mode3 => c(r, 11, 5) | c(g, 5, 6) | c(b, 0, 5),
+	};
+	v as u16
+}
+
+/// Reduces an image to 16-bit color, with Floyd–Steinberg dithering.
+///
+/// The rounding error of each channel is spread to the neighboring pixels, which trades banding
+/// for noise.
+pub fn dither_argb16(mode: Argb16Mode, raster: &Raster<u32>) -> Raster<u16> {
+	let w = raster.width();
+	// Offset by one, so that the pixel to the left of the first does not need special casing
+	let mut cur = vec![[0f32; 4]; w + 2];
+	let mut next = vec![[0f32; 4]; w + 2];
+	let mut data = Vec::with_capacity(w * raster.height());
+	for y in 0..raster.height() {
+		for x in 0..w {
+			let px = raster[[x, y]].to_le_bytes();
+			let v: [f32; 4] =
+				std::array::from_fn(|i| (px[i] as f32 + cur[x + 1][i]).clamp(0.0, 255.0));
+			let packed = pack_argb16(mode, u32::from_le_bytes(v.map(|c| c.round() as u8)));
+			let out = unpack_argb16(mode, packed).to_le_bytes();
+			for i in 0..4 {
+				let e = v[i] - out[i] as f32;
+				cur[x + 2][i] += e * 7.0 / 16.0;
+				next[x][i] += e * 3.0 / 16.0;
+				next[x + 1][i] += e * 5.0 / 16.0;
+				next[x + 2][i] += e * 1.0 / 16.0;
+			}
+			data.push(packed);
+		}
+		std::mem::swap(&mut cur, &mut next);
+		next.fill([0.0; 4]);
+	}
+	Raster::new_with(w, raster.height(), data)
+}
+
+/// Converts a BT.601 limited-range YUV pixel to ARGB.
+pub fn yuv_to_argb(y: u8, u: u8, v: u8, a: u8) -> u32 {
+	let c = 298 * (y as i32 - 16);
+	let d = u as i32 - 128;
+	let e = v as i32 - 128;
+	let clamp = |x: i32| ((x + 128) >> 8).clamp(0, 255) as u8;
+	let r = clamp(c + 409 * e);
+	let g = clamp(c - 100 * d - 208 * e);
+	let b = clamp(c + 516 * d);
+	u32::from_le_bytes([b, g, r, a])
+}
+
+/// Unpacks an `R9G9B9E5_SHAREDEXP` pixel into linear RGB.
+///
+/// Each channel is a 9-bit mantissa, scaled by a 5-bit exponent shared between all three.
+pub fn rgb9e5_to_float(rgbe: u32) -> [f32; 3] {
+	let scale = 2f32.powi((rgbe >> 27) as i32 - 15 - 9);
+	let r = rgbe & 0x1FF;
+	let g = (rgbe >> 9) & 0x1FF;
+	let b = (rgbe >> 18) & 0x1FF;
+	[r as f32 * scale, g as f32 * scale, b as f32 * scale]
+}
+
+/// Converts an sRGB-encoded channel to linear light, in 0..=1.
+pub fn srgb_to_linear(c: u8) -> f32 {
+	let c = c as f32 / 255.0;
+	if c <= 0.04045 {
+		c / 12.92
+	} else {
+		((c + 0.055) / 1.055).powf(2.4)
+	}
+}
+
+/// Converts linear light, in 0..=1, to an sRGB-encoded channel.
+pub fn linear_to_srgb(c: f32) -> u8 {
+	let c = if c <= 0.0031308 {
+		c * 12.92
+	} else {
+		1.055 * c.powf(1.0 / 2.4) - 0.055
+	};
+	(c * 255.0).round().clamp(0.0, 255.0) as u8
+}
+
+/// Halves the size of an ARGB image by averaging each 2×2 block.
+///
+/// If `srgb` is set, the color channels are averaged in linear light, which keeps the brightness
+/// of fine detail. Alpha is always averaged as is.
+/// With odd dimensions, the last row or column is dropped.
+pub fn downsample(raster: &Raster<u32>, srgb: bool) -> Raster<u32> {
+	let w = raster.width() / 2;
+	let h = raster.height() / 2;
+	let mut data = Vec::with_capacity(w * h);
+	for y in 0..h {
+		for x in 0..w {
+			let px = [
+				raster[[2 * x, 2 * y]],
+				raster[[2 * x + 1, 2 * y]],
+				raster[[2 * x, 2 * y + 1]],
+				raster[[2 * x + 1, 2 * y + 1]],
+			]
+			.map(u32::to_le_bytes);
+			let avg = |i: usize| {
+				if srgb && i != 3 {
+					linear_to_srgb(px.iter().map(|p| srgb_to_linear(p[i])).sum::<f32>() / 4.0)
+				} else {
+					((px.iter().map(|p| p[i] as u32).sum::<u32>() + 2) / 4) as u8
+				}
+			};
+			data.push(u32::from_le_bytes([avg(0), avg(1), avg(2), avg(3)]));
+		}
+	}
+	Raster::new_with(w, h, data)
+}
+
+/// Generates a mipmap chain from `raster`, starting with a copy of it.
+///
+/// Each level is half the size of the previous, until either dimension reaches 1.
+pub fn mipmaps(raster: &Raster<u32>, srgb: bool) -> Vec<Raster<u32>> {
+	let mut mips = vec![raster.clone()];
+	while let Some(last) = mips.last().filter(|a| a.width() >= 2 && a.height() >= 2) {
+		let next = downsample(last, srgb);
+		mips.push(next);
+	}
+	mips
+}
+
+/// Sets the color of fully transparent pixels to black.
+pub fn clear_transparent(raster: &mut Raster<u32>) {
+	for a in raster.as_mut_slice() {
+		if *a >> 24 == 0 {
+			*a = 0;
+		}
+	}
+}
+
+/// Gives fully transparent pixels the color of the nearest visible ones, keeping their alpha at 0.
+///
+/// Pixels are filled one ring at a time outwards from the visible ones, each taking the average of
+/// the already filled pixels among its eight neighbors. This keeps filtering and block compression
+/// from pulling unrelated colors into the edges. If nothing is visible, everything becomes black.
+pub fn bleed_transparent(raster: &mut Raster<u32>) {
+	let (w, h) = (raster.width(), raster.height());
+	let mut filled = raster
+		.as_slice()
+		.iter()
+		.map(|a| a >> 24 != 0)
+		.collect::<Vec<_>>();
+	if !filled.contains(&true) {
+		clear_transparent(raster);
+		return;
+	}
+	let neighbors = move |i: usize| {
+		let (x, y) = (i % w, i / w);
+		(y.saturating_sub(1)..(y + 2).min(h))
+			.flat_map(move |y| (x.saturating_sub(1)..(x + 2).min(w)).map(move |x| y * w + x))
+			.filter(move |&j| j != i)
+	};
+
+	let mut queued = filled.clone();
+	let mut ring = (0..w * h)
+		.filter(|&i| !filled[i] && neighbors(i).any(|j| filled[j]))
+		.collect::<Vec<_>>();
+	ring.iter().for_each(|&i| queued[i] = true);
+	while !ring.is_empty() {
+		let colors = ring
+			.iter()
+			.map(|&i| {
+				let mut sum = [0; 3];
+				let mut n = 0;
+				for j in neighbors(i).filter(|&j| filled[j]) {
+					let c = raster.as_slice()[j].to_le_bytes();
+					(0..3).for_each(|k| sum[k] += c[k] as u32);
+					n += 1;
+				}
+				let [b, g, r] = sum.map(|s| ((s + n / 2) / n) as u8);
+				u32::from_le_bytes([b, g, r, 0])
+			})
+			.collect::<Vec<_>>();
+		for (&i, c) in ring.iter().zip(colors) {
+			raster.as_mut_slice()[i] = c;
+			filled[i] = true;
+		}
+		let mut next = Vec::new();
+		for &i in &ring {
+			for j in neighbors(i) {
+				if !queued[j] {
+					queued[j] = true;
+					next.push(j);
+				}
+			}
+		}
+		ring = next;
+	}
+}
+
+/// Finds the palette entry closest to `argb`, by squared distance over all four channels.
+///
+/// Ties go to the earliest entry. Panics if the palette is empty.
+pub fn nearest_color(pal: &[u32], argb: u32) -> usize {
+	let dist = |c: u32| {
+		let (a, b) = (c.to_le_bytes(), argb.to_le_bytes());
+		(0..4)
+			.map(|i| (a[i] as i32 - b[i] as i32).pow(2) as u32)
+			.sum::<u32>()
+	};
+	(0..pal.len()).min_by_key(|&i| dist(pal[i])).unwrap()
+}
+
+#[test]
+fn test_unpack_argb16() {
+	assert_eq!(unpack_argb16(Argb16Mode::Mode1, 0xFFFF), 0xFFFFFFFF);
+	assert_eq!(unpack_argb16(Argb16Mode::Mode1, 0x7C00), 0x00FF0000);
+	assert_eq!(unpack_argb16(Argb16Mode::Mode2, 0x8F21), 0x88FF2211);
+	assert_eq!(unpack_argb16(Argb16Mode::Mode3, 0x07E0), 0xFF00FF00);
+	assert_eq!(unpack_argb16(Argb16Mode::Mode3, 0x8010), 0xFF840084);
+}
+
+#[test]
+fn test_premultiply() {
+	assert_eq!(premultiply(0x80FFFFFF), 0x80808080);
+	assert_eq!(premultiply(0xFF123456), 0xFF123456);
+	assert_eq!(premultiply(0x00123456), 0x00000000);
+}
+
+#[test]
+fn test_swap_channels() {
+	let mut data = (0..2048 * 2048 + 7)
+		.map(|i: u32| i.wrapping_mul(0x9E3779B9))
+		.collect::<Vec<_>>();
+	let expected = data
+		.iter()
+		.map(|a| {
+			let [b, g, r, a] = a.to_le_bytes();
+			u32::from_le_bytes([r, g, b, a])
+		})
+		.collect::<Vec<_>>();
+	swap_channels(&mut data);
+	assert_eq!(data, expected);
+}
+
+#[test]
+fn test_yuv_to_argb() {
+	assert_eq!(yuv_to_argb(16, 128, 128, 0xFF), 0xFF000000);
+	assert_eq!(yuv_to_argb(235, 128, 128, 0xFF), 0xFFFFFFFF);
+	assert_eq!(yuv_to_argb(81, 90, 240, 0x80), 0x80FF0000);
+}
+
+#[test]
+fn test_rgb9e5_to_float() {
+	assert_eq!(rgb9e5_to_float(0), [0.0, 0.0, 0.0]);
+	// Exponent 16, so a mantissa of 256 is 256 * 2^-8 = 1.0
+	assert_eq!(rgb9e5_to_float(16 << 27 | 256), [1.0, 0.0, 0.0]);
+	assert_eq!(
+		rgb9e5_to_float(17 << 27 | 64 << 18 | 128 << 9 | 511),
+		[3.9921875, 1.0, 0.5]
+	);
+}
+
+#[test]
+fn test_downsample() {
+	let checker = Raster::new_with(2, 2, vec![0xFF000000, 0x00FFFFFF, 0x00FFFFFF, 0xFF000000]);
+	assert_eq!(downsample(&checker, true).as_slice(), [0x80BCBCBC]);
+	assert_eq!(downsample(&checker, false).as_slice(), [0x80808080]);
+
+	let mips = mipmaps(&Raster::new(8, 2), true);
+	let sizes = mips
+		.iter()
+		.map(|a| (a.width(), a.height()))
+		.collect::<Vec<_>>();
+	assert_eq!(sizes, [(8, 2), (4, 1)]);
+}
+
+#[test]
+fn test_pack_argb16() {
+	for mode in [Argb16Mode::Mode1, Argb16Mode::Mode2, Argb16Mode::Mode3] {
+		for v in 0..=u16::MAX {
+			assert_eq!(pack_argb16(mode, unpack_argb16(mode, v)), v);
+		}
+	}
+}
+
+#[test]
+fn test_clean_transparent() {
+	#[rustfmt::skip]
+	let raster = Raster::new_with(4, 3, vec![
+		0xFF102030, 0x00FFFFFF, 0x00123456, 0x00ABCDEF,
+		0x80FF0000, 0x00FFFFFF, 0x00123456, 0x00ABCDEF,
+		0x00FFFFFF, 0x00FFFFFF, 0x00123456, 0x00ABCDEF,
+	]);
+	let visible = |r: &Raster<u32>| {
+		r.as_slice()
+			.iter()
+			.zip(raster.as_slice())
+			.all(|(a, b)| b >> 24 == 0 || a == b)
+	};
+
+	let mut cleared = raster.clone();
+	clear_transparent(&mut cleared);
+	assert!(visible(&cleared));
+	assert_eq!(cleared.as_slice()[1..4], [0, 0, 0]);
+
+	let mut bled = raster.clone();
+	bleed_transparent(&mut bled);
+	assert!(visible(&bled));
+	// The first ring averages the visible pixels next to it
+	assert_eq!(bled[[1, 0]], 0x00881018);
+	assert_eq!(bled[[0, 2]], 0x00FF0000);
+	// and the next ring averages the first
+	assert_eq!(bled[[2, 0]], 0x00881018);
+	assert_eq!(bled[[2, 1]], 0x00B00B10);
+	assert_eq!(bled[[3, 2]] >> 24, 0);
+
+	let mut empty = Raster::splat(2, 2, 0x00FFFFFF);
+	bleed_transparent(&mut empty);
+	assert_eq!(empty.as_slice(), [0; 4]);
+}
+
+#[test]
+fn test_dither_argb16() {
+	// A gentle gradient, which bands heavily in 5 bits per channel
+	let (w, h) = (64, 16);
+	let gradient = Raster::new_with(
+		w,
+		h,
+		(0..w * h)
+			.map(|i| 0xFF000000 | (i % w) as u32 * 0x010101)
+			.collect(),
+	);
+	let plain = gradient.map(|&a| pack_argb16(Argb16Mode::Mode1, a));
+	let dithered = dither_argb16(Argb16Mode::Mode1, &gradient);
+
+	// Averaged over each column, the dithered image should follow the gradient more closely
+	let column_error = |r: &Raster<u16>| {
+		(0..w)
+			.map(|x| {
+				let sum = (0..h)
+					.map(|y| unpack_argb16(Argb16Mode::Mode1, r[[x, y]]) & 0xFF)
+					.sum::<u32>();
+				(sum as f32 / h as f32 - x as f32).abs()
+			})
+			.sum::<f32>()
+	};
+	assert!(column_error(&dithered) < column_error(&plain) / 2.0);
+
+	let distinct = |r: &Raster<u16>| {
+		let mut v = r.as_slice()[..w].to_vec();
+		v.sort();
+		v.dedup();
+		v.len()
+	};
+	assert!(distinct(&dithered) >= distinct(&plain));
+}
+
+#[test]
+fn test_nearest_color() {
+	let pal = [0xFF000000, 0xFFFFFFFF, 0xFFFF0000, 0x00000000];
+	assert_eq!(nearest_color(&pal, 0xFF202020), 0);
+	assert_eq!(nearest_color(&pal, 0xFFE0E0E0), 1);
+	assert_eq!(nearest_color(&pal, 0xFFC01010), 2);
+	assert_eq!(nearest_color(&pal, 0x10000000), 3);
+	assert_eq!(nearest_color(&pal[..3], 0xFFFF0000), 2);
+}
@@ -0,0 +1,51 @@
+use crate::{itc, itp, permute};
+
+/// Any error produced by this crate.
+///
+/// Each module has its own error types, which describe the failure in more detail. This type
+/// merely wraps them, so that a single `?` works across modules.
+#[derive(Debug, thiserror::Error)]
+#[non_exhaustive]
+pub enum Error {
+	#[error(transparent)]
+	ItpRead(#[from] itp::ReadError),
+
+	#[error(transparent)]
+	ItpWrite(#[from] itp::WriteError),
+
+	#[error(transparent)]
+	ItcRead(#[from] itc::ReadError),
+
+	#[error(transparent)]
+	ItcWrite(#[from] itc::WriteError),
+
+	#[error(transparent)]
+	Swizzle(#[from] permute::SwizzleError),
+}
+
+#[test]
+fn test_from() {
+	fn assert_from<T: Into<Error>>() {}
+	assert_from::<itp::ReadError>();
+	assert_from::<itp::WriteError>();
+	assert_from::<itc::ReadError>();
+	assert_from::<itc::WriteError>();
+	assert_from::<permute::SwizzleError>();
+
+	fn read_both(itp: &[u8], itc: &[u8]) -> Result<(), Error> {
+		itp::read(itp)?;
+		itc::read(itc)?;
+		Ok(())
+	}
+	assert!(matches!(read_both(b"", b""), Err(Error::ItpRead(_))));
+	let itp = itp::write(&itp::Itp::new(
+		itp::ItpRevision::V3,
+		itp::ImageData::Argb32(vec![crate::raster::Raster::new(4, 4)]),
+	))
+	.unwrap();
+	assert!(matches!(read_both(&itp, b""), Err(Error::ItcRead(_))));
+
+	let mut data = vec![0u32; 3];
+	let err = (|| -> Result<(), Error> { Ok(permute::try_swizzle(&mut data, 2, 2, 1, 1)?) })();
+	assert!(matches!(err, Err(Error::Swizzle(_))));
+}
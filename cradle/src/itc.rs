@@ -114,6 +114,27 @@ impl Default for Frame {
 pub struct Itc {
 	pub frames: [Frame; 128],
 	pub palette: Option<Vec<u32>>,
+	/// Bytes after the end of the last frame. Normally empty, but some files, such as Geofront's,
+	/// have one or two extra bytes there.
+	pub trailing: Vec<u8>,
+}
+
+impl Itc {
+	/// Iterates over the frames that have an image, along with their index and the image.
+	pub fn frames(&self) -> impl Iterator<Item = (usize, &Frame, &[u8])> {
+		self.frames
+			.iter()
+			.enumerate()
+			.filter_map(|(i, f)| Some((i, f, f.itp.as_deref()?)))
+	}
+
+	/// Like [`frames`](Self::frames), but mutable.
+	pub fn frames_mut(&mut self) -> impl Iterator<Item = (usize, &mut Frame)> {
+		self.frames
+			.iter_mut()
+			.enumerate()
+			.filter(|(_, f)| f.itp.is_some())
+	}
 }
 
 impl Default for Itc {
@@ -121,6 +142,7 @@ impl Default for Itc {
 		Self {
 			frames: std::array::from_fn(|_| Default::default()),
 			palette: Default::default(),
+			trailing: Default::default(),
 		}
 	}
 }
@@ -135,6 +157,8 @@ pub fn read(data: &[u8]) -> Result<Itc, ReadError> {
 	};
 
 	let mut frames = std::array::from_fn(|_| Frame::default());
+	// Where the last frame's data ends, since they can be stored in any order
+	let mut data_end = 0;
 
 	for frame in &mut frames {
 		let start = f.u32()? as usize;
@@ -142,6 +166,7 @@ pub fn read(data: &[u8]) -> Result<Itc, ReadError> {
 		if (start, length) != (0, 0) {
 			frame.order = start;
 			frame.itp = Some(f.at(start)?.slice(length)?.to_vec());
+			data_end = data_end.max(start + length);
 		}
 	}
 
@@ -180,7 +205,13 @@ pub fn read(data: &[u8]) -> Result<Itc, ReadError> {
 		None
 	};
 
-	Ok(Itc { frames, palette })
+	let trailing = data[data_end.max(f.pos())..].to_vec();
+
+	Ok(Itc {
+		frames,
+		palette,
+		trailing,
+	})
 }
 
 pub fn write(itc: &Itc) -> Result<Vec<u8>, WriteError> {
@@ -203,15 +234,20 @@ pub fn write(itc: &Itc) -> Result<Vec<u8>, WriteError> {
 		f.slice(b"V101");
 	}
 
-	let mut outputs = Vec::new();
+	let mut outputs = Vec::<(usize, &[u8], Writer)>::new();
 
 	for frame in &itc.frames {
 		if let Some(itp) = &frame.itp {
-			let mut g = Writer::new();
-			slice.label32(g.here());
-			g.slice(itp);
-			slice.u32(g.len() as u32);
-			outputs.push((frame.order, g));
+			// Frames with the same order and data share a single copy
+			let shared = outputs
+				.iter()
+				.position(|(order, data, _)| *order == frame.order && *data == itp.as_slice());
+			let i = shared.unwrap_or_else(|| {
+				outputs.push((frame.order, itp, Writer::new()));
+				outputs.len() - 1
+			});
+			slice.label32(outputs[i].2.here());
+			slice.u32(itp.len() as u32);
 		} else {
 			slice.u32(0);
 			slice.u32(0);
@@ -232,9 +268,11 @@ pub fn write(itc: &Itc) -> Result<Vec<u8>, WriteError> {
 	f.append(palette);
 
 	outputs.sort_by_key(|a| a.0);
-	for (_, output) in outputs {
+	for (_, data, mut output) in outputs {
+		output.slice(data);
 		f.append(output)
 	}
+	f.slice(&itc.trailing);
 
 	Ok(f.finish()?)
 }
@@ -254,3 +292,65 @@ fn test_parse_all(bytes: &[u8]) -> Result<(), anyhow::Error> {
 	}
 	Ok(())
 }
+
+#[test]
+fn test_shared_data() -> Result<(), anyhow::Error> {
+	let mut itc = Itc::default();
+	for (i, frame) in itc.frames[..3].iter_mut().enumerate() {
+		frame.itp = Some(vec![1, 2, 3, 4]);
+		frame.order = i.min(1);
+	}
+	let bytes = write(&itc)?;
+	let separate = write(&Itc {
+		frames: std::array::from_fn(|i| Frame {
+			order: i,
+			..itc.frames[i].clone()
+		}),
+		..itc.clone()
+	})?;
+	assert_eq!(bytes.len() + 4, separate.len());
+
+	let itc2 = read(&bytes)?;
+	assert_eq!(itc2.frames[1].order, itc2.frames[2].order);
+	assert_ne!(itc2.frames[0].order, itc2.frames[1].order);
+	assert_eq!(write(&itc2)?, bytes);
+	Ok(())
+}
+
+#[test]
+fn test_trailing_bytes() -> Result<(), anyhow::Error> {
+	let mut itc = Itc::default();
+	itc.frames[0].itp = Some(vec![1, 2, 3, 4]);
+	itc.frames[0].order = 0;
+	let bytes = write(&itc)?;
+	assert!(read(&bytes)?.trailing.is_empty());
+
+	// Like the Geofront files, which have a byte or two of padding at the end
+	for trailing in [&[0][..], &[0, 0]] {
+		let padded = [&bytes[..], trailing].concat();
+		let itc2 = read(&padded)?;
+		assert_eq!(itc2.trailing, trailing);
+		assert_eq!(itc2.frames[0].itp, itc.frames[0].itp);
+		assert_eq!(write(&itc2)?, padded);
+	}
+
+	// Without frames, the trailing bytes come after the header
+	let empty = [&write(&Itc::default())?[..], &[0]].concat();
+	assert_eq!(read(&empty)?.trailing, [0]);
+	Ok(())
+}
+
+#[test]
+fn test_frames() {
+	let mut itc = Itc::default();
+	for i in [5, 1, 100] {
+		itc.frames[i].itp = Some(vec![i as u8]);
+	}
+	let frames = itc.frames().map(|(i, _, itp)| (i, itp)).collect::<Vec<_>>();
+	assert_eq!(frames, [(1, &[1][..]), (5, &[5]), (100, &[100])]);
+	for (_, frame) in itc.frames_mut() {
+		frame.order = 0;
+	}
+	assert_eq!(itc.frames[5].order, 0);
+	assert_eq!(itc.frames[6].order, usize::MAX);
+}
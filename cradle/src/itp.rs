@@ -1,11 +1,15 @@
 use gospel::read::Reader;
 use num_enum::TryFromPrimitive;
+use std::collections::HashMap;
 use std::ffi::CString;
 
+mod builder;
 mod read;
 mod write;
 
+pub use builder::{BuildError, Builder};
 pub use read::Error as ReadError;
+pub use read::Ignored;
 pub use write::Error as WriteError;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -21,6 +25,108 @@ impl Itp {
 			data,
 		}
 	}
+
+	/// Starts building an itp with a consistent status. See [`Builder`].
+	pub fn builder() -> Builder {
+		Builder::default()
+	}
+
+	/// Returns the width and height of the largest mipmap, in pixels.
+	pub fn dimensions(&self) -> (usize, usize) {
+		(self.data.width(), self.data.height())
+	}
+
+	/// Returns a copy of this itp that will be written with the given revision.
+	///
+	/// Revisions 1 and 2 cannot express C77 compression, so Bz_1 is used instead. They also have
+	/// no IHAS chunk, so the hash is dropped.
+	/// Returns `None` if the revision cannot represent the rest of the status.
+	pub fn to_revision(&self, itp_revision: IR) -> Option<Itp> {
+		let mut itp = self.clone();
+		itp.status.itp_revision = itp_revision;
+		if itp_revision != IR::V3 {
+			if itp.status.compression == CT::C77 {
+				itp.status.compression = CT::Bz_1;
+			}
+			itp.status.hash = None;
+		}
+		itp.status.is_representable().then_some(itp)
+	}
+
+	/// Converts 16-bit color to 32-bit, or returns `None` if this itp is not 16-bit.
+	///
+	/// This is lossless; converting back to 16-bit color is not.
+	/// The revision is raised to 2 if needed, since revision 1 cannot represent 32-bit color.
+	pub fn argb16_to_argb32(&self) -> Option<Itp> {
+		let ImageData::Argb16(mode, data) = &self.data else {
+			return None;
+		};
+		let data = data
+			.iter()
+			.map(|r| r.map(|&a| crate::color::unpack_argb16(*mode, a)))
+			.collect();
+		let mut status = self.status.clone();
+		status.base_format = BFT::Argb32;
+		status.pixel_bit_format = PBFT::Argb32;
+		if !status.is_representable() {
+			status.itp_revision = IR::V2;
+		}
+		Some(Itp {
+			status,
+			data: ImageData::Argb32(data),
+		})
+	}
+
+	/// Converts indexed color to 32-bit color by looking up each pixel in the palette.
+	///
+	/// Indices outside the palette become transparent black. Returns false and leaves the image
+	/// unchanged if it is not indexed, or if its palette is external.
+	pub fn to_truecolor(&mut self) -> bool {
+		let ImageData::Indexed(pal, data) = &self.data else {
+			return false;
+		};
+		let Some(pal) = pal.colors() else {
+			return false;
+		};
+		let data = data
+			.iter()
+			.map(|r| r.map(|&a| pal.get(a as usize).copied().unwrap_or(0)))
+			.collect();
+		self.data = ImageData::Argb32(data);
+		self.status.base_format = BFT::Argb32;
+		self.status.pixel_bit_format = PBFT::Argb32;
+		if !self.status.is_representable() {
+			self.status.itp_revision = IR::V2;
+		}
+		true
+	}
+
+	/// Converts 32-bit color to indexed color, if there are at most `max_colors` distinct colors.
+	///
+	/// The palette is in order of first appearance, and the conversion is lossless. Images with
+	/// more colors are left unchanged, and false is returned.
+	pub fn try_to_indexed(&mut self, max_colors: usize) -> bool {
+		let ImageData::Argb32(data) = &self.data else {
+			return false;
+		};
+		let max_colors = max_colors.min(256);
+		let mut pal = Vec::new();
+		let mut indices = HashMap::new();
+		for &c in data.iter().flat_map(|r| r.as_slice()) {
+			indices.entry(c).or_insert_with(|| {
+				pal.push(c);
+				pal.len() - 1
+			});
+			if pal.len() > max_colors {
+				return false;
+			}
+		}
+		let data = data.iter().map(|r| r.map(|c| indices[c] as u8)).collect();
+		self.data = ImageData::Indexed(Palette::Embedded(pal), data);
+		self.status.base_format = BFT::Indexed1;
+		self.status.pixel_bit_format = PBFT::Indexed;
+		true
+	}
 }
 
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -70,8 +176,109 @@ impl ImageData {
 			ImageData::Bc7(d) => d.len(),
 		}
 	}
+
+	/// The size in pixels of the given mip level. See [`mip_dimensions`].
+	pub fn mip_dimensions(&self, level: usize) -> (usize, usize) {
+		let block = match self {
+			ImageData::Bc1(_) | ImageData::Bc2(_) | ImageData::Bc3(_) | ImageData::Bc7(_) => 4,
+			_ => 1,
+		};
+		mip_dimensions(self.width(), self.height(), level, block)
+	}
+
+	/// The base format family of the variant.
+	///
+	/// Indexed images give [`Indexed1`](BaseFormatType::Indexed1), since the variant does not
+	/// distinguish between the indexed formats; that is up to [`ItpStatus`].
+	pub fn base_format(&self) -> BaseFormatType {
+		match self {
+			ImageData::Indexed(_, _) => BFT::Indexed1,
+			ImageData::Argb16(_, _) => BFT::Argb16,
+			ImageData::Argb32(_) => BFT::Argb32,
+			ImageData::Bc1(_) => BFT::Bc1,
+			ImageData::Bc2(_) => BFT::Bc2,
+			ImageData::Bc3(_) => BFT::Bc3,
+			ImageData::Bc7(_) => BFT::Bc7,
+		}
+	}
+
+	/// Whether the pixels are palette indices, regardless of whether the palette is external.
+	pub fn is_indexed(&self) -> bool {
+		matches!(self, ImageData::Indexed(_, _))
+	}
+
+	/// Whether the image is BC1 with transparent pixels in any mip level.
+	///
+	/// A BC1 block has those when it is in three-color mode, meaning `c0 <= c1`, and some pixel uses
+	/// the fourth index. Blocks in three-color mode that do not use that index are still opaque.
+	pub fn bc1_has_alpha(&self) -> bool {
+		let ImageData::Bc1(data) = self else {
+			return false;
+		};
+		let has_alpha = |&block: &u64| {
+			let c0 = block as u16;
+			let c1 = (block >> 16) as u16;
+			let bits = (block >> 32) as u32;
+			c0 <= c1 && (0..16).any(|i| (bits >> (i * 2)) & 3 == 3)
+		};
+		data.iter()
+			.any(|level| level.as_slice().iter().any(has_alpha))
+	}
+
+	/// A short human-readable description of the pixel format, such as `BC7` or `Indexed (256)`.
+	pub fn format_name(&self) -> String {
+		match self {
+			ImageData::Indexed(pal, _) => match pal.len() {
+				Some(n) => format!("Indexed ({n})"),
+				None => "Indexed (external)".to_owned(),
+			},
+			ImageData::Argb16(A16::Mode1, _) => "ARGB16 (1555)".to_owned(),
+			ImageData::Argb16(A16::Mode2, _) => "ARGB16 (4444)".to_owned(),
+			ImageData::Argb16(A16::Mode3, _) => "ARGB16 (565)".to_owned(),
+			ImageData::Argb32(_) => "ARGB32".to_owned(),
+			ImageData::Bc1(_) => "BC1".to_owned(),
+			ImageData::Bc2(_) => "BC2".to_owned(),
+			ImageData::Bc3(_) => "BC3".to_owned(),
+			ImageData::Bc7(_) => "BC7".to_owned(),
+		}
+	}
+
+	/// Reverses the byte order of every pixel and palette entry.
+	///
+	/// Headers are always little-endian, but this allows handling files whose pixel and palette
+	/// words are big-endian. See [`read_be`] and [`write_be`].
+	pub fn swap_bytes(&mut self) {
+		fn swap<T: Copy>(data: &mut [Raster<T>], f: fn(T) -> T) {
+			for raster in data {
+				for a in raster.as_mut_slice() {
+					*a = f(*a);
+				}
+			}
+		}
+		match self {
+			ImageData::Indexed(pal, _) => {
+				if let Palette::Embedded(pal) = pal {
+					for a in pal {
+						*a = a.swap_bytes();
+					}
+				}
+			}
+			ImageData::Argb16(_, d) => swap(d, u16::swap_bytes),
+			ImageData::Argb32(d) => swap(d, u32::swap_bytes),
+			ImageData::Bc1(d) => swap(d, u64::swap_bytes),
+			ImageData::Bc2(d) => swap(d, u128::swap_bytes),
+			ImageData::Bc3(d) => swap(d, u128::swap_bytes),
+			ImageData::Bc7(d) => swap(d, u128::swap_bytes),
+		}
+	}
 }
 
+/// The layout of 16-bit color.
+///
+/// The mapping of modes to layouts is a best guess, since samples are scarce:
+/// - `Mode1`: 1555,
+/// - `Mode2`: 4444,
+/// - `Mode3`: 565, without alpha.
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Argb16Mode {
 	Mode1,
@@ -85,6 +292,32 @@ pub enum Palette {
 	External(CString),
 }
 
+// Clippy wants `is_empty` to return `Option<bool>` to match `len`, which would be awkward to use
+#[allow(clippy::len_without_is_empty)]
+impl Palette {
+	/// Number of colors, or `None` for external palettes.
+	pub fn len(&self) -> Option<usize> {
+		self.colors().map(<[u32]>::len)
+	}
+
+	/// Whether there are no colors. External palettes are assumed to have some.
+	pub fn is_empty(&self) -> bool {
+		self.colors().is_some_and(<[u32]>::is_empty)
+	}
+
+	/// The colors, or `None` for external palettes.
+	pub fn colors(&self) -> Option<&[u32]> {
+		match self {
+			Palette::Embedded(pal) => Some(pal),
+			Palette::External(_) => None,
+		}
+	}
+
+	pub fn is_external(&self) -> bool {
+		matches!(self, Palette::External(_))
+	}
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Default)]
 pub struct ItpStatus {
 	pub itp_revision: ItpRevision,
@@ -95,20 +328,26 @@ pub struct ItpStatus {
 	pub multi_plane: MultiPlaneType,
 	pub mipmap: MipmapType,
 	pub use_alpha: Option<bool>,
+	/// Contents of the IHAS chunk, if any.
+	///
+	/// This seems to be a hash of some sort, but the algorithm is unknown, so it is copied verbatim.
+	/// It is neither verified on read nor updated if the image data is changed. It does not match
+	/// any of the common CRC-64, FNV or xxHash variants over the file or the IDAT chunks.
+	pub hash: Option<[u8; 8]>,
 }
 
 impl ItpStatus {
 	pub fn default_for(itp_revision: ItpRevision, data: &ImageData) -> ItpStatus {
-		let (base_format, pixel_bit_format) = match &data {
-			ImageData::Indexed(_, _) => (BFT::Indexed1, PBFT::Indexed), // Indexed2/3 not supported
-			ImageData::Argb16(A16::Mode1, _) => (BFT::Argb16, PBFT::Argb16_1),
-			ImageData::Argb16(A16::Mode2, _) => (BFT::Argb16, PBFT::Argb16_2),
-			ImageData::Argb16(A16::Mode3, _) => (BFT::Argb16, PBFT::Argb16_3),
-			ImageData::Argb32(_) => (BFT::Argb32, PBFT::Argb32),
-			ImageData::Bc1(_) => (BFT::Bc1, PBFT::Compressed),
-			ImageData::Bc2(_) => (BFT::Bc2, PBFT::Compressed),
-			ImageData::Bc3(_) => (BFT::Bc3, PBFT::Compressed),
-			ImageData::Bc7(_) => (BFT::Bc7, PBFT::Compressed),
+		let base_format = data.base_format(); // Indexed2/3 not supported
+		let pixel_bit_format = match &data {
+			ImageData::Indexed(_, _) => PBFT::Indexed,
+			ImageData::Argb16(A16::Mode1, _) => PBFT::Argb16_1,
+			ImageData::Argb16(A16::Mode2, _) => PBFT::Argb16_2,
+			ImageData::Argb16(A16::Mode3, _) => PBFT::Argb16_3,
+			ImageData::Argb32(_) => PBFT::Argb32,
+			ImageData::Bc1(_) | ImageData::Bc2(_) | ImageData::Bc3(_) | ImageData::Bc7(_) => {
+				PBFT::Compressed
+			}
 		};
 		ItpStatus {
 			itp_revision,
@@ -123,6 +362,29 @@ impl ItpStatus {
 				MT::None
 			},
 			use_alpha: None,
+			hash: None,
+		}
+	}
+
+	/// The compression the games typically use for this kind of image.
+	///
+	/// Most images are compressed with Bz_1, which every revision supports. Small indexed images
+	/// are usually left uncompressed, since there is little to gain.
+	pub fn default_compression(data: &ImageData) -> CompressionType {
+		match data {
+			ImageData::Indexed(_, _) if data.width() * data.height() <= 64 * 64 => CT::None,
+			_ => CT::Bz_1,
+		}
+	}
+
+	/// Whether the header of [`itp_revision`](Self::itp_revision) can represent this status.
+	pub fn is_representable(&self) -> bool {
+		match self.itp_revision {
+			IR::V1 => write::status_to_flags(self)
+				.and_then(write::flags_to_gen1)
+				.is_some(),
+			IR::V2 => write::status_to_flags(self).is_some(),
+			IR::V3 => true,
 		}
 	}
 }
@@ -136,6 +398,7 @@ pub enum ItpRevision {
 	V3 = 3, // ITP\xFF
 }
 
+#[allow(non_camel_case_types)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, TryFromPrimitive)]
 #[repr(u16)]
 pub enum BaseFormatType {
@@ -149,7 +412,7 @@ pub enum BaseFormatType {
 	Bc1 = 6,
 	Bc2 = 7,
 	Bc3 = 8,
-	// BcAuto_1_3 = 9,
+	BcAuto_1_3 = 9, // either Bc1 or Bc3, depending on data size
 	Bc7 = 10,
 }
 
@@ -196,6 +459,12 @@ pub enum MultiPlaneType {
 	None = 0,
 }
 
+/// Whether, and how, the image has mipmaps.
+///
+/// Both types store the levels the same way: one IDAT chunk per level, each half the size of the
+/// previous. The only `Mipmap_2` sample seen so far (a Nayuta icon) also uses `Pfp_4`, so the
+/// difference presumably lies in how the game filters or samples them. Since that is unknown, the
+/// value is preserved as read, and new images use `Mipmap_1`.
 #[allow(non_camel_case_types)]
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default, TryFromPrimitive)]
 #[repr(u16)]
@@ -206,6 +475,27 @@ pub enum MipmapType {
 	Mipmap_2 = 2,
 }
 
+macro_rules! display_as_debug {
+	($($t:ty),* $(,)?) => { $(
+		impl std::fmt::Display for $t {
+			fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+				std::fmt::Debug::fmt(self, f)
+			}
+		}
+	)* };
+}
+
+display_as_debug!(
+	Argb16Mode,
+	ItpRevision,
+	BaseFormatType,
+	PixelBitFormatType,
+	PixelFormatType,
+	CompressionType,
+	MultiPlaneType,
+	MipmapType,
+);
+
 pub mod abbr {
 	pub use super::Argb16Mode as A16;
 	pub use super::BaseFormatType as BFT;
@@ -221,18 +511,138 @@ use abbr::*;
 
 use crate::raster::Raster;
 
+/// Reads an itp file. All fields, including pixels and palettes, are little-endian.
 pub fn read(f: &[u8]) -> Result<Itp, read::Error> {
 	read::read(&mut Reader::new(f))
 }
 
+/// Reads an itp, also returning each mip's pixel data after decompression, but before unswizzling.
+///
+/// This is mainly useful for studying unknown pixel formats. CCPI images have no such data.
+pub fn read_raw(f: &[u8]) -> Result<(Itp, Vec<Vec<u8>>), read::Error> {
+	read::read_raw(&mut Reader::new(f))
+}
+
+/// Finds the byte range of each chunk in `f`, without decoding any of them.
+///
+/// Only revision 3 files have chunks; for others, there are none. The IPAL size field is wrong in
+/// some files, so if it does not lead to another chunk, the IPAL is taken to end where IALP starts.
+pub fn chunk_ranges(f: &[u8]) -> Result<Vec<std::ops::Range<usize>>, read::Error> {
+	read::chunk_ranges(&mut Reader::new(f))
+}
+
+/// Reads an itp, ignoring unknown flags in revision 1 and 2 headers and unknown pixel formats.
+///
+/// Some files may set bits whose meaning is unknown, which [`read`] rejects. This instead returns
+/// those bits alongside the image, so the caller can decide whether to warn about them. Likewise,
+/// revision 3 pixel formats that are not known are read as linear, and reported. If IMIP declares
+/// a different number of mips than there are IDAT chunks, the IDAT chunks are trusted.
+///
+/// Some modding tools also prepend their own data to the file. If the file does not start with a
+/// known header, the first [`MAX_PREFIX`] bytes are searched for a revision 3 magic, and the image
+/// is read from there.
+pub fn read_lenient(f: &[u8]) -> Result<(Itp, Ignored), read::Error> {
+	match read::read_lenient(&mut Reader::new(f)) {
+		Err(read::Error::NotItp) => {
+			let end = f.len().min(MAX_PREFIX + 4);
+			let Some(start) = f[..end].windows(4).position(|a| a == b"ITP\xFF") else {
+				return Err(read::Error::NotItp);
+			};
+			read::read_lenient(&mut Reader::new(&f[start..]))
+		}
+		r => r,
+	}
+}
+
+/// How many bytes [`read_lenient`] skips over at most when looking for an itp.
+pub const MAX_PREFIX: usize = 256;
+
+/// Reads an itp whose pixel and palette words are big-endian.
+///
+/// Headers are still read as little-endian.
+pub fn read_be(f: &[u8]) -> Result<Itp, read::Error> {
+	let mut itp = read(f)?;
+	itp.data.swap_bytes();
+	Ok(itp)
+}
+
+/// Writes an itp with big-endian pixel and palette words, as read by [`read_be`].
+pub fn write_be(itp: &Itp) -> Result<Vec<u8>, write::Error> {
+	let mut itp = itp.clone();
+	itp.data.swap_bytes();
+	write(&itp)
+}
+
+/// Reads an itp starting at `offset` in `f`, such as one embedded in an archive.
+///
+/// Also returns the number of bytes the itp occupies.
+pub fn read_at(f: &[u8], offset: usize) -> Result<(Itp, usize), read::Error> {
+	let mut f = Reader::new(f);
+	f.seek(offset)?;
+	let itp = read::read(&mut f)?;
+	Ok((itp, f.pos() - offset))
+}
+
 pub fn read_size(f: &[u8]) -> Result<(usize, usize), read::Error> {
 	read::read_size(&mut Reader::new(f))
 }
 
+/// Writes an itp file.
+///
+/// C77 compression is not supported for writing, so such images are written as Bz_1 instead.
 pub fn write(itp: &Itp) -> Result<Vec<u8>, write::Error> {
 	write::write(itp)
 }
 
+/// The size in pixels of mip `level` of an image whose top level is `width`×`height`.
+///
+/// Each level is half the size of the previous one, but at least one pixel. Block-compressed data
+/// always stores whole blocks, so for `block` sizes above 1 the size is rounded up to fit.
+pub fn mip_dimensions(width: usize, height: usize, level: usize, block: usize) -> (usize, usize) {
+	let size = |a: usize| (a >> level).max(1).next_multiple_of(block);
+	(size(width), size(height))
+}
+
+/// Finds the encoding of the image that gives the smallest file, without changing any pixels.
+///
+/// This tries every compression type, and for indexed images in older revisions, every indexed
+/// format including CCPI. Each candidate is read back to make sure it holds the same image.
+/// The revision is kept, and if nothing is smaller, the image is written as is.
+pub fn repack(itp: &Itp) -> Result<(Itp, Vec<u8>), write::Error> {
+	// C77 cannot be written, so it is not a candidate, and an existing C77 image is written as Bz_1
+	let mut current = itp.clone();
+	if current.status.compression == CT::C77 {
+		current.status.compression = CT::Bz_1;
+	}
+	let mut best = (current.clone(), write(&current)?);
+	let base_formats = if itp.data.is_indexed() && itp.status.itp_revision != IR::V3 {
+		vec![BFT::Indexed1, BFT::Indexed2, BFT::Indexed3]
+	} else {
+		vec![itp.status.base_format]
+	};
+	for base_format in base_formats {
+		for compression in [CT::None, CT::Bz_1, CT::Bz_2] {
+			let mut candidate = itp.clone();
+			candidate.status.base_format = base_format;
+			candidate.status.compression = compression;
+			if base_format == BFT::Indexed3 {
+				candidate.status.pixel_format = PFT::Pfp_1;
+			}
+			if !candidate.status.is_representable() {
+				continue;
+			}
+			let Ok(bytes) = write(&candidate) else {
+				continue;
+			};
+			let same = read(&bytes).is_ok_and(|a| a.data == itp.data);
+			if same && bytes.len() < best.1.len() {
+				best = (candidate, bytes);
+			}
+		}
+	}
+	Ok(best)
+}
+
 fn show_fourcc(fourcc: [u8; 4]) -> String {
 	fourcc
 		.iter()
@@ -246,7 +656,684 @@ fn show_fourcc(fourcc: [u8; 4]) -> String {
 fn test_parse_all(bytes: &[u8]) -> Result<(), anyhow::Error> {
 	let itp = read(bytes)?;
 	let bytes2 = write(&itp)?;
-	let itp2 = read(&bytes2)?;
+	let mut itp2 = read(&bytes2)?;
+	if itp.status.compression == CT::C77 {
+		assert_eq!(itp2.status.compression, CT::Bz_1);
+		itp2.status.compression = CT::C77;
+	}
 	assert_eq!(itp, itp2);
 	Ok(())
 }
+
+#[test]
+fn test_read_at() -> Result<(), anyhow::Error> {
+	let bytes = include_bytes!("../../samples/itp/ao__extra020.itp");
+	let mut data = b"junk!".to_vec();
+	data.extend_from_slice(bytes);
+	data.extend_from_slice(b"more junk");
+	let (itp, len) = read_at(&data, 5)?;
+	assert_eq!(itp, read(bytes)?);
+	assert_eq!(len, bytes.len());
+	Ok(())
+}
+
+#[test]
+fn test_big_endian() -> Result<(), anyhow::Error> {
+	let pixels = Raster::new_with(2, 1, vec![0x11223344, 0xAABBCCDD]);
+	let itp = Itp::new(IR::V3, ImageData::Argb32(vec![pixels]));
+	let bytes = write_be(&itp)?;
+	let swapped = Raster::new_with(2, 1, vec![0x44332211, 0xDDCCBBAA]);
+	assert_eq!(read(&bytes)?.data, ImageData::Argb32(vec![swapped]));
+	assert_eq!(read_be(&bytes)?, itp);
+	Ok(())
+}
+
+#[test]
+fn test_read_raw() -> Result<(), anyhow::Error> {
+	let bytes = include_bytes!("../../samples/itp/nayuta__exterior.itp");
+	let (itp, raw) = read_raw(bytes)?;
+	assert_eq!(itp, read(bytes)?);
+	assert_eq!(raw.len(), itp.data.mipmaps());
+	let idat = bytes.windows(4).position(|a| a == b"IDAT").unwrap();
+	let mip0 = falcompress::freadp::freadp(&mut Reader::new(&bytes[idat + 16..]))?;
+	assert_eq!(raw[0], mip0);
+	Ok(())
+}
+
+#[test]
+fn test_chunk_ranges() -> Result<(), anyhow::Error> {
+	let bytes = include_bytes!("../../samples/itp/nayuta__m_record.itp");
+	let chunks = chunk_ranges(bytes)?;
+	let fourccs = chunks
+		.iter()
+		.map(|c| &bytes[c.start..c.start + 4])
+		.collect::<Vec<_>>();
+	assert_eq!(
+		fourccs,
+		[b"IHDR", b"IMIP", b"IHAS", b"IPAL", b"IALP", b"IDAT", b"IEND"]
+	);
+	assert_eq!(chunks[0].start, 4);
+	assert!(chunks.windows(2).all(|a| a[0].end == a[1].start));
+	assert_eq!(chunks[6].end, bytes.len());
+	// The IPAL size field is wrong in this file
+	assert_ne!(chunks[3].len(), 1032 + 8);
+
+	let bytes = include_bytes!("../../samples/itp/3rd_evo__ea.itp");
+	assert!(chunk_ranges(bytes)?.is_empty());
+	Ok(())
+}
+
+#[test]
+fn test_to_revision() -> Result<(), anyhow::Error> {
+	let pal = Palette::Embedded((0..256).map(|i| i * 0x010101 | 0xFF000000).collect());
+	let pixels = Raster::new_with(2, 2, vec![0, 1, 2, 255]);
+	let mut itp = Itp::new(IR::V3, ImageData::Indexed(pal, vec![pixels]));
+	itp.status.hash = Some([1; 8]);
+
+	let itp1 = itp.to_revision(IR::V1).unwrap();
+	assert_eq!(itp1.status.hash, None);
+	let bytes = write(&itp1)?;
+	assert_eq!(bytes[..4], 1000u32.to_le_bytes());
+	assert_eq!(read(&bytes)?, itp1);
+
+	let bc7 = Itp::new(IR::V3, ImageData::Bc7(vec![Raster::new(1, 1)]));
+	assert_eq!(bc7.to_revision(IR::V1), None);
+	Ok(())
+}
+
+#[test]
+fn test_argb16_999() -> Result<(), anyhow::Error> {
+	let pixels = Raster::new_with(2, 2, vec![0x0000, 0x7C00, 0x83E0, 0xFFFF]);
+	let itp = Itp::new(IR::V1, ImageData::Argb16(A16::Mode2, vec![pixels]));
+	let bytes = write(&itp)?;
+	assert_eq!(bytes[..4], 999u32.to_le_bytes());
+	assert_eq!(read(&bytes)?, itp);
+	Ok(())
+}
+
+#[test]
+fn test_imip_after_idat() -> Result<(), anyhow::Error> {
+	let mips = vec![
+		Raster::splat(4, 4, 0xFF00FF00),
+		Raster::splat(2, 2, 0xFFFF0000),
+	];
+	let itp = Itp::new(IR::V3, ImageData::Argb32(mips));
+	let bytes = write(&itp)?;
+	let reordered = move_chunk_to_end(&bytes, b"IMIP");
+	assert_ne!(reordered, bytes);
+	assert_eq!(read(&reordered)?, itp);
+	Ok(())
+}
+
+#[test]
+fn test_ipal_after_idat() -> Result<(), anyhow::Error> {
+	let pixels = Raster::new_with(2, 2, vec![0, 1, 2, 1]);
+	for pal in [
+		Palette::Embedded(vec![0xFF000000, 0xFFFF0000, 0xFF00FF00]),
+		Palette::External(CString::new("a.itp").unwrap()),
+	] {
+		let itp = Itp::new(IR::V3, ImageData::Indexed(pal, vec![pixels.clone()]));
+		let bytes = write(&itp)?;
+		let reordered = move_chunk_to_end(&bytes, b"IPAL");
+		assert_ne!(reordered, bytes);
+		assert_eq!(read(&reordered)?, itp);
+	}
+	Ok(())
+}
+
+#[test]
+fn test_idat_out_of_order() -> Result<(), anyhow::Error> {
+	let mips = vec![
+		Raster::splat(4, 4, 0xFF00FF00),
+		Raster::splat(2, 2, 0xFFFF0000),
+		Raster::splat(1, 1, 0xFF0000FF),
+	];
+	let itp = Itp::new(IR::V3, ImageData::Argb32(mips));
+	let bytes = write(&itp)?;
+	let reordered = reorder_chunks(&bytes, |chunks| {
+		let start = chunks.iter().position(|c| c.starts_with(b"IDAT")).unwrap();
+		chunks[start..start + 3].reverse();
+	});
+	assert_ne!(reordered, bytes);
+	assert_eq!(read(&reordered)?, itp);
+
+	// Mip 1 twice, and mip 2 never
+	let chunks = split_chunks(&bytes);
+	let idat = chunks.iter().position(|c| c.starts_with(b"IDAT")).unwrap();
+	let broken = reorder_chunks(&bytes, |c| c[idat + 2] = chunks[idat + 1]);
+	assert!(read(&broken).is_err());
+
+	// A mip index that would shift the dimensions out entirely
+	let mut broken = bytes.clone();
+	let pos = broken.windows(4).position(|a| a == b"IDAT").unwrap();
+	broken[pos + 14..pos + 16].copy_from_slice(&100u16.to_le_bytes());
+	assert!(read(&broken).is_err());
+	Ok(())
+}
+
+#[test]
+fn test_swizzle_size() -> Result<(), anyhow::Error> {
+	let mut itp = Itp::new(IR::V3, ImageData::Argb32(vec![Raster::new(10, 10)]));
+	itp.status.pixel_format = PFT::Pfp_1;
+	let err = write(&itp).unwrap_err();
+	let message = std::error::Error::source(&err).unwrap().to_string();
+	assert!(message.contains("10×10"), "{message}");
+
+	let mut itp = Itp::new(IR::V3, ImageData::Bc3(vec![Raster::new(3, 2)]));
+	itp.status.pixel_format = PFT::Pfp_1;
+	let err = write(&itp).unwrap_err();
+	let message = std::error::Error::source(&err).unwrap().to_string();
+	assert!(message.contains("12×8"), "{message}");
+
+	itp.data = ImageData::Bc3(vec![Raster::new(16, 8)]);
+	assert_eq!(read(&write(&itp)?)?, itp);
+	Ok(())
+}
+
+/// The chunks of a revision 3 itp, as found by [`chunk_ranges`].
+#[cfg(test)]
+fn split_chunks(bytes: &[u8]) -> Vec<&[u8]> {
+	let ranges = chunk_ranges(bytes).unwrap();
+	ranges.into_iter().map(|r| &bytes[r]).collect()
+}
+
+/// Rearranges the chunks of a revision 3 itp, fixing up nothing else.
+#[cfg(test)]
+fn reorder_chunks<'a>(bytes: &'a [u8], f: impl FnOnce(&mut Vec<&'a [u8]>)) -> Vec<u8> {
+	let mut chunks = split_chunks(bytes);
+	f(&mut chunks);
+	[&bytes[..4]]
+		.into_iter()
+		.chain(chunks)
+		.collect::<Vec<_>>()
+		.concat()
+}
+
+/// Moves a chunk of a revision 3 itp to just before IEND, after all IDAT chunks.
+#[cfg(test)]
+fn move_chunk_to_end(bytes: &[u8], fourcc: &[u8; 4]) -> Vec<u8> {
+	let reordered = reorder_chunks(bytes, |chunks| {
+		let i = chunks.iter().position(|c| c.starts_with(fourcc)).unwrap();
+		let chunk = chunks.remove(i);
+		chunks.insert(chunks.len() - 1, chunk);
+		assert!(chunks[chunks.len() - 3].starts_with(b"IDAT"));
+	});
+	assert_eq!(reordered.len(), bytes.len());
+	reordered
+}
+
+/// Writing the samples with IPAL or IHAS chunks back reproduces all their chunks, except for what
+/// depends on how the data compresses.
+///
+/// The IPAL size field is wrong in both IPAL samples: it is the size of the uncompressed palette,
+/// while the chunk holds a compressed one. That is not reproduced.
+#[test]
+fn test_golden_chunks() -> Result<(), anyhow::Error> {
+	for bytes in [
+		&include_bytes!("../../samples/itp/nayuta__m_record.itp")[..],
+		&include_bytes!("../../samples/itp/ys8__bn_b020.itp")[..],
+		&include_bytes!("../../samples/itp/ao__extra020.itp")[..],
+	] {
+		let itp = read(bytes)?;
+		let bytes2 = write(&itp)?;
+		let mut itp2 = read(&bytes2)?;
+		// C77 is written as Bz_1
+		let compression = match itp.status.compression {
+			CT::C77 => CT::Bz_1,
+			c => c,
+		};
+		assert_eq!(itp2.status.compression, compression);
+		itp2.status.compression = itp.status.compression;
+		assert_eq!(itp2, itp);
+
+		let (chunks1, chunks2) = (split_chunks(bytes), split_chunks(&bytes2));
+		let fourccs = |c: &[&[u8]]| c.iter().map(|c| c[..4].to_vec()).collect::<Vec<_>>();
+		assert_eq!(fourccs(&chunks1), fourccs(&chunks2));
+		for (c1, c2) in chunks1.into_iter().zip(chunks2) {
+			match &c1[..4] {
+				b"IHDR" => {
+					// Everything but the file size and compression
+					assert_eq!(c1[..20], c2[..20]);
+					assert_eq!(c1[24..32], c2[24..32]);
+					assert_eq!(c1[34..], c2[34..]);
+					assert_eq!(c1[20..24], (bytes.len() as u32).to_le_bytes());
+					assert_eq!(c2[20..24], (bytes2.len() as u32).to_le_bytes());
+				}
+				b"IPAL" => {
+					let pal_size = u16::from_le_bytes([c1[14], c1[15]]) as u32;
+					assert_eq!(c1[4..8], (8 + 4 * pal_size).to_le_bytes());
+					assert_eq!(c1[8..16], c2[8..16]);
+				}
+				b"IDAT" => assert_eq!(c1[8..16], c2[8..16]),
+				_ => assert_eq!(c1, c2),
+			}
+		}
+	}
+	Ok(())
+}
+
+#[test]
+fn test_ccpi_flips() -> Result<(), anyhow::Error> {
+	// Left and right halves mirror each other, and the bottom half repeats the top
+	let pixels = (0..32 * 16)
+		.map(|i| {
+			let (x, y) = (i % 16, i / 16);
+			(x.min(15 - x) + 8 * (y % 16)) as u8
+		})
+		.collect();
+	let pal = Palette::Embedded((0..256).map(|i| i * 0x010101 | 0xFF000000).collect());
+	let mut itp = Itp::new(
+		IR::V1,
+		ImageData::Indexed(pal, vec![Raster::new_with(16, 32, pixels)]),
+	);
+	itp.status.base_format = BFT::Indexed3;
+	itp.status.pixel_format = PFT::Pfp_1;
+	let bytes = write(&itp)?;
+	assert_eq!(bytes[..4], 1006u32.to_le_bytes());
+	assert_eq!(read(&bytes)?, itp);
+
+	// Header, CCPI header, palette, and every tile written out literally
+	let literal = 4 + 20 + 256 * 4 + 1 + 128 * 4 + 128;
+	assert!(bytes.len() < literal - 128 * 4 / 2);
+	Ok(())
+}
+
+#[test]
+fn test_base_format() {
+	let pal = Palette::Embedded(vec![0]);
+	for (data, bft) in [
+		(
+			ImageData::Indexed(pal, vec![Raster::new(1, 1)]),
+			BFT::Indexed1,
+		),
+		(
+			ImageData::Argb16(A16::Mode1, vec![Raster::new(1, 1)]),
+			BFT::Argb16,
+		),
+		(
+			ImageData::Argb16(A16::Mode3, vec![Raster::new(1, 1)]),
+			BFT::Argb16,
+		),
+		(ImageData::Argb32(vec![Raster::new(1, 1)]), BFT::Argb32),
+		(ImageData::Bc1(vec![Raster::new(1, 1)]), BFT::Bc1),
+		(ImageData::Bc2(vec![Raster::new(1, 1)]), BFT::Bc2),
+		(ImageData::Bc3(vec![Raster::new(1, 1)]), BFT::Bc3),
+		(ImageData::Bc7(vec![Raster::new(1, 1)]), BFT::Bc7),
+	] {
+		assert_eq!(data.base_format(), bft);
+		assert_eq!(data.is_indexed(), bft == BFT::Indexed1);
+		assert_eq!(Itp::new(IR::V3, data).status.base_format, bft);
+	}
+}
+
+#[test]
+fn test_argb16_gen1_no_palette() -> Result<(), anyhow::Error> {
+	let pixels = Raster::new_with(2, 2, vec![0x0000, 0x7C00, 0x83E0, 0xFFFF]);
+	let itp = Itp::new(IR::V1, ImageData::Argb16(A16::Mode2, vec![pixels]));
+	let bytes = write(&itp)?;
+	// Header, width, height, and then directly the pixels
+	assert_eq!(bytes.len(), 12 + 2 * 2 * 2);
+	assert_eq!(bytes[12..14], 0x0000u16.to_le_bytes());
+	assert_eq!(bytes[18..20], 0xFFFFu16.to_le_bytes());
+
+	let pixels = Raster::new_with(32, 32, (0..1024).map(|a| a as u16 * 61).collect());
+	let mut itp = Itp::new(IR::V1, ImageData::Argb16(A16::Mode2, vec![pixels]));
+	itp.status.compression = CT::Bz_1;
+	itp.status.pixel_format = PFT::Pfp_1;
+	let bytes = write(&itp)?;
+	assert_eq!(bytes[..4], 1003u32.to_le_bytes());
+	assert_eq!(read(&bytes)?, itp);
+	Ok(())
+}
+
+#[test]
+fn test_argb16_to_argb32() -> Result<(), anyhow::Error> {
+	let pixels = Raster::new_with(2, 2, vec![0x0000, 0xF00F, 0x8F21, 0xFFFF]);
+	let itp = Itp::new(IR::V1, ImageData::Argb16(A16::Mode2, vec![pixels]));
+	let itp32 = itp.argb16_to_argb32().unwrap();
+	let ImageData::Argb32(data) = &itp32.data else {
+		panic!("expected argb32")
+	};
+	assert_eq!(
+		data[0].as_slice(),
+		[0x00000000, 0xFF0000FF, 0x88FF2211, 0xFFFFFFFF]
+	);
+	assert_eq!(read(&write(&itp32)?)?, itp32);
+	assert_eq!(itp32.argb16_to_argb32(), None);
+	Ok(())
+}
+
+#[test]
+fn test_palette_accessors() {
+	let pal = Palette::Embedded(vec![1, 2, 3]);
+	assert_eq!(pal.len(), Some(3));
+	assert_eq!(pal.colors(), Some([1, 2, 3].as_slice()));
+	assert!(!pal.is_external());
+	assert!(!pal.is_empty());
+	assert!(Palette::Embedded(Vec::new()).is_empty());
+
+	let pal = Palette::External(CString::new("a.itp").unwrap());
+	assert_eq!(pal.len(), None);
+	assert_eq!(pal.colors(), None);
+	assert!(pal.is_external());
+	assert!(!pal.is_empty());
+}
+
+#[test]
+fn test_bc_auto() -> Result<(), anyhow::Error> {
+	for data in [
+		ImageData::Bc1(vec![Raster::new(2, 2)]),
+		ImageData::Bc3(vec![Raster::new(2, 2)]),
+	] {
+		let mut itp = Itp::new(IR::V3, data);
+		itp.status.base_format = BFT::BcAuto_1_3;
+		let itp2 = read(&write(&itp)?)?;
+		assert_eq!(itp, itp2);
+	}
+	Ok(())
+}
+
+#[test]
+fn test_c77_written_as_bz() -> Result<(), anyhow::Error> {
+	let bytes = include_bytes!("../../samples/itp/nayuta__exterior.itp");
+	let idat = bytes.windows(4).position(|a| a == b"IDAT").unwrap();
+	assert_eq!(bytes[idat + 16..idat + 20], 0x80000001u32.to_le_bytes());
+	let itp = read(bytes)?;
+	assert_eq!(itp.status.compression, CT::C77);
+
+	let bytes2 = write(&itp)?;
+	let idat = bytes2.windows(4).position(|a| a == b"IDAT").unwrap();
+	assert_ne!(bytes2[idat + 16..idat + 20], 0x80000001u32.to_le_bytes());
+	let itp2 = read(&bytes2)?;
+	assert_eq!(itp2.status.compression, CT::Bz_1);
+	assert_eq!(itp2.data, itp.data);
+	Ok(())
+}
+
+#[test]
+fn test_ihas_preserved() -> Result<(), anyhow::Error> {
+	let bytes = include_bytes!("../../samples/itp/ao__extra020.itp");
+	let itp = read(bytes)?;
+	assert_eq!(
+		itp.status.hash,
+		Some([0x62, 0xEE, 0xC7, 0x31, 0xAF, 0xCD, 0x9F, 0xD6])
+	);
+	let bytes2 = write(&itp)?;
+	assert_eq!(read(&bytes2)?.status.hash, itp.status.hash);
+	Ok(())
+}
+
+#[test]
+fn test_dimensions() {
+	let itp = Itp::new(IR::V3, ImageData::Bc1(vec![Raster::new(3, 2)]));
+	assert_eq!(itp.dimensions(), (12, 8));
+	assert_eq!(itp.dimensions(), (itp.data.width(), itp.data.height()));
+}
+
+#[test]
+fn test_implicit_palette_size() -> Result<(), anyhow::Error> {
+	for n in [256, 255, 16] {
+		let pal = (0..n).map(|i| 0xFF000000 | i).collect::<Vec<_>>();
+		let pixels = Raster::new_with(4, 2, (0..8).collect());
+		let itp = Itp::new(
+			IR::V2,
+			ImageData::Indexed(Palette::Embedded(pal), vec![pixels]),
+		);
+		let bytes = write(&itp)?;
+		assert_eq!(bytes[12..16], (n as u32).to_le_bytes());
+		assert_eq!(read(&bytes)?, itp);
+
+		if n == 256 {
+			// The same file without the size field
+			let mut bytes = bytes;
+			bytes.drain(12..16);
+			assert_eq!(read(&bytes)?, itp);
+		}
+	}
+	Ok(())
+}
+
+#[test]
+fn test_bc1_has_alpha() {
+	let bc1 = |c0: u64, c1: u64, bits: u64| {
+		ImageData::Bc1(vec![Raster::splat(1, 1, bits << 32 | c1 << 16 | c0)])
+	};
+	assert!(!bc1(0xFFFF, 0x0000, 0xFFFFFFFF).bc1_has_alpha());
+	assert!(!bc1(0x0000, 0xFFFF, 0x55555555).bc1_has_alpha());
+	assert!(bc1(0x0000, 0xFFFF, 0x000000C0).bc1_has_alpha());
+	assert!(bc1(0x1234, 0x1234, 0xFFFFFFFF).bc1_has_alpha());
+	assert!(!ImageData::Argb32(vec![Raster::new(1, 1)]).bc1_has_alpha());
+}
+
+#[test]
+fn test_format_name() {
+	let names = [
+		ImageData::Indexed(Palette::Embedded(vec![0; 16]), vec![]),
+		ImageData::Indexed(Palette::External(CString::new("a").unwrap()), vec![]),
+		ImageData::Argb16(A16::Mode1, vec![]),
+		ImageData::Argb16(A16::Mode2, vec![]),
+		ImageData::Argb16(A16::Mode3, vec![]),
+		ImageData::Argb32(vec![]),
+		ImageData::Bc1(vec![]),
+		ImageData::Bc2(vec![]),
+		ImageData::Bc3(vec![]),
+		ImageData::Bc7(vec![]),
+	]
+	.map(|a| a.format_name());
+	assert_eq!(
+		names,
+		[
+			"Indexed (16)",
+			"Indexed (external)",
+			"ARGB16 (1555)",
+			"ARGB16 (4444)",
+			"ARGB16 (565)",
+			"ARGB32",
+			"BC1",
+			"BC2",
+			"BC3",
+			"BC7",
+		]
+	);
+	assert_eq!(CT::Bz_1.to_string(), "Bz_1");
+	assert_eq!(IR::V3.to_string(), "V3");
+}
+
+#[test]
+fn test_read_lenient() -> Result<(), anyhow::Error> {
+	let pixels = Raster::new_with(2, 2, vec![0x0000, 0x7C00, 0x83E0, 0xFFFF]);
+	let itp = Itp::new(IR::V2, ImageData::Argb16(A16::Mode2, vec![pixels]));
+	let mut bytes = write(&itp)?;
+	assert_eq!(bytes[3] & 0x40, 0x40);
+	bytes[0] |= 1 << 5;
+
+	assert!(read(&bytes).is_err());
+	let (itp2, ignored) = read_lenient(&bytes)?;
+	assert_eq!(ignored.flags, 1 << 5);
+	assert_eq!(itp2.data, itp.data);
+
+	let (_, ignored) = read_lenient(&write(&itp)?)?;
+	assert!(ignored.is_empty());
+	Ok(())
+}
+
+#[test]
+fn test_read_unknown_pixel_format() -> Result<(), anyhow::Error> {
+	let pixels = Raster::new_with(2, 2, vec![1, 2, 3, 4]);
+	let itp = Itp::new(IR::V3, ImageData::Argb32(vec![pixels]));
+	let mut bytes = write(&itp)?;
+	let ihdr = bytes.windows(4).position(|a| a == b"IHDR").unwrap();
+	assert_eq!(bytes[ihdr + 28..ihdr + 30], [0, 0]);
+	bytes[ihdr + 28] = 9;
+
+	assert!(read(&bytes).is_err());
+	let (itp2, ignored) = read_lenient(&bytes)?;
+	assert_eq!(ignored.pixel_format, Some(9));
+	assert_eq!(itp2.status.pixel_format, PFT::Linear);
+	assert_eq!(itp2.data, itp.data);
+	Ok(())
+}
+
+#[test]
+fn test_read_lenient_mip_count() -> Result<(), anyhow::Error> {
+	let mips = vec![
+		Raster::splat(4, 4, 1u32),
+		Raster::splat(2, 2, 2),
+		Raster::splat(1, 1, 3),
+	];
+	let itp = Itp::new(IR::V3, ImageData::Argb32(mips));
+	let mut bytes = write(&itp)?;
+	let imip = bytes.windows(4).position(|a| a == b"IMIP").unwrap();
+	assert_eq!(bytes[imip + 14..imip + 16], [2, 0]);
+	bytes[imip + 14] = 0;
+
+	assert!(read(&bytes).is_err());
+	let (itp2, ignored) = read_lenient(&bytes)?;
+	assert_eq!(ignored.mip_count, Some(1));
+	assert_eq!(itp2.data, itp.data);
+	Ok(())
+}
+
+#[test]
+fn test_read_lenient_prefix() -> Result<(), anyhow::Error> {
+	let itp = Itp::new(IR::V3, ImageData::Argb32(vec![Raster::new(4, 4)]));
+	// A byte order mark and some more junk
+	let mut bytes = b"\xEF\xBB\xBF\0wrapped tool".to_vec();
+	assert_eq!(bytes.len(), 16);
+	bytes.extend(write(&itp)?);
+
+	assert!(matches!(read(&bytes), Err(read::Error::NotItp)));
+	assert_eq!(read_lenient(&bytes)?.0, itp);
+
+	let mut bytes = vec![0; MAX_PREFIX + 1];
+	bytes.extend(write(&itp)?);
+	assert!(matches!(read_lenient(&bytes), Err(read::Error::NotItp)));
+	Ok(())
+}
+
+#[test]
+fn test_mip_dimensions() {
+	let sizes = (0..9)
+		.map(|i| mip_dimensions(257, 129, i, 4))
+		.collect::<Vec<_>>();
+	#[rustfmt::skip]
+	assert_eq!(sizes, [
+		(260, 132), (128, 64), (64, 32), (32, 16), (16, 8),
+		(8, 4), (4, 4), (4, 4), (4, 4),
+	]);
+	assert_eq!(mip_dimensions(257, 129, 8, 1), (1, 1));
+
+	let bc3 = ImageData::Bc3(vec![Raster::new(65, 33)]);
+	assert_eq!(bc3.mip_dimensions(0), (260, 132));
+	assert_eq!(bc3.mip_dimensions(7), (4, 4));
+}
+
+#[test]
+fn test_builder() -> Result<(), anyhow::Error> {
+	let data = ImageData::Argb32(vec![Raster::new(16, 8)]);
+	let itp = Itp::builder()
+		.revision(IR::V2)
+		.compression(CT::Bz_1)
+		.swizzle(PFT::Pfp_1)
+		.data(data.clone())
+		.build()?;
+	assert_eq!(itp.status.base_format, BFT::Argb32);
+	assert_eq!(itp.status.pixel_bit_format, PBFT::Argb32);
+	assert_eq!(itp.status.pixel_format, PFT::Pfp_1);
+	assert_eq!(read(&write(&itp)?)?, itp);
+
+	// C77 needs revision 3, and BC7 cannot be expressed in revision 2 flags
+	let c77 = Itp::builder().revision(IR::V2).compression(CT::C77);
+	assert!(matches!(
+		c77.data(data.clone()).build(),
+		Err(BuildError::Unrepresentable { .. })
+	));
+	let bc7 = Itp::builder().revision(IR::V2);
+	assert!(matches!(
+		bc7.data(ImageData::Bc7(vec![Raster::new(4, 4)])).build(),
+		Err(BuildError::Unrepresentable { .. })
+	));
+
+	let odd = ImageData::Argb32(vec![Raster::new(12, 8)]);
+	let pfp1 = Itp::builder().swizzle(PFT::Pfp_1);
+	assert!(matches!(
+		pfp1.data(odd).build(),
+		Err(BuildError::Swizzle { width: 12, .. })
+	));
+	assert!(matches!(Itp::builder().build(), Err(BuildError::NoData)));
+	Ok(())
+}
+
+#[test]
+fn test_indexed_conversion() {
+	let colors = [0xFF000000, 0xFFFF0000, 0x80FFFFFF];
+	let pixels = Raster::new_with(3, 2, (0..6).map(|i| colors[i % 3]).collect());
+	let truecolor = Itp::new(IR::V2, ImageData::Argb32(vec![pixels]));
+
+	let mut itp = truecolor.clone();
+	assert!(!itp.try_to_indexed(2));
+	assert_eq!(itp, truecolor);
+	assert!(itp.try_to_indexed(3));
+	let ImageData::Indexed(pal, data) = &itp.data else {
+		panic!("not indexed")
+	};
+	assert_eq!(pal.colors(), Some(&colors[..]));
+	assert_eq!(data[0].as_slice(), [0, 1, 2, 0, 1, 2]);
+	assert_eq!(itp.status.base_format, BFT::Indexed1);
+
+	assert!(itp.to_truecolor());
+	assert_eq!(itp, truecolor);
+	assert!(!itp.to_truecolor());
+
+	// Revision 1 cannot hold 32-bit color
+	let mut itp = truecolor.clone();
+	itp.try_to_indexed(256);
+	itp.status.itp_revision = IR::V1;
+	assert!(itp.to_truecolor());
+	assert_eq!(itp.status.itp_revision, IR::V2);
+}
+
+#[test]
+fn test_mipmap_2() -> Result<(), anyhow::Error> {
+	let imip = |bytes: &[u8]| {
+		let pos = bytes.windows(4).position(|a| a == b"IMIP").unwrap();
+		bytes[pos..pos + 20].to_vec()
+	};
+	let bytes = include_bytes!("../../samples/itp/nayuta__ti_icon.itp");
+	let itp = read(bytes)?;
+	assert_eq!(itp.status.mipmap, MT::Mipmap_2);
+	assert_eq!(imip(&write(&itp)?), imip(bytes));
+
+	let mips = vec![Raster::new(4, 4), Raster::new(2, 2)];
+	let mut itp = Itp::new(IR::V3, ImageData::Argb32(mips));
+	assert_eq!(itp.status.mipmap, MT::Mipmap_1);
+	itp.status.mipmap = MT::Mipmap_2;
+	let bytes = write(&itp)?;
+	assert_eq!(imip(&bytes)[12..14], 2u16.to_le_bytes());
+	assert_eq!(read(&bytes)?, itp);
+	Ok(())
+}
+
+#[test]
+fn test_repack() -> Result<(), anyhow::Error> {
+	for bytes in [
+		&include_bytes!("../../samples/itp/ao__extra020.itp")[..],
+		&include_bytes!("../../samples/itp/ys8__ic_000.itp")[..],
+	] {
+		let itp = read(bytes)?;
+		let (repacked, data) = repack(&itp)?;
+		assert!(data.len() <= write(&itp)?.len());
+		assert_eq!(read(&data)?, repacked);
+		assert_eq!(repacked.data, itp.data);
+	}
+
+	// A flat uncompressed image should end up compressed
+	let pal = Palette::Embedded((0..256).map(|i| i * 0x010101 | 0xFF000000).collect());
+	let pixels = Raster::splat(128, 128, 7u8);
+	let itp = Itp::new(IR::V1, ImageData::Indexed(pal, vec![pixels]));
+	let (repacked, data) = repack(&itp)?;
+	assert_ne!(repacked.status.compression, CT::None);
+	assert!(data.len() < 128 * 128);
+	assert_eq!(read(&data)?.data, itp.data);
+	Ok(())
+}
@@ -0,0 +1,94 @@
+use snafu::prelude::*;
+
+use super::{abbr::*, write::swizzle_fits, ImageData, Itp, ItpStatus};
+
+#[derive(Debug, Snafu)]
+pub enum BuildError {
+	#[snafu(display("no image data was given"))]
+	NoData,
+
+	#[snafu(display("revision {revision} cannot represent this combination of formats"))]
+	Unrepresentable { revision: IR },
+
+	#[snafu(display("a {width}×{height} image cannot be swizzled with {pixel_format}"))]
+	Swizzle {
+		pixel_format: PFT,
+		width: usize,
+		height: usize,
+	},
+}
+
+/// Assembles an [`Itp`] from image data and a few choices, deriving the rest of the status.
+///
+/// The base and pixel bit formats are always taken from the image data, so they cannot
+/// disagree with it. Anything not specified is the same as in [`Itp::new`].
+#[derive(Debug, Clone, Default)]
+#[must_use]
+pub struct Builder {
+	revision: IR,
+	compression: CT,
+	pixel_format: PFT,
+	use_alpha: Option<bool>,
+	data: Option<ImageData>,
+}
+
+impl Builder {
+	pub fn revision(mut self, revision: IR) -> Self {
+		self.revision = revision;
+		self
+	}
+
+	pub fn compression(mut self, compression: CT) -> Self {
+		self.compression = compression;
+		self
+	}
+
+	pub fn swizzle(mut self, pixel_format: PFT) -> Self {
+		self.pixel_format = pixel_format;
+		self
+	}
+
+	pub fn use_alpha(mut self, use_alpha: bool) -> Self {
+		self.use_alpha = Some(use_alpha);
+		self
+	}
+
+	pub fn data(mut self, data: ImageData) -> Self {
+		self.data = Some(data);
+		self
+	}
+
+	/// Checks that the choices can be written together, and builds the itp.
+	pub fn build(self) -> Result<Itp, BuildError> {
+		let data = self.data.context(NoDataSnafu)?;
+		let status = ItpStatus {
+			compression: self.compression,
+			pixel_format: self.pixel_format,
+			use_alpha: self.use_alpha,
+			..ItpStatus::default_for(self.revision, &data)
+		};
+		ensure!(
+			status.is_representable(),
+			UnrepresentableSnafu {
+				revision: self.revision
+			}
+		);
+		// Swizzling works on elements, which are blocks for block-compressed formats
+		let block = match data {
+			ImageData::Bc1(_) | ImageData::Bc2(_) | ImageData::Bc3(_) | ImageData::Bc7(_) => 4,
+			_ => 1,
+		};
+		for level in 0..data.mipmaps() {
+			let (width, height) = data.mip_dimensions(level);
+			ensure!(
+				swizzle_fits(self.pixel_format, width / block, height / block),
+				SwizzleSnafu {
+					pixel_format: self.pixel_format,
+					width,
+					height,
+				}
+			);
+		}
+		Ok(Itp { status, data })
+	}
+}
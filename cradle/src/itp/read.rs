@@ -1,3 +1,5 @@
+use std::ops::Range;
+
 use falcompress::freadp::freadp;
 use gospel::read::{Le as _, Reader};
 use num_enum::{TryFromPrimitive, TryFromPrimitiveError};
@@ -47,6 +49,18 @@ enum InnerError {
 	#[snafu(display("wrong number of mipmaps: header says {expected}, but there are {value}"))]
 	WrongMips { expected: usize, value: usize },
 
+	#[snafu(display("more than one IDAT chunk for mip {index}"))]
+	DuplicateMip { index: usize },
+
+	#[snafu(display("no IDAT chunk for mip {index}"))]
+	MissingMip { index: usize },
+
+	#[snafu(display("mip {index} is too small to have any pixels"))]
+	MipOutOfRange { index: usize },
+
+	#[snafu(display("chunk at {pos:#X} extends past the end of the file"))]
+	ChunkTruncated { pos: usize },
+
 	#[snafu(display("unexpected data after end"))]
 	RemainingData,
 
@@ -95,12 +109,88 @@ const PNG: u32 = u32::from_le_bytes(*b"\x89PNG");
 const DDS: u32 = u32::from_le_bytes(*b"DDS ");
 
 pub fn read(f: &mut Reader) -> Result<Itp, Error> {
+	read_inner(f, None, None)
+}
+
+/// Like [`read`], but also collects each mip's pixel data after decompression, before unswizzling.
+///
+/// CCPI images are decompressed as a whole, so there is no such data for them.
+pub fn read_raw(f: &mut Reader) -> Result<(Itp, Vec<Vec<u8>>), Error> {
+	let mut raw = Vec::new();
+	let itp = read_inner(f, Some(&mut raw), None)?;
+	Ok((itp, raw))
+}
+
+/// Like [`read`], but unknown gen2 flags and pixel formats, and wrong mip counts, are ignored
+/// rather than rejected.
+pub fn read_lenient(f: &mut Reader) -> Result<(Itp, Ignored), Error> {
+	let mut ignored = Ignored::default();
+	let itp = read_inner(f, None, Some(&mut ignored))?;
+	Ok((itp, ignored))
+}
+
+/// Finds the byte range of each revision 3 chunk, looking only at the chunk headers.
+///
+/// The ranges are relative to the reader's position, which is not advanced.
+pub fn chunk_ranges(f: &mut Reader) -> Result<Vec<Range<usize>>, Error> {
+	const FOURCCS: [&[u8]; 8] = [
+		b"IHDR", b"IMIP", b"IHAS", b"IPAL", b"IALP", b"IDAT", b"IEXT", b"IEND",
+	];
+	let bytes = f.remaining();
+	if !bytes.starts_with(b"ITP\xFF") {
+		return Ok(Vec::new());
+	}
+	let mut chunks = Vec::new();
+	let mut pos = 4;
+	loop {
+		let head = bytes.get(pos..pos + 8).context(e::ChunkTruncated { pos })?;
+		let size = u32::from_le_bytes(head[4..].try_into().unwrap()) as usize;
+		let mut end = pos + 8 + size;
+		// The IPAL size is wrong in some files; see read_revision_3
+		let next = bytes.get(end..end + 4);
+		if &head[..4] == b"IPAL" && !next.is_some_and(|a| FOURCCS.contains(&a)) {
+			let ialp = bytes[pos..].windows(4).position(|a| a == b"IALP");
+			end = pos + ialp.context(e::ChunkTruncated { pos })?;
+		}
+		ensure!(end <= bytes.len(), e::ChunkTruncated { pos });
+		chunks.push(pos..end);
+		pos = end;
+		if &head[..4] == b"IEND" {
+			break;
+		}
+	}
+	Ok(chunks)
+}
+
+/// What [`read_lenient`] did not understand, and ignored.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Ignored {
+	/// Unknown gen2 flags.
+	pub flags: u32,
+	/// Unknown revision 3 pixel format. The pixels are read as [`Linear`](PFT::Linear).
+	pub pixel_format: Option<u16>,
+	/// Mip count declared in IMIP, if it disagrees with the IDAT chunks. Those are trusted instead.
+	pub mip_count: Option<usize>,
+}
+
+impl Ignored {
+	/// Whether the file was fully understood.
+	pub fn is_empty(&self) -> bool {
+		self == &Ignored::default()
+	}
+}
+
+fn read_inner(
+	f: &mut Reader,
+	mut raw: Option<&mut Vec<Vec<u8>>>,
+	ignored: Option<&mut Ignored>,
+) -> Result<Itp, Error> {
 	let head = f.u32()?;
 	let flags = match head {
 		PNG | DDS => bail!(NotItpSnafu),
 		ITP => {
 			f.seek(f.pos() - 4)?;
-			return read_revision_3(f);
+			return read_revision_3(f, raw, ignored);
 		}
 		#[rustfmt::skip]
 		999  => 0x108802, // Argb16_2, None, Linear
@@ -114,7 +204,7 @@ pub fn read(f: &mut Reader) -> Result<Itp, Error> {
 		x if x & 0x40000000 != 0 => x,
 		_ => return Err(Error::NotItp),
 	};
-	let status = status_from_flags(flags)?;
+	let status = status_from_flags(flags, ignored.map(|i| &mut i.flags))?;
 
 	if status.base_format == BFT::Indexed3 {
 		return read_ccpi(f, status);
@@ -128,19 +218,44 @@ pub fn read(f: &mut Reader) -> Result<Itp, Error> {
 	let height = f.u32()? as usize;
 
 	if let ImageData::Indexed(pal, _) = &mut data {
-		let pal_size = if matches!(head, 1000 | 1002) {
-			256
-		} else {
-			f.u32()? as usize
-		};
+		let pal_size = palette_size(f, head, &status, width, height)?;
 		*pal = read_ipal(f, &status, false, pal_size)?;
 	}
 
-	read_idat(f, &status, &mut data, width, height)?;
+	read_idat(f, &status, &mut data, width, height, raw.as_deref_mut())?;
 
 	Ok(Itp { status, data })
 }
 
+/// Determines the number of colors in the palette, for revisions 1 and 2.
+///
+/// Headers 1000 and 1002 have no size field, and always have 256 colors. Other headers normally
+/// have an explicit size, but in case some files omit it, an uncompressed Indexed1 image whose
+/// remaining data is exactly 256 colors plus the pixels is assumed to have no size field.
+///
+/// This is ambiguous with an explicit size of 255, so if the first word is 255 it is taken to be the
+/// size; an implicit palette would need to start with transparent blue for that to go wrong.
+fn palette_size(
+	f: &mut Reader,
+	head: u32,
+	status: &ItpStatus,
+	w: usize,
+	h: usize,
+) -> Result<usize, Error> {
+	if matches!(head, 1000 | 1002) {
+		return Ok(256);
+	}
+	let rest = f.remaining();
+	if status.compression == CT::None
+		&& status.base_format == BFT::Indexed1
+		&& rest.len() == 256 * 4 + w * h
+		&& rest[..4] != 255u32.to_le_bytes()
+	{
+		return Ok(256);
+	}
+	Ok(f.u32()? as usize)
+}
+
 pub fn read_size(f: &mut Reader) -> Result<(usize, usize), Error> {
 	let head = f.u32()?;
 	let flags = match head {
@@ -165,7 +280,7 @@ pub fn read_size(f: &mut Reader) -> Result<(usize, usize), Error> {
 		x if x & 0x40000000 != 0 => x,
 		_ => return Err(Error::NotItp),
 	};
-	let status = status_from_flags(flags)?;
+	let status = status_from_flags(flags, None)?;
 
 	if status.base_format == BFT::Indexed3 {
 		f.u32()?;
@@ -177,21 +292,26 @@ pub fn read_size(f: &mut Reader) -> Result<(usize, usize), Error> {
 	}
 }
 
-fn read_revision_3(f: &mut Reader) -> Result<Itp, Error> {
+fn read_revision_3(
+	f: &mut Reader,
+	mut raw: Option<&mut Vec<Vec<u8>>>,
+	mut ignored: Option<&mut Ignored>,
+) -> Result<Itp, Error> {
 	let start = f.pos();
 	f.check(b"ITP\xFF")?;
 	let mut width = 0;
 	let mut height = 0;
 	let mut file_size = 0;
 	let mut n_mip = 0;
-	let mut current_mip = 0;
+	// The mip index of each IDAT, in the order they were read
+	let mut mips = Vec::new();
 	let mut status = ItpStatus::default();
 	let mut pal = None;
 	let mut data = None;
 
 	loop {
 		let fourcc = f.array::<4>()?;
-		let _size = f.u32()? as usize;
+		let size = f.u32()? as usize;
 		// Size is incorrect on both IPAL-having files I have
 		match &fourcc {
 			b"IHDR" => {
@@ -201,7 +321,7 @@ fn read_revision_3(f: &mut Reader) -> Result<Itp, Error> {
 				file_size = f.u32()? as usize;
 				status.itp_revision = f.enum16("IHDR.itp_revision")?;
 				status.base_format = f.enum16("IHDR.base_format")?;
-				status.pixel_format = f.enum16("IHDR.pixel_format")?;
+				status.pixel_format = pixel_format(f, ignored.as_deref_mut())?;
 				status.pixel_bit_format = f.enum16("IHDR.pixel_bit_format")?;
 				status.compression = f.enum16("IHDR.compression")?;
 				status.multi_plane = f.enum16("IHDR.multi_plane")?;
@@ -219,7 +339,7 @@ fn read_revision_3(f: &mut Reader) -> Result<Itp, Error> {
 			b"IHAS" => {
 				f.check_u32(16)?;
 				f.check_u32(0)?;
-				f.array::<8>()?;
+				status.hash = Some(f.array::<8>()?);
 			}
 
 			b"IPAL" => {
@@ -238,16 +358,29 @@ fn read_revision_3(f: &mut Reader) -> Result<Itp, Error> {
 			b"IDAT" => {
 				f.check_u32(8)?;
 				f.check_u16(0)?;
-				f.check_u16(current_mip as u16)?;
+				let mip = f.u16()? as usize;
+				ensure!(!mips.contains(&mip), e::DuplicateMip { index: mip });
+				// The dimensions are read as u32, so anything beyond this would shift them out
+				ensure!(mip < 32, e::MipOutOfRange { index: mip });
+				if status.base_format == BFT::BcAuto_1_3 && mips.is_empty() && data.is_some() {
+					data = Some(resolve_bc_auto(
+						f,
+						&status,
+						size.saturating_sub(8),
+						width >> mip,
+						height >> mip,
+					)?);
+				}
 				let data = data.as_mut().context(e::NoHeader)?;
 				read_idat(
 					f,
 					&status,
 					data,
-					width >> current_mip,
-					height >> current_mip,
+					width >> mip,
+					height >> mip,
+					raw.as_deref_mut(),
 				)?;
-				current_mip += 1;
+				mips.push(mip);
 			}
 
 			b"IEXT" => bail!(e::Todo { what: "IEXT chunk" }),
@@ -259,31 +392,64 @@ fn read_revision_3(f: &mut Reader) -> Result<Itp, Error> {
 
 	let mut data = data.context(e::NoHeader)?;
 
+	// IDAT chunks are normally in mip order, but nothing requires it
+	if !mips.is_sorted() {
+		sort_mips(&mut data, &mips);
+		if let Some(raw) = raw {
+			sort_by_mip(raw, &mips);
+		}
+		mips.sort();
+	}
+	if let Some(index) = (0..mips.len()).find(|&i| mips[i] != i) {
+		bail!(e::MissingMip { index })
+	}
+
+	// IPAL may come before or after IDAT; the indices don't need it, so it's attached last
 	if let Some(palette) = pal {
 		let ImageData::Indexed(pal, _) = &mut data else {
 			bail!(e::PalettePresent)
 		};
 		*pal = palette;
-	} else {
-		#[allow(clippy::collapsible_else_if)]
-		if let ImageData::Indexed(..) = data {
-			bail!(e::PaletteMissing)
-		}
+	} else if data.is_indexed() {
+		bail!(e::PaletteMissing)
 	}
 
 	ensure_size(f.pos() - start, file_size)?;
-	ensure!(
-		n_mip == current_mip,
-		e::WrongMips {
-			expected: n_mip,
-			value: current_mip
-		}
-	);
+	// Checked only after all chunks are read, since IMIP may come after the IDATs
+	match ignored {
+		Some(ignored) if n_mip != mips.len() => ignored.mip_count = Some(n_mip),
+		_ => ensure!(
+			n_mip == mips.len(),
+			e::WrongMips {
+				expected: n_mip,
+				value: mips.len()
+			}
+		),
+	}
 
 	Ok(Itp { status, data })
 }
 
-fn status_from_flags(f: u32) -> Result<ItpStatus, Error> {
+/// Reads the revision 3 pixel format. If `ignored` is given, unknown formats are stored there and
+/// read as linear, since that at least shows the right colors.
+fn pixel_format(f: &mut Reader, ignored: Option<&mut Ignored>) -> Result<PFT, Error> {
+	let value = f.u16()?;
+	match (PFT::try_from_primitive(value), ignored) {
+		(Ok(pixel_format), _) => Ok(pixel_format),
+		(Err(_), Some(ignored)) => {
+			ignored.pixel_format = Some(value);
+			Ok(PFT::Linear)
+		}
+		(Err(_), None) => bail!(e::Invalid {
+			field: "IHDR.pixel_format",
+			value,
+		}),
+	}
+}
+
+/// Decodes the gen2 flags. If `ignored` is given, unknown flags are stored there instead of
+/// causing an error.
+fn status_from_flags(f: u32, ignored: Option<&mut u32>) -> Result<ItpStatus, Error> {
 	macro_rules! bits {
 		($($bit:expr => $v:expr,)* _ => $def:expr) => {
 			$(if f & (1<<$bit) != 0 { $v } else)* { $def }
@@ -344,7 +510,10 @@ fn status_from_flags(f: u32) -> Result<ItpStatus, Error> {
 		.iter()
 		.map(|a| 1 << *a)
 		.sum();
-	ensure!(f & unused == 0, e::ExtraFlags { flags: f & unused });
+	match ignored {
+		Some(ignored) => *ignored = f & unused,
+		None => ensure!(f & unused == 0, e::ExtraFlags { flags: f & unused }),
+	}
 
 	Ok(ItpStatus {
 		itp_revision,
@@ -355,6 +524,7 @@ fn status_from_flags(f: u32) -> Result<ItpStatus, Error> {
 		multi_plane,
 		mipmap,
 		use_alpha,
+		hash: None,
 	})
 }
 
@@ -395,25 +565,27 @@ fn read_idat(
 	data: &mut ImageData,
 	w: usize,
 	h: usize,
+	raw: Option<&mut Vec<Vec<u8>>>,
 ) -> Result<(), Error> {
 	fn raster<T, const N: usize>(
 		f: &mut Reader,
 		status: &ItpStatus,
+		raw: Option<&mut Vec<Vec<u8>>>,
 		w: usize,
 		h: usize,
 		from_le_bytes: fn([u8; N]) -> T,
 	) -> Result<Raster<T>, Error> {
-		let data = read_maybe_compressed(f, status.compression, w * h * N)?;
+		let data = read_idat_data(f, status, raw, w * h * N)?;
 		let data = data.array_chunks().copied().map(from_le_bytes).collect();
 		Ok(do_unswizzle(data, w, h, status.pixel_format))
 	}
 
 	match data {
 		ImageData::Indexed(_, data) => match status.base_format {
-			BFT::Indexed1 => data.push(raster(f, status, w, h, u8::from_le_bytes)?),
+			BFT::Indexed1 => data.push(raster(f, status, raw, w, h, u8::from_le_bytes)?),
 			BFT::Indexed2 => data.push({
 				let size = f.u32()? as usize;
-				let data = read_maybe_compressed(f, status.compression, size)?;
+				let data = read_idat_data(f, status, raw, size)?;
 				let g = &mut Reader::new(&data);
 				let data = a_fast_mode2(g, w, h)?;
 				ensure_end(g)?;
@@ -424,16 +596,59 @@ fn read_idat(
 			}),
 			_ => unreachable!(),
 		},
-		ImageData::Argb16(_, data) => data.push(raster(f, status, w, h, u16::from_le_bytes)?),
-		ImageData::Argb32(data) => data.push(raster(f, status, w, h, u32::from_le_bytes)?),
-		ImageData::Bc1(data) => data.push(raster(f, status, w / 4, h / 4, u64::from_le_bytes)?),
-		ImageData::Bc2(data) => data.push(raster(f, status, w / 4, h / 4, u128::from_le_bytes)?),
-		ImageData::Bc3(data) => data.push(raster(f, status, w / 4, h / 4, u128::from_le_bytes)?),
-		ImageData::Bc7(data) => data.push(raster(f, status, w / 4, h / 4, u128::from_le_bytes)?),
+		ImageData::Argb16(_, data) => data.push(raster(f, status, raw, w, h, u16::from_le_bytes)?),
+		ImageData::Argb32(data) => data.push(raster(f, status, raw, w, h, u32::from_le_bytes)?),
+		ImageData::Bc1(data) => {
+			data.push(raster(f, status, raw, w / 4, h / 4, u64::from_le_bytes)?)
+		}
+		ImageData::Bc2(data) => {
+			data.push(raster(f, status, raw, w / 4, h / 4, u128::from_le_bytes)?)
+		}
+		ImageData::Bc3(data) => {
+			data.push(raster(f, status, raw, w / 4, h / 4, u128::from_le_bytes)?)
+		}
+		ImageData::Bc7(data) => {
+			data.push(raster(f, status, raw, w / 4, h / 4, u128::from_le_bytes)?)
+		}
 	}
 	Ok(())
 }
 
+/// Puts the levels, which were read in the order given by `mips`, in ascending mip order.
+fn sort_mips(data: &mut ImageData, mips: &[usize]) {
+	match data {
+		ImageData::Indexed(_, data) => sort_by_mip(data, mips),
+		ImageData::Argb16(_, data) => sort_by_mip(data, mips),
+		ImageData::Argb32(data) => sort_by_mip(data, mips),
+		ImageData::Bc1(data) => sort_by_mip(data, mips),
+		ImageData::Bc2(data) => sort_by_mip(data, mips),
+		ImageData::Bc3(data) => sort_by_mip(data, mips),
+		ImageData::Bc7(data) => sort_by_mip(data, mips),
+	}
+}
+
+fn sort_by_mip<T>(data: &mut Vec<T>, mips: &[usize]) {
+	let mut levels = std::mem::take(data)
+		.into_iter()
+		.zip(mips)
+		.collect::<Vec<_>>();
+	levels.sort_by_key(|a| *a.1);
+	*data = levels.into_iter().map(|a| a.0).collect();
+}
+
+fn read_idat_data(
+	f: &mut Reader,
+	status: &ItpStatus,
+	raw: Option<&mut Vec<Vec<u8>>>,
+	len: usize,
+) -> Result<Vec<u8>, Error> {
+	let data = read_maybe_compressed(f, status.compression, len)?;
+	if let Some(raw) = raw {
+		raw.push(data.clone());
+	}
+	Ok(data)
+}
+
 fn do_unswizzle<T>(mut data: Vec<T>, width: usize, height: usize, pixel_format: PFT) -> Raster<T> {
 	match pixel_format {
 		PFT::Linear => {}
@@ -460,11 +675,40 @@ fn make_data(status: &ItpStatus) -> Result<ImageData, Error> {
 		(BFT::Bc1, PBFT::Compressed) => ImageData::Bc1(Vec::new()),
 		(BFT::Bc2, PBFT::Compressed) => ImageData::Bc2(Vec::new()),
 		(BFT::Bc3, PBFT::Compressed) => ImageData::Bc3(Vec::new()),
+		// Replaced by resolve_bc_auto once the data is known
+		(BFT::BcAuto_1_3, PBFT::Compressed) => ImageData::Bc3(Vec::new()),
 		(BFT::Bc7, PBFT::Compressed) => ImageData::Bc7(Vec::new()),
 		(bft, pbft) => bail!(e::PixelFormat { bft, pbft }),
 	})
 }
 
+/// BcAuto_1_3 stores either Bc1 or Bc3 blocks, and the only way to tell which is the data size.
+fn resolve_bc_auto(
+	f: &Reader,
+	status: &ItpStatus,
+	raw_size: usize,
+	w: usize,
+	h: usize,
+) -> Result<ImageData, Error> {
+	let size = match status.compression {
+		CT::None => raw_size,
+		CT::Bz_1 | CT::C77 => freadp(&mut Reader::new(f.remaining()))?.len(),
+		CT::Bz_2 => bail!(e::Todo {
+			what: "BcAuto_1_3 with Bz_2 compression"
+		}),
+	};
+	Ok(if size == w * h / 2 {
+		ImageData::Bc1(Vec::new())
+	} else if size == w * h {
+		ImageData::Bc3(Vec::new())
+	} else {
+		bail!(e::WrongSize {
+			expected: w * h,
+			value: size
+		})
+	})
+}
+
 fn read_ccpi(f: &mut Reader, mut status: ItpStatus) -> Result<Itp, Error> {
 	let data_size = f.u32()? as usize;
 	f.check(b"CCPI")?;
@@ -619,7 +863,9 @@ fn a_fast_mode2(f: &mut Reader, width: usize, height: usize) -> Result<Raster<u8
 }
 
 fn read_maybe_compressed(f: &mut Reader, comp: CT, len: usize) -> Result<Vec<u8>, Error> {
-	// Reader seems to make no difference between Bz_1 and C77. Guess writer does though?
+	// C77 data is stored in a chunked container starting with 0x80000001, but freadp detects that
+	// by itself, so it needs no separate handling here. There is no C77 compressor, so writing
+	// such an image gives Bz_1 instead; see `write`.
 	let data = match comp {
 		CT::None => f.slice(len)?.to_vec(),
 		CT::Bz_1 => freadp(f)?,
@@ -39,6 +39,13 @@ enum InnerError {
 	#[snafu(display("AFastMode2 can only store 16 colors per 8×16 tile"))]
 	AFastMode2Colors,
 
+	#[snafu(display("a {width}×{height} image cannot be swizzled with {pixel_format}"))]
+	Swizzle {
+		pixel_format: PFT,
+		width: usize,
+		height: usize,
+	},
+
 	#[snafu(display("{what} is not yet implemented"))]
 	Todo { what: String },
 }
@@ -56,6 +63,18 @@ macro_rules! bail {
 }
 
 pub fn write(itp: &Itp) -> Result<Vec<u8>, Error> {
+	if itp.status.compression == CT::C77 {
+		// C77 cannot be compressed yet, and a Bz payload under a C77 header would be mislabelled
+		let status = ItpStatus {
+			compression: CT::Bz_1,
+			..itp.status.clone()
+		};
+		return write(&Itp {
+			status,
+			data: itp.data.clone(),
+		});
+	}
+
 	let Itp {
 		ref status,
 		ref data,
@@ -139,7 +158,15 @@ fn write_revision_3(itp: &Itp) -> Result<Vec<u8>, Error> {
 		f
 	});
 
-	// IHAS: ignored
+	if let Some(hash) = status.hash {
+		chunk(&mut f, b"IHAS", {
+			let mut f = Writer::new();
+			f.u32(16);
+			f.u32(0);
+			f.slice(&hash);
+			f
+		});
+	}
 
 	if let ImageData::Indexed(pal, _) = data {
 		chunk(&mut f, b"IPAL", {
@@ -298,38 +325,66 @@ fn write_ipal(
 }
 
 fn write_idat(status: &ItpStatus, data: &ImageData, level: usize) -> Result<Vec<u8>, Error> {
+	/// `block` is the number of pixels per element in each direction, for the error message.
 	fn raster<T: Clone, const N: usize>(
 		data: &Raster<T>,
 		status: &ItpStatus,
+		block: usize,
 		to_le_bytes: fn(T) -> [u8; N],
-	) -> Vec<u8> {
+	) -> Result<Vec<u8>, Error> {
+		check_swizzle(data, status.pixel_format, block)?;
 		let data = do_swizzle(data, status.pixel_format);
 		let data = data.into_iter().flat_map(to_le_bytes).collect::<Vec<u8>>();
-		maybe_compress(status.compression, &data)
+		Ok(maybe_compress(status.compression, &data))
 	}
 
-	Ok(match data {
+	match data {
 		ImageData::Indexed(_, data) => match status.base_format {
-			BFT::Indexed1 => raster(&data[level], status, u8::to_le_bytes),
+			BFT::Indexed1 => raster(&data[level], status, 1, u8::to_le_bytes),
 			BFT::Indexed2 => {
+				check_swizzle(&data[level], PFT::Pfp_1, 1)?;
 				let data = a_fast_mode2(&data[level])?;
 				let mut f = Writer::new();
 				f.u32(data.len() as u32);
 				f.slice(&maybe_compress(status.compression, &data));
-				f.finish()?
+				Ok(f.finish()?)
 			}
 			BFT::Indexed3 => bail!(e::Todo {
 				what: "CCPI is not supported for revision 3"
 			}),
 			_ => unreachable!(),
 		},
-		ImageData::Argb16(_, data) => raster(&data[level], status, u16::to_le_bytes),
-		ImageData::Argb32(data) => raster(&data[level], status, u32::to_le_bytes),
-		ImageData::Bc1(data) => raster(&data[level], status, u64::to_le_bytes),
-		ImageData::Bc2(data) => raster(&data[level], status, u128::to_le_bytes),
-		ImageData::Bc3(data) => raster(&data[level], status, u128::to_le_bytes),
-		ImageData::Bc7(data) => raster(&data[level], status, u128::to_le_bytes),
-	})
+		ImageData::Argb16(_, data) => raster(&data[level], status, 1, u16::to_le_bytes),
+		ImageData::Argb32(data) => raster(&data[level], status, 1, u32::to_le_bytes),
+		ImageData::Bc1(data) => raster(&data[level], status, 4, u64::to_le_bytes),
+		ImageData::Bc2(data) => raster(&data[level], status, 4, u128::to_le_bytes),
+		ImageData::Bc3(data) => raster(&data[level], status, 4, u128::to_le_bytes),
+		ImageData::Bc7(data) => raster(&data[level], status, 4, u128::to_le_bytes),
+	}
+}
+
+/// Fails instead of letting [`do_swizzle`] panic on a size it cannot handle.
+fn check_swizzle<T>(data: &Raster<T>, pixel_format: PFT, block: usize) -> Result<(), Error> {
+	ensure!(
+		swizzle_fits(pixel_format, data.width(), data.height()),
+		e::Swizzle {
+			pixel_format,
+			width: data.width() * block,
+			height: data.height() * block,
+		}
+	);
+	Ok(())
+}
+
+/// Whether [`do_swizzle`] can handle a raster of this size.
+pub(super) fn swizzle_fits(pixel_format: PFT, w: usize, h: usize) -> bool {
+	match pixel_format {
+		PFT::Linear => true,
+		PFT::Pfp_1 => w % 16 == 0 && h % 8 == 0,
+		PFT::Pfp_2 => w % 32 == 0 && h % 32 == 0,
+		PFT::Pfp_3 => w.is_power_of_two() && h.is_power_of_two(),
+		PFT::Pfp_4 => h % 8 == 0 && (w * h / 8).is_power_of_two(),
+	}
 }
 
 fn do_swizzle<T: Clone>(raster: &Raster<T>, pixel_format: PFT) -> Vec<T> {
@@ -384,8 +439,9 @@ fn write_ccpi(itp: &Itp) -> Result<Vec<u8>, Error> {
 	f.u16(pal_size as u16);
 	f.u8(cw.ilog2() as u8);
 	f.u8(ch.ilog2() as u8);
-	f.u16(itp.data.width() as u16);
-	f.u16(itp.data.height() as u16);
+	let (width, height) = itp.dimensions();
+	f.u16(width as u16);
+	f.u16(height as u16);
 	f.u16(flags);
 	f.slice(&maybe_compress(itp.status.compression, &g.finish()?));
 	Ok(f.finish()?)
@@ -416,15 +472,53 @@ fn encode_ccpi(pixels: &Raster<u8>) -> (usize, usize, Vec<u8>) {
 }
 
 fn encode_ccpi_chunk(chunk: &[u8]) -> Vec<u8> {
+	let tiles = chunk.array_chunks::<4>().copied().collect::<Vec<_>>();
+	assert!(tiles.len() < 255); // intentionally not <= since 0xFF means RLE
+
+	// Flipped tiles are only worthwhile if all their indices fit in a byte; otherwise fall back to
+	// reusing identical tiles only, which always fits.
+	let (bases, refs) = ccpi_tiles(&tiles, true);
+	let n = bases.len();
+	let (bases, refs) = if refs.iter().all(|&(i, flip)| flip * n + i < 255) {
+		(bases, refs)
+	} else {
+		ccpi_tiles(&tiles, false)
+	};
+	let n = bases.len();
+
 	let mut v = Vec::new();
-	let n = chunk.len() / 4;
-	assert!(n < 255); // intentionally not <= since 0xFF means RLE
 	v.push(n as u8);
-	v.extend(chunk);
-	v.extend(0..n as u8);
+	v.extend(bases.iter().flatten());
+	v.extend(refs.iter().map(|&(i, flip)| (flip * n + i) as u8));
 	v
 }
 
+/// Splits the tiles into unique base tiles, and for each tile, which base it is and how it is
+/// flipped: 0 for none, 1 for x, 2 for y, or 3 for both. This is the order the reader lays out
+/// the flipped tiles in.
+fn ccpi_tiles(tiles: &[[u8; 4]], flips: bool) -> (Vec<[u8; 4]>, Vec<(usize, usize)>) {
+	let x_flip = |[a, b, c, d]: [u8; 4]| [b, a, d, c];
+	let y_flip = |[a, b, c, d]: [u8; 4]| [c, d, a, b];
+	let mut bases = Vec::<[u8; 4]>::new();
+	let mut refs = Vec::with_capacity(tiles.len());
+	for &tile in tiles {
+		let variants = if flips {
+			[tile, x_flip(tile), y_flip(tile), y_flip(x_flip(tile))]
+		} else {
+			[tile; 4]
+		};
+		let found = variants
+			.iter()
+			.enumerate()
+			.find_map(|(flip, v)| Some((bases.iter().position(|b| b == v)?, flip)));
+		refs.push(found.unwrap_or_else(|| {
+			bases.push(tile);
+			(bases.len() - 1, 0)
+		}));
+	}
+	(bases, refs)
+}
+
 fn a_fast_mode2(data: &Raster<u8>) -> Result<Vec<u8>, Error> {
 	fn nibbles(f: &mut Writer, data: impl IntoIterator<Item = u8>) {
 		let mut iter = data.into_iter();
@@ -1,8 +1,13 @@
 #![feature(error_generic_member_access)]
 #![feature(array_chunks)]
 #![feature(never_type)]
+#![cfg_attr(feature = "simd", feature(portable_simd))]
 
+pub mod color;
+mod error;
 pub mod itc;
 pub mod itp;
 pub mod permute;
 pub mod raster;
+
+pub use error::Error;
@@ -105,6 +105,61 @@ pub fn unswizzle<T>(slice: &mut [T], h: usize, w: usize, ch: usize, cw: usize) {
 	}
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("cannot swizzle {len} elements as {h}×{w} in chunks of {ch}×{cw}")]
+pub struct SwizzleError {
+	pub len: usize,
+	pub h: usize,
+	pub w: usize,
+	pub ch: usize,
+	pub cw: usize,
+}
+
+fn check_swizzle(len: usize, h: usize, w: usize, ch: usize, cw: usize) -> Result<(), SwizzleError> {
+	if len == w * h && ch != 0 && cw != 0 && w % cw == 0 && h % ch == 0 {
+		Ok(())
+	} else {
+		Err(SwizzleError { len, h, w, ch, cw })
+	}
+}
+
+/// Like [`swizzle`], but returns an error instead of panicking if the dimensions do not match.
+pub fn try_swizzle<T>(
+	slice: &mut [T],
+	h: usize,
+	w: usize,
+	ch: usize,
+	cw: usize,
+) -> Result<(), SwizzleError> {
+	check_swizzle(slice.len(), h, w, ch, cw)?;
+	swizzle(slice, h, w, ch, cw);
+	Ok(())
+}
+
+/// Like [`unswizzle`], but returns an error instead of panicking if the dimensions do not match.
+pub fn try_unswizzle<T>(
+	slice: &mut [T],
+	h: usize,
+	w: usize,
+	ch: usize,
+	cw: usize,
+) -> Result<(), SwizzleError> {
+	check_swizzle(slice.len(), h, w, ch, cw)?;
+	unswizzle(slice, h, w, ch, cw);
+	Ok(())
+}
+
+#[test]
+fn test_try_swizzle() {
+	let mut data = (0..16).collect::<Vec<_>>();
+	assert!(try_unswizzle(&mut data, 4, 4, 2, 2).is_ok());
+	assert!(try_swizzle(&mut data, 4, 4, 2, 2).is_ok());
+	assert_eq!(data, (0..16).collect::<Vec<_>>());
+	assert!(try_unswizzle(&mut data[..12], 4, 4, 2, 2).is_err());
+	assert!(try_swizzle(&mut data, 4, 4, 3, 2).is_err());
+	assert!(try_swizzle(&mut data, 4, 4, 0, 2).is_err());
+}
+
 #[inline]
 pub fn morton<T>(slice: &mut [T], height: usize, width: usize) {
 	assert_eq!(slice.len(), width * height);
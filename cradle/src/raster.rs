@@ -1,3 +1,5 @@
+use std::collections::{HashMap, HashSet};
+
 #[derive(Clone, PartialEq, Eq)]
 pub struct Raster<T> {
 	width: usize,
@@ -5,6 +7,14 @@ pub struct Raster<T> {
 	data: Vec<T>,
 }
 
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+#[error("cannot make a {width}×{height} raster from {len} elements")]
+pub struct SizeError {
+	pub len: usize,
+	pub width: usize,
+	pub height: usize,
+}
+
 impl<T> std::fmt::Debug for Raster<T> {
 	fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
 		f.debug_struct("Raster")
@@ -42,6 +52,20 @@ impl<T> Raster<T> {
 		}
 	}
 
+	/// Like [`new_with`](Self::new_with), but returns an error instead of panicking if the
+	/// length does not match. Use this for data that comes from a file.
+	pub fn try_new_with(width: usize, height: usize, data: Vec<T>) -> Result<Self, SizeError> {
+		if data.len() == width * height {
+			Ok(Self::new_with(width, height, data))
+		} else {
+			Err(SizeError {
+				len: data.len(),
+				width,
+				height,
+			})
+		}
+	}
+
 	pub fn width(&self) -> usize {
 		self.width
 	}
@@ -54,9 +78,162 @@ impl<T> Raster<T> {
 		&self.data
 	}
 
+	pub fn as_mut_slice(&mut self) -> &mut [T] {
+		&mut self.data
+	}
+
 	pub fn map<U>(&self, f: impl FnMut(&T) -> U) -> Raster<U> {
 		Raster::new_with(self.width, self.height, self.data.iter().map(f).collect())
 	}
+
+	/// Swaps the rows and columns.
+	pub fn transpose(&self) -> Raster<T>
+	where
+		T: Clone,
+	{
+		let mut data = Vec::with_capacity(self.data.len());
+		for x in 0..self.width {
+			for y in 0..self.height {
+				data.push(self[[x, y]].clone());
+			}
+		}
+		Raster::new_with(self.height, self.width, data)
+	}
+
+	/// Rotates the raster 90° clockwise.
+	pub fn rotate90(&self) -> Raster<T>
+	where
+		T: Clone,
+	{
+		let mut data = Vec::with_capacity(self.data.len());
+		for y in 0..self.width {
+			for x in 0..self.height {
+				data.push(self[[y, self.height - 1 - x]].clone());
+			}
+		}
+		Raster::new_with(self.height, self.width, data)
+	}
+
+	pub fn rotate180(&self) -> Raster<T>
+	where
+		T: Clone,
+	{
+		Raster::new_with(
+			self.width,
+			self.height,
+			self.data.iter().rev().cloned().collect(),
+		)
+	}
+
+	/// Rotates the raster 90° counterclockwise.
+	pub fn rotate270(&self) -> Raster<T>
+	where
+		T: Clone,
+	{
+		let mut data = Vec::with_capacity(self.data.len());
+		for y in 0..self.width {
+			for x in 0..self.height {
+				data.push(self[[self.width - 1 - y, x]].clone());
+			}
+		}
+		Raster::new_with(self.height, self.width, data)
+	}
+
+	/// Sets every pixel to `val`.
+	pub fn fill(&mut self, val: T)
+	where
+		T: Clone,
+	{
+		self.data.fill(val);
+	}
+
+	/// Sets the pixels in a rectangle to `val`. The parts outside the raster are ignored.
+	pub fn fill_rect(&mut self, x: usize, y: usize, w: usize, h: usize, val: T)
+	where
+		T: Clone,
+	{
+		let x1 = x.saturating_add(w).min(self.width);
+		let y1 = y.saturating_add(h).min(self.height);
+		for row in y.min(y1)..y1 {
+			self.data[row * self.width..][x.min(x1)..x1].fill(val.clone());
+		}
+	}
+
+	/// Resamples the raster to the given size, taking the nearest pixel.
+	pub fn resize_nearest(&self, width: usize, height: usize) -> Raster<T>
+	where
+		T: Clone,
+	{
+		let mut data = Vec::with_capacity(width * height);
+		for y in 0..height {
+			// Sample at pixel centers, so that each source pixel covers an equal share
+			let sy = (2 * y + 1) * self.height / (2 * height);
+			for x in 0..width {
+				let sx = (2 * x + 1) * self.width / (2 * width);
+				data.push(self[[sx, sy]].clone());
+			}
+		}
+		Raster::new_with(width, height, data)
+	}
+
+	/// Surrounds the raster with the given number of `fill` pixels on each side.
+	pub fn pad(&self, left: usize, top: usize, right: usize, bottom: usize, fill: T) -> Raster<T>
+	where
+		T: Clone,
+	{
+		let width = left + self.width + right;
+		let height = top + self.height + bottom;
+		let mut data = Vec::with_capacity(width * height);
+		data.resize(width * top, fill.clone());
+		for y in 0..self.height {
+			data.resize(data.len() + left, fill.clone());
+			data.extend_from_slice(&self.data[y * self.width..][..self.width]);
+			data.resize(data.len() + right, fill.clone());
+		}
+		data.resize(width * height, fill);
+		Raster::new_with(width, height, data)
+	}
+}
+
+impl Raster<u32> {
+	/// Splits an ARGB raster into its blue, green, red, and alpha planes, in that order.
+	///
+	/// This is the order of the bytes when stored as little-endian.
+	pub fn split_channels(&self) -> [Raster<u8>; 4] {
+		std::array::from_fn(|i| self.map(|a| a.to_le_bytes()[i]))
+	}
+
+	/// Combines blue, green, red, and alpha planes into an ARGB raster.
+	///
+	/// Panics if the planes are not all the same size.
+	pub fn from_channels(channels: [Raster<u8>; 4]) -> Raster<u32> {
+		let [b, g, r, a] = &channels;
+		let (width, height) = (b.width, b.height);
+		assert!(
+			channels
+				.iter()
+				.all(|c| c.width == width && c.height == height),
+			"channels must have the same size"
+		);
+		let data = (0..width * height)
+			.map(|i| u32::from_le_bytes([b.data[i], g.data[i], r.data[i], a.data[i]]))
+			.collect();
+		Raster::new_with(width, height, data)
+	}
+
+	/// Counts how many times each color occurs.
+	pub fn color_histogram(&self) -> HashMap<u32, usize> {
+		let mut hist = HashMap::new();
+		for &c in &self.data {
+			*hist.entry(c).or_default() += 1;
+		}
+		hist
+	}
+
+	/// Counts how many distinct colors the raster contains.
+	pub fn unique_colors(&self) -> usize {
+		self.data.iter().collect::<HashSet<_>>().len()
+	}
 }
 
 impl<T> std::ops::Index<[usize; 2]> for Raster<T> {
@@ -72,3 +249,89 @@ impl<T> std::ops::IndexMut<[usize; 2]> for Raster<T> {
 		&mut self.data[y * self.width + x]
 	}
 }
+
+#[test]
+fn test_pad() {
+	let raster = Raster::new_with(2, 2, vec![1, 2, 3, 4]);
+	let padded = raster.pad(1, 1, 1, 1, 0);
+	assert_eq!(padded.width(), 4);
+	assert_eq!(padded.height(), 4);
+	#[rustfmt::skip]
+	assert_eq!(padded.as_slice(), [
+		0, 0, 0, 0,
+		0, 1, 2, 0,
+		0, 3, 4, 0,
+		0, 0, 0, 0,
+	]);
+}
+
+#[test]
+fn test_rotate() {
+	let raster = Raster::new_with(3, 2, vec![1, 2, 3, 4, 5, 6]);
+	assert_eq!(raster.rotate90().as_slice(), [4, 1, 5, 2, 6, 3]);
+	assert_eq!(raster.rotate270().as_slice(), [3, 6, 2, 5, 1, 4]);
+	assert_eq!(raster.transpose().as_slice(), [1, 4, 2, 5, 3, 6]);
+	assert_eq!(raster.rotate90().rotate90(), raster.rotate180());
+	assert_eq!(raster.rotate90().rotate90().rotate90(), raster.rotate270());
+	assert_eq!(raster.rotate90().rotate90().rotate90().rotate90(), raster);
+	assert_eq!(raster.transpose().transpose(), raster);
+}
+
+#[test]
+fn test_channels() {
+	let raster = Raster::new_with(3, 1, vec![0x11223344, 0xAABBCCDD, 0xFF000080]);
+	let [b, g, r, a] = raster.split_channels();
+	assert_eq!(b.as_slice(), [0x44, 0xDD, 0x80]);
+	assert_eq!(g.as_slice(), [0x33, 0xCC, 0x00]);
+	assert_eq!(r.as_slice(), [0x22, 0xBB, 0x00]);
+	assert_eq!(a.as_slice(), [0x11, 0xAA, 0xFF]);
+	assert_eq!(Raster::from_channels([b, g, r, a]), raster);
+}
+
+#[test]
+fn test_colors() {
+	let raster = Raster::new_with(3, 2, vec![1, 2, 1, 3, 1, 2]);
+	assert_eq!(raster.unique_colors(), 3);
+	let hist = raster.color_histogram();
+	assert_eq!(hist.len(), 3);
+	assert_eq!((hist[&1], hist[&2], hist[&3]), (3, 2, 1));
+	assert_eq!(Raster::<u32>::new(0, 0).unique_colors(), 0);
+}
+
+#[test]
+fn test_fill_rect() {
+	let mut raster = Raster::new(4, 3);
+	raster.fill_rect(1, 1, 2, 1, 7);
+	assert_eq!(raster.as_slice(), [0, 0, 0, 0, 0, 7, 7, 0, 0, 0, 0, 0]);
+	// Clipped to the raster
+	raster.fill_rect(3, 2, 5, 5, 8);
+	assert_eq!(raster.as_slice(), [0, 0, 0, 0, 0, 7, 7, 0, 0, 0, 0, 8]);
+	raster.fill_rect(9, 0, 1, 1, 9);
+	assert_eq!(raster.as_slice(), [0, 0, 0, 0, 0, 7, 7, 0, 0, 0, 0, 8]);
+	raster.fill(1);
+	assert_eq!(raster.as_slice(), [1; 12]);
+}
+
+#[test]
+fn test_resize_nearest() {
+	let raster = Raster::new_with(2, 1, vec![1, 2]);
+	let wide = raster.resize_nearest(4, 2);
+	assert_eq!(wide.as_slice(), [1, 1, 2, 2, 1, 1, 2, 2]);
+	assert_eq!(wide.resize_nearest(2, 1), raster);
+	assert_eq!(raster.resize_nearest(3, 1).as_slice(), [1, 1, 2]);
+}
+
+#[test]
+fn test_try_new_with() {
+	let raster = Raster::try_new_with(2, 1, vec![1, 2]).unwrap();
+	assert_eq!(raster.as_slice(), [1, 2]);
+	let err = Raster::try_new_with(2, 2, vec![1, 2, 3]).unwrap_err();
+	assert_eq!(
+		err,
+		SizeError {
+			len: 3,
+			width: 2,
+			height: 2
+		}
+	);
+}